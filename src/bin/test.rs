@@ -11,13 +11,13 @@ extern crate maplit;
 extern crate correlation;
 extern crate uuid;
 
-use correlation::correlator::Correlator;
+use correlation::correlator::{Correlator, CorrelatorBuilder};
 use correlation::config::ContextConfigBuilder;
+use correlation::test_utils::ManualClock;
 use correlation::ConditionsBuilder;
 use correlation::MessageBuilder;
-use correlation::ContextMap;
 use uuid::Uuid;
-use std::thread;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[allow(dead_code)]
@@ -40,11 +40,16 @@ fn main() {
         ContextConfigBuilder::new(Uuid::new_v4(), condition.clone()).patterns(patterns.clone()).actions(Vec::new()).build(),
     ];
     let mut external_handler_data = ();
-    let mut correlator: Correlator<()> = Correlator::new(ContextMap::from_configs(contexts));
+    let clock = ManualClock::new();
+    let mut correlator: Correlator<()> = CorrelatorBuilder::from_configs(contexts)
+                                             .clock(Arc::new(clock.clone()))
+                                             .build()
+                                             .expect("Failed to build the correlator");
     let _ = correlator.push_message(MessageBuilder::new(&uuid1, "message").build());
-    thread::sleep(Duration::from_millis(20));
+    clock.advance(Duration::from_millis(20));
     let _ = correlator.push_message(MessageBuilder::new(&uuid2, "message").build());
-    thread::sleep(Duration::from_millis(80));
+    clock.advance(Duration::from_millis(80));
     let _ = correlator.push_message(MessageBuilder::new(&uuid3, "message").build());
+    correlator.sync(&mut external_handler_data);
     let _ = correlator.stop(&mut external_handler_data);
 }
@@ -0,0 +1,54 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The source of time of the correlator: the timers are measured by now() and
+// the active windows of the contexts use timestamp() if the time of the
+// messages is not read from them.
+pub trait Clock: Send + Sync {
+    // Monotonic time since an arbitrary origin.
+    fn now(&self) -> Duration;
+    // Seconds since the Unix epoch.
+    fn timestamp(&self) -> u64;
+    // If false, the time passes only when the clock is advanced explicitly, so
+    // the dispatcher doesn't wait for the deadlines in real time.
+    fn is_real_time(&self) -> bool {
+        true
+    }
+}
+
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { origin: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn timestamp(&self) -> u64 {
+        system_timestamp()
+    }
+}
+
+pub fn system_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
@@ -115,9 +115,9 @@ mod test {
         let msg_opening = Arc::new(MessageBuilder::new(&msg_id1, "message").build());
         let msg_closing = Arc::new(MessageBuilder::new(&msg_id2, "message").build());
         assert_false!(state.is_open());
-        context.on_message(msg_opening, &mut state, &mut responder);
+        context.on_message(msg_opening, &mut state, Duration::from_secs(0), &mut responder);
         assert_true!(state.is_open());
-        context.on_message(msg_closing, &mut state, &mut responder);
+        context.on_message(msg_closing, &mut state, Duration::from_secs(0), &mut responder);
         assert_false!(state.is_open());
     }
 
@@ -207,8 +207,8 @@ mod test {
         let p2_msg = MessageBuilder::new(&p2_uuid, "message").name(Some(p2)).build();
         let context = BaseContextBuilder::new(Uuid::new_v4(), conditions).patterns(patterns).build();
         assert_false!(state.is_open());
-        context.on_message(Arc::new(p1_msg), &mut state, &mut responder);
-        context.on_message(Arc::new(p2_msg), &mut state, &mut responder);
+        context.on_message(Arc::new(p1_msg), &mut state, Duration::from_secs(0), &mut responder);
+        context.on_message(Arc::new(p2_msg), &mut state, Duration::from_secs(0), &mut responder);
         assert_false!(state.is_open());
     }
 
@@ -224,7 +224,7 @@ mod test {
                              .build();
         let context = BaseContextBuilder::new(Uuid::new_v4(), conditions).patterns(Vec::new()).build();
         let mut state = State::new();
-        context.on_message(Arc::new(msg), &mut state, &mut responder);
+        context.on_message(Arc::new(msg), &mut state, Duration::from_secs(0), &mut responder);
     }
 
     #[test]
@@ -239,7 +239,7 @@ mod test {
                              .build();
         let context = BaseContextBuilder::new(Uuid::new_v4(), conditions).build();
         let mut state = State::new();
-        context.on_message(Arc::new(msg), &mut state, &mut responder);
+        context.on_message(Arc::new(msg), &mut state, Duration::from_secs(0), &mut responder);
     }
 }
 
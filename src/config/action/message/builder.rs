@@ -19,6 +19,8 @@ pub struct MessageActionBuilder {
     values: BTreeMap<String, String>,
     when: ExecCondition,
     inject_mode: InjectMode,
+    inherit: Vec<String>,
+    output: Option<String>,
 }
 
 impl MessageActionBuilder {
@@ -30,6 +32,8 @@ impl MessageActionBuilder {
             values: BTreeMap::default(),
             when: ExecCondition::default(),
             inject_mode: InjectMode::default(),
+            inherit: Vec::new(),
+            output: None,
         }
     }
 
@@ -58,6 +62,16 @@ impl MessageActionBuilder {
         self
     }
 
+    pub fn inherit(mut self, keys: Vec<String>) -> MessageActionBuilder {
+        self.inherit = keys;
+        self
+    }
+
+    pub fn output<S: Into<String>>(mut self, output: Option<S>) -> MessageActionBuilder {
+        self.output = output.map(|output| output.into());
+        self
+    }
+
     pub fn build(self) -> MessageAction {
         MessageAction {
             uuid: self.uuid,
@@ -66,6 +80,8 @@ impl MessageActionBuilder {
             values: self.values,
             when: self.when,
             inject_mode: self.inject_mode,
+            inherit: self.inherit,
+            output: self.output,
         }
     }
 }
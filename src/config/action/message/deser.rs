@@ -6,11 +6,12 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use super::MessageAction;
+use super::{Alert, MessageAction};
 use super::InjectMode;
 use config::action::ExecCondition;
 
 use serde::de::{Deserialize, Deserializer, Error, MapVisitor, Visitor};
+use serde::ser::{self, Serialize, Serializer};
 use std::collections::BTreeMap;
 
 impl Deserialize for MessageAction {
@@ -28,6 +29,8 @@ enum Field {
     Values,
     When,
     InjectMode,
+    Inherit,
+    Output,
 }
 
 impl Deserialize for Field {
@@ -49,6 +52,8 @@ impl Deserialize for Field {
                     "message" => Ok(Field::Message),
                     "when" => Ok(Field::When),
                     "inject_mode" => Ok(Field::InjectMode),
+                    "inherit" => Ok(Field::Inherit),
+                    "output" => Ok(Field::Output),
                     _ => Err(Error::custom(format!("Unexpected field: {}", value))),
                 }
             }
@@ -72,6 +77,8 @@ impl Visitor for MessageActionVisitor {
         let mut values: Option<BTreeMap<String, String>> = None;
         let mut when: ExecCondition = ExecCondition::new();
         let mut inject_mode = Default::default();
+        let mut inherit = Vec::new();
+        let mut output: Option<String> = None;
 
         while let Some(field) = try!(visitor.visit_key()) {
             match field {
@@ -81,6 +88,8 @@ impl Visitor for MessageActionVisitor {
                 Field::Values => values = Some(try!(visitor.visit_value())),
                 Field::When => when = try!(visitor.visit_value()),
                 Field::InjectMode => inject_mode = try!(visitor.visit_value()),
+                Field::Inherit => inherit = try!(visitor.visit_value()),
+                Field::Output => output = Some(try!(visitor.visit_value())),
             }
         }
 
@@ -106,6 +115,8 @@ impl Visitor for MessageActionVisitor {
             values: values.unwrap_or_default(),
             when: when,
             inject_mode: inject_mode,
+            inherit: inherit,
+            output: output,
         })
     }
 }
@@ -135,11 +146,135 @@ impl Deserialize for InjectMode {
     }
 }
 
+impl Serialize for InjectMode {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        let value = match *self {
+            InjectMode::Log => "log",
+            InjectMode::Loopback => "loopback",
+            InjectMode::Forward => "forward",
+        };
+        serializer.serialize_str(value)
+    }
+}
+
+impl Serialize for Alert {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("Alert",
+                                    AlertMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct AlertMapVisitor<'a> {
+    value: &'a Alert,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for AlertMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("message", &self.value.message)))),
+            2 => {
+                Ok(Some(try!(serializer.serialize_struct_elt("inject_mode",
+                                                             &self.value.inject_mode))))
+            }
+            3 if self.value.output.is_some() => {
+                Ok(Some(try!(serializer.serialize_struct_elt("output", &self.value.output))))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Deserialize for Alert {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Alert, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Alert", &[], AlertVisitor)
+    }
+}
+
+enum AlertField {
+    Message,
+    InjectMode,
+    Output,
+}
+
+impl Deserialize for AlertField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<AlertField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = AlertField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<AlertField, E>
+                where E: Error
+            {
+                match value {
+                    "message" => Ok(AlertField::Message),
+                    "inject_mode" => Ok(AlertField::InjectMode),
+                    "output" => Ok(AlertField::Output),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct AlertVisitor;
+
+impl Visitor for AlertVisitor {
+    type Value = Alert;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Alert, V::Error>
+        where V: MapVisitor
+    {
+        let mut message = None;
+        let mut inject_mode = InjectMode::default();
+        let mut output = None;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                AlertField::Message => message = Some(try!(visitor.visit_value())),
+                AlertField::InjectMode => inject_mode = try!(visitor.visit_value()),
+                AlertField::Output => output = try!(visitor.visit_value()),
+            }
+        }
+
+        let message = match message {
+            Some(message) => message,
+            None => return visitor.missing_field("message"),
+        };
+
+        try!(visitor.end());
+
+        Ok(Alert {
+            message: message,
+            inject_mode: inject_mode,
+            output: output,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use config::action::message::{MessageActionBuilder, MessageAction, InjectMode};
+    use config::action::message::{Alert, MessageActionBuilder, MessageAction, InjectMode};
+    use message::MessageBuilder;
 
-    use serde_json::from_str;
+    use serde_json::{from_str, to_string};
 
     fn assert_message_action_eq(expected: &MessageAction, actual: &MessageAction) {
         assert_eq!(expected.uuid(), actual.uuid());
@@ -245,6 +380,21 @@ mod test {
         assert_message_action_eq(&expected_message, &message);
     }
 
+    #[test]
+    fn test_given_message_when_it_contains_inherited_keys_then_it_can_be_deserialized() {
+        let text = r#"
+        {
+          "uuid": "UUID",
+          "message": "message",
+          "inherit": ["user_name", "session_id"]
+        }
+        "#;
+
+        let result = from_str::<MessageAction>(text);
+        let message = result.expect("Failed to deserialize a valid MessageAction object");
+        assert_eq!(&["user_name".to_owned(), "session_id".to_owned()], message.inherit());
+    }
+
     #[test]
     fn test_given_message_is_deserialized_when_it_contains_an_unexpected_field_then_an_error_is_returned() {
         let text = r#"{ "unexpected": "UUID" }"#;
@@ -258,4 +408,35 @@ mod test {
         let result = from_str::<MessageAction>(text);
         let _ = result.err().unwrap();
     }
+
+    #[test]
+    fn test_given_message_when_it_contains_an_output_then_it_can_be_deserialized() {
+        let text = r#"
+        {
+          "uuid": "UUID",
+          "message": "message",
+          "output": "pager"
+        }
+        "#;
+
+        let result = from_str::<MessageAction>(text);
+        let message = result.expect("Failed to deserialize a valid MessageAction object");
+        assert_eq!(Some(&"pager".to_owned()), message.output());
+    }
+
+    #[test]
+    fn test_given_alert_with_output_when_it_is_serialized_then_it_can_be_deserialized() {
+        let alert = Alert {
+            message: MessageBuilder::new("UUID", "message").build(),
+            inject_mode: InjectMode::Forward,
+            output: Some("siem".to_owned()),
+        };
+        let text = to_string(&alert).expect("Failed to serialize an Alert");
+        let result = from_str::<Alert>(&text).expect("Failed to deserialize a serialized Alert");
+        assert_eq!(Some("siem".to_owned()), result.output);
+        let alert = Alert { output: None, ..alert };
+        let text = to_string(&alert).expect("Failed to serialize an Alert");
+        assert_false!(text.contains("output"));
+        assert_eq!(None, from_str::<Alert>(&text).unwrap().output);
+    }
 }
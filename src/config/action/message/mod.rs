@@ -10,7 +10,7 @@ use action::Action;
 use context::base::BaseContext;
 use dispatcher::Response;
 use dispatcher::response::ResponseSender;
-use message::{Message, MessageBuilder};
+use message::{Message, MessageBuilder, LOOPBACK_HOPS};
 
 use std::collections::BTreeMap;
 use state::State;
@@ -26,8 +26,10 @@ mod test;
 pub const CONTEXT_UUID: &'static str = "context_uuid";
 pub const CONTEXT_NAME: &'static str = "context_name";
 pub const CONTEXT_LEN: &'static str = "context_len";
+pub const CONTEXT_CLOSE_REASON: &'static str = "context_close_reason";
 pub const MESSAGES: &'static str = "messages";
 
+#[derive(Clone, Debug)]
 pub struct MessageAction {
     uuid: String,
     name: Option<String>,
@@ -35,6 +37,8 @@ pub struct MessageAction {
     values: BTreeMap<String, String>,
     when: ExecCondition,
     inject_mode: InjectMode,
+    inherit: Vec<String>,
+    output: Option<String>,
 }
 
 impl MessageAction {
@@ -53,15 +57,34 @@ impl MessageAction {
     pub fn inject_mode(&self) -> &InjectMode {
         &self.inject_mode
     }
+    pub fn inherit(&self) -> &[String] {
+        &self.inherit
+    }
+    pub fn output(&self) -> Option<&String> {
+        self.output.as_ref()
+    }
 
-    fn execute(&self, _state: &State, _context: &BaseContext, responder: &mut ResponseSender) {
+    fn execute(&self, state: &State, _context: &BaseContext, responder: &mut ResponseSender) {
+        let mut values = self.values.clone();
+        for key in &self.inherit {
+            if let Some(value) = state.parent_values().get(key) {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+        if self.inject_mode == InjectMode::Loopback {
+            values.insert(LOOPBACK_HOPS.to_owned(), (state.hops() + 1).to_string());
+        }
+        if let Some(reason) = state.close_reason() {
+            values.insert(CONTEXT_CLOSE_REASON.to_owned(), reason.as_str().to_owned());
+        }
         let message = MessageBuilder::new(&self.uuid, self.message.clone())
                                     .name(self.name.clone())
-                                    .values(self.values.clone())
+                                    .values(values)
                                     .build();
         let response = Alert {
             message: message,
             inject_mode: self.inject_mode.clone(),
+            output: self.output.clone(),
         };
         responder.send_response(Response::Alert(response));
     }
@@ -90,6 +113,9 @@ impl Default for InjectMode {
 pub struct Alert {
     pub message: Message,
     pub inject_mode: InjectMode,
+    // The name of the output the alert is routed to, None means the default
+    // output.
+    pub output: Option<String>,
 }
 
 impl Action for MessageAction {
@@ -15,6 +15,7 @@ use self::message::MessageAction;
 pub mod message;
 mod deser;
 
+#[derive(Clone, Debug)]
 pub enum ActionType {
     Message(MessageAction),
 }
@@ -7,11 +7,12 @@
 // modified, or distributed except according to those terms.
 
 use config::ContextConfig;
+use context::map::KeyExpression;
 use serde::de::{Deserialize, Deserializer, MapVisitor, Error, Visitor};
 
 use uuid::Uuid;
 
-const FIELDS: &'static [&'static str] = &["name", "uuid", "conditions", "actions"];
+const FIELDS: &'static [&'static str] = &["name", "uuid", "conditions", "actions", "active", "parent", "key_limit"];
 
 impl Deserialize for ContextConfig {
     fn deserialize<D>(deserializer: &mut D) -> Result<ContextConfig, D::Error>
@@ -28,6 +29,9 @@ enum Field {
     ContextId,
     Actions,
     Patterns,
+    Active,
+    Parent,
+    KeyLimit,
 }
 
 impl Deserialize for Field {
@@ -49,6 +53,9 @@ impl Deserialize for Field {
                     "context_id" => Ok(Field::ContextId),
                     "actions" => Ok(Field::Actions),
                     "patterns" => Ok(Field::Patterns),
+                    "active" => Ok(Field::Active),
+                    "parent" => Ok(Field::Parent),
+                    "key_limit" => Ok(Field::KeyLimit),
                     _ => Err(Error::custom(format!("Unexpected field: {}", value))),
                 }
             }
@@ -65,20 +72,24 @@ impl ContextVisitor {
         where V: MapVisitor
     {
         match uuid {
-            Some(value) => {
-                match Uuid::parse_str(&value) {
-                    Ok(uuid) => Ok(uuid),
-                    Err(err) => {
-                        Err(Error::custom(format!("Failed to parse field 'uuid': uuid={} \
-                                                    error={}",
-                                                   value,
-                                                   err)))
-                    }
-                }
-            }
+            Some(value) => ContextVisitor::parse_uuid_field::<V>("uuid", value),
             None => Err(Error::missing_field("uuid")),
         }
     }
+
+    fn parse_uuid_field<V>(field: &str, value: String) -> Result<Uuid, V::Error>
+        where V: MapVisitor
+    {
+        match Uuid::parse_str(&value) {
+            Ok(uuid) => Ok(uuid),
+            Err(err) => {
+                Err(Error::custom(format!("Failed to parse field '{}': uuid={} error={}",
+                                          field,
+                                          value,
+                                          err)))
+            }
+        }
+    }
 }
 
 impl Visitor for ContextVisitor {
@@ -90,9 +101,12 @@ impl Visitor for ContextVisitor {
         let mut name = None;
         let mut uuid: Option<String> = None;
         let mut conditions = None;
-        let mut context_id: Option<Vec<String>> = None;
+        let mut context_id: Option<Vec<KeyExpression>> = None;
         let mut actions = None;
         let mut patterns = None;
+        let mut active = None;
+        let mut parent: Option<String> = None;
+        let mut key_limit = None;
 
         while let Some(field) = try!(visitor.visit_key()) {
             match field {
@@ -102,21 +116,35 @@ impl Visitor for ContextVisitor {
                 Field::ContextId => context_id = Some(try!(visitor.visit_value())),
                 Field::Actions => actions = Some(try!(visitor.visit_value())),
                 Field::Patterns => patterns = Some(try!(visitor.visit_value())),
+                Field::Active => active = Some(try!(visitor.visit_value())),
+                Field::Parent => parent = Some(try!(visitor.visit_value())),
+                Field::KeyLimit => key_limit = Some(try!(visitor.visit_value())),
             }
         }
 
         let uuid = try!(ContextVisitor::parse_uuid::<V>(uuid));
+        let conditions = match conditions {
+            Some(conditions) => conditions,
+            None => return Err(Error::missing_field("conditions")),
+        };
         let actions = actions.unwrap_or_default();
+        let parent = match parent {
+            Some(parent) => Some(try!(ContextVisitor::parse_uuid_field::<V>("parent", parent))),
+            None => None,
+        };
 
         try!(visitor.end());
 
         Ok(ContextConfig {
             name: name,
             uuid: uuid,
-            conditions: conditions.unwrap(),
+            conditions: conditions,
             context_id: context_id,
             actions: actions,
-            patterns: patterns.unwrap_or_default()
+            patterns: patterns.unwrap_or_default(),
+            active: active,
+            parent: parent,
+            key_limit: key_limit
         })
     }
 }
@@ -127,6 +155,7 @@ mod test {
     use config::action::message::MessageActionBuilder;
     use conditions::ConditionsBuilder;
     use config::ContextConfig;
+    use context::map::KeyExpression;
     use serde_json::from_str;
     use uuid::Uuid;
     use std::time::Duration;
@@ -240,6 +269,38 @@ mod test {
                                invalid)");
     }
 
+    #[test]
+    fn test_given_config_context_when_it_contains_a_parent_then_can_be_deserialized() {
+        let text = r#"
+        {
+            "uuid": "86ca9f93-84fb-4813-b037-6526f7a585a3",
+            "parent": "f13dafee-cd14-4dda-995c-6ed476a21de3",
+            "conditions": {
+                "timeout": 100
+            }
+        }
+        "#;
+        let expected_parent = Uuid::parse_str("f13dafee-cd14-4dda-995c-6ed476a21de3").ok();
+        let result = from_str::<ContextConfig>(text);
+        let context = result.expect("Failed to deserialize a valid ContextConfig");
+        assert_eq!(expected_parent, context.parent);
+    }
+
+    #[test]
+    fn test_given_config_context_when_its_parent_is_not_an_uuid_then_we_report_an_error() {
+        let text = r#"
+        {
+            "uuid": "86ca9f93-84fb-4813-b037-6526f7a585a3",
+            "parent": "SESSION",
+            "conditions": {
+                "timeout": 100
+            }
+        }
+        "#;
+        let result = from_str::<ContextConfig>(text);
+        let _ = result.err().expect("Successfully deserialized a ContextConfig with invalid parent");
+    }
+
     #[test]
     fn test_given_config_context_when_it_contains_context_id_then_can_be_deserialized() {
         let text = r#"
@@ -251,10 +312,21 @@ mod test {
             }
         }
         "#;
-        let expected_context_id = vec!["HOST".to_owned(), "PROGRAM".to_owned()];
+        let expected_context_id = vec![KeyExpression::from("HOST"), KeyExpression::from("PROGRAM")];
         let result = from_str::<ContextConfig>(text);
         let context = result.expect("Failed to deserialize a valid ContextConfig");
         assert_eq!(&expected_context_id,
                    context.context_id.as_ref().unwrap());
     }
+
+    #[test]
+    fn test_given_config_context_when_its_conditions_are_missing_then_we_report_an_error() {
+        let text = r#"
+        {
+            "uuid": "86ca9f93-84fb-4813-b037-6526f7a585a3"
+        }
+        "#;
+        let result = from_str::<ContextConfig>(text);
+        let _ = result.err().expect("Successfully deserialized a ContextConfig without conditions");
+    }
 }
@@ -10,26 +10,35 @@ use uuid::Uuid;
 
 use config::action::ActionType;
 use conditions::Conditions;
+use context::map::{KeyExpression, KeyLimit};
+use schedule::Schedule;
 
 mod deser;
 pub mod action;
 
+#[derive(Clone, Debug)]
 pub struct ContextConfig {
     pub name: Option<String>,
     pub uuid: Uuid,
     pub conditions: Conditions,
-    pub context_id: Option<Vec<String>>,
+    pub context_id: Option<Vec<KeyExpression>>,
     pub actions: Vec<ActionType>,
-    pub patterns: Vec<String>
+    pub patterns: Vec<String>,
+    pub active: Option<Schedule>,
+    pub parent: Option<Uuid>,
+    pub key_limit: Option<KeyLimit>
 }
 
 pub struct ContextConfigBuilder {
     name: Option<String>,
     uuid: Uuid,
     conditions: Conditions,
-    context_id: Option<Vec<String>>,
+    context_id: Option<Vec<KeyExpression>>,
     actions: Vec<ActionType>,
-    patterns: Vec<String>
+    patterns: Vec<String>,
+    active: Option<Schedule>,
+    parent: Option<Uuid>,
+    key_limit: Option<KeyLimit>
 }
 
 impl ContextConfigBuilder {
@@ -40,11 +49,14 @@ impl ContextConfigBuilder {
             conditions: conditions,
             context_id: None,
             actions: Vec::new(),
-            patterns: Vec::new()
+            patterns: Vec::new(),
+            active: None,
+            parent: None,
+            key_limit: None
         }
     }
 
-    pub fn context_id(mut self, context_id: Option<Vec<String>>) -> ContextConfigBuilder {
+    pub fn context_id(mut self, context_id: Option<Vec<KeyExpression>>) -> ContextConfigBuilder {
         self.context_id = context_id;
         self
     }
@@ -64,6 +76,21 @@ impl ContextConfigBuilder {
         self
     }
 
+    pub fn active(mut self, active: Option<Schedule>) -> ContextConfigBuilder {
+        self.active = active;
+        self
+    }
+
+    pub fn parent(mut self, parent: Option<Uuid>) -> ContextConfigBuilder {
+        self.parent = parent;
+        self
+    }
+
+    pub fn key_limit(mut self, key_limit: Option<KeyLimit>) -> ContextConfigBuilder {
+        self.key_limit = key_limit;
+        self
+    }
+
     pub fn build(self) -> ContextConfig {
         ContextConfig {
             name: self.name,
@@ -71,7 +98,10 @@ impl ContextConfigBuilder {
             conditions: self.conditions,
             context_id: self.context_id,
             actions: self.actions,
-            patterns: self.patterns
+            patterns: self.patterns,
+            active: self.active,
+            parent: self.parent,
+            key_limit: self.key_limit
         }
     }
 }
@@ -6,17 +6,26 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+use std::cmp;
+use std::mem;
 use std::sync::Arc;
+use std::time::Duration;
 
 use uuid::Uuid;
 
+use clock::{self, Clock};
 use config::action::ActionType;
 use conditions::Conditions;
+use schedule::Schedule;
 use state::State;
 use dispatcher::response::ResponseSender;
 use action::Action;
+use context::child::{ChildContext, ChildResponder};
+use context::map::map::ContextKey;
+use control::{CloseMode, CloseReason, Selector};
+use introspection::{ContextInfo, StateInfo};
 use message::Message;
-use timer::TimerEvent;
+use snapshot::StateSnapshot;
 
 pub struct BaseContext {
     name: Option<String>,
@@ -24,6 +33,9 @@ pub struct BaseContext {
     conditions: Conditions,
     actions: Vec<ActionType>,
     pub patterns: Vec<String>,
+    active: Option<Schedule>,
+    children: Vec<ChildContext>,
+    clock: Option<Arc<Clock>>,
 }
 
 impl BaseContext {
@@ -39,26 +51,122 @@ impl BaseContext {
         &self.actions
     }
 
+    pub fn children(&self) -> &[ChildContext] {
+        &self.children
+    }
+
+    // The clock is used by the active windows if the time is not read from
+    // the messages. Without a clock the system time is used.
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        for child in &mut self.children {
+            child.base_mut().set_clock(clock.clone());
+        }
+        self.clock = Some(clock);
+    }
+
+    fn wall_time(&self) -> u64 {
+        self.clock.as_ref().map_or_else(clock::system_timestamp, |clock| clock.timestamp())
+    }
+
+    pub fn is_subscribed(&self, message: &Message) -> bool {
+        self.patterns.is_empty() || message.ids().any(|id| self.patterns.contains(id))
+    }
+
+    pub fn is_interested(&self, message: &Message) -> bool {
+        self.is_subscribed(message) ||
+        self.children.iter().any(|child| child.base().is_interested(message))
+    }
+
+    // The patterns of this context and all of its descendants. An empty
+    // vector means that the context is interested in every message.
+    pub fn subscriptions(&self) -> Vec<String> {
+        let mut subscriptions = self.patterns.clone();
+        if subscriptions.is_empty() {
+            return subscriptions;
+        }
+        for child in &self.children {
+            let child_subscriptions = child.base().subscriptions();
+            if child_subscriptions.is_empty() {
+                return child_subscriptions;
+            }
+            subscriptions.extend(child_subscriptions);
+        }
+        subscriptions
+    }
+
     pub fn is_opening(&self, message: &Message) -> bool {
-        if self.conditions.first_opens {
+        let is_first_matching = if self.conditions.first_opens {
             self.patterns.first().iter().any(|first| message.ids().any(|id| &id == first))
         } else {
             true
-        }
+        };
+        is_first_matching && self.is_active(message)
+    }
+
+    fn is_active(&self, message: &Message) -> bool {
+        self.active.as_ref().map_or(true, |schedule| schedule.is_active(message, self.wall_time()))
     }
 
-    pub fn is_closing(&self, state: &State) -> bool {
+    pub fn is_closing(&self, state: &State, now: Duration) -> bool {
         trace!("Conditions: shoud we close this context?");
-        state.is_open() && self.is_closing_condition_met(state)
+        state.is_open() && self.is_closing_condition_met(state, now)
     }
 
-    fn is_closing_condition_met(&self, state: &State) -> bool {
+    fn is_closing_condition_met(&self, state: &State, now: Duration) -> bool {
         self.is_max_size_reached(state) || self.is_closing_message(state) ||
-        self.is_any_timer_expired(state)
+        self.is_any_timer_expired(state, now) || self.is_active_window_ended(state, now)
+    }
+
+    // The point of the timeline when the state (or one of its child states)
+    // should be closed by a timer.
+    pub fn deadline(&self, state: &State, now: Duration) -> Option<Duration> {
+        if !state.is_open() {
+            return None;
+        }
+        let mut deadline = now + remaining(self.conditions.timeout, state.elapsed_time(now));
+        if let Some(renew_timeout) = self.conditions.renew_timeout {
+            let renew_deadline = now + remaining(renew_timeout, state.elapsed_time_since_last_message(now));
+            deadline = cmp::min(deadline, renew_deadline);
+        }
+        if let Some(window_end) = self.window_end(state, now) {
+            deadline = cmp::min(deadline, window_end);
+        }
+        for (child, states) in self.children.iter().zip(state.children()) {
+            if let Some(child_deadline) = child.deadline(states, now) {
+                deadline = cmp::min(deadline, child_deadline);
+            }
+        }
+        Some(deadline)
+    }
+
+    // The window can end between the messages, so the time since the last
+    // one is taken into account.
+    fn is_active_window_ended(&self, state: &State, now: Duration) -> bool {
+        self.active.as_ref().map_or(false, |schedule| {
+            schedule.close_on_end &&
+            state.messages().last().map_or(false, |last_message| {
+                let elapsed = state.elapsed_time_since_last_message(now);
+                !schedule.is_active_at(schedule.time_after(last_message, self.wall_time(), elapsed))
+            })
+        })
+    }
+
+    // The point of the timeline when the active window of the state ends, so
+    // the state is closed by a timer like by a timeout.
+    fn window_end(&self, state: &State, now: Duration) -> Option<Duration> {
+        let schedule = match self.active {
+            Some(ref schedule) if schedule.close_on_end => schedule,
+            _ => return None,
+        };
+        state.messages().last().and_then(|last_message| {
+            let elapsed = state.elapsed_time_since_last_message(now);
+            let time = schedule.time_after(last_message, self.wall_time(), elapsed);
+            schedule.end_after(time).map(|end| now + Duration::from_secs(end - time))
+        })
     }
 
     fn is_max_size_reached(&self, state: &State) -> bool {
-        self.conditions.max_size.map_or(false, |max_size| state.messages().len() >= max_size)
+        self.conditions.max_size.map_or(false, |max_size| state.message_count() >= max_size)
     }
 
     fn is_closing_message(&self, state: &State) -> bool {
@@ -71,45 +179,125 @@ impl BaseContext {
         }
     }
 
-    fn is_any_timer_expired(&self, state: &State) -> bool {
-        self.is_timeout_expired(state) || self.is_renew_timeout_expired(state)
+    fn is_any_timer_expired(&self, state: &State, now: Duration) -> bool {
+        self.is_timeout_expired(state, now) || self.is_renew_timeout_expired(state, now)
     }
 
-    fn is_timeout_expired(&self, state: &State) -> bool {
-        state.elapsed_time() >= self.conditions.timeout
+    fn is_timeout_expired(&self, state: &State, now: Duration) -> bool {
+        state.elapsed_time(now) >= self.conditions.timeout
     }
 
-    fn is_renew_timeout_expired(&self, state: &State) -> bool {
+    fn is_renew_timeout_expired(&self, state: &State, now: Duration) -> bool {
         self.conditions.renew_timeout.map_or(false, |renew_timeout| {
-            state.elapsed_time_since_last_message() >= renew_timeout
+            state.elapsed_time_since_last_message(now) >= renew_timeout
         })
     }
 
-    pub fn on_timer(&self,
-                    event: &TimerEvent,
-                    state: &mut State,
-                    responder: &mut ResponseSender) {
+    pub fn on_timer(&self, now: Duration, state: &mut State, responder: &mut ResponseSender) {
         if state.is_open() {
-            state.update_timers(event);
+            state.sync(now);
+            self.on_children_timer(now, state, responder);
+        }
+        if self.is_closing(state, now) {
+            self.close(state, CloseReason::Timeout, responder);
+        }
+    }
+
+    fn on_children_timer(&self,
+                         now: Duration,
+                         state: &mut State,
+                         responder: &mut ResponseSender) {
+        if self.children.is_empty() {
+            return;
         }
-        if self.is_closing(state) {
-            self.close(state, responder);
+        let results = {
+            let mut child_responder = ChildResponder::new(responder);
+            let child_states = state.children_mut(self.children.len());
+            for (child, states) in self.children.iter().zip(child_states.iter_mut()) {
+                child.on_timer(now, states, &mut child_responder);
+            }
+            child_responder.into_results()
+        };
+        for result in results {
+            state.add_message(result);
         }
     }
 
     pub fn on_message(&self,
                       event: Arc<Message>,
                       state: &mut State,
+                      now: Duration,
                       responder: &mut ResponseSender) {
+        state.sync(now);
+        if self.is_subscribed(&event) {
+            if state.is_open() {
+                state.add_message(event.clone());
+            } else if self.is_opening(&event) {
+                state.add_message(event.clone());
+                self.open(state, responder);
+            }
+        }
+
         if state.is_open() {
-            state.add_message(event);
-        } else if self.is_opening(&event) {
-            state.add_message(event);
-            self.open(state, responder);
+            self.on_children_message(event, state, now, responder);
+        }
+
+        if self.is_closing(state, now) {
+            let reason = if self.is_any_timer_expired(state, now) {
+                CloseReason::Timeout
+            } else {
+                CloseReason::Condition
+            };
+            self.close(state, reason, responder);
         }
+    }
+
+    // The alerts of the children are added to the state like any other
+    // message, so they count towards max_size and renew the renew_timeout.
+    fn on_children_message(&self,
+                           event: Arc<Message>,
+                           state: &mut State,
+                           now: Duration,
+                           responder: &mut ResponseSender) {
+        if self.children.is_empty() {
+            return;
+        }
+        let retaining = state.is_retaining();
+        let results = {
+            let mut child_responder = ChildResponder::new(responder);
+            let (messages, child_states) = state.messages_and_children_mut(self.children.len());
+            for (child, states) in self.children.iter().zip(child_states.iter_mut()) {
+                child.on_message(event.clone(),
+                                 messages,
+                                 retaining,
+                                 states,
+                                 now,
+                                 &mut child_responder);
+            }
+            child_responder.into_results()
+        };
+        for result in results {
+            state.add_message(result);
+        }
+    }
 
-        if self.is_closing(state) {
-            self.close(state, responder);
+    fn close_children(&self,
+                      state: &mut State,
+                      reason: CloseReason,
+                      responder: &mut ResponseSender) {
+        if self.children.is_empty() {
+            return;
+        }
+        let results = {
+            let mut child_responder = ChildResponder::new(responder);
+            let child_states = state.children_mut(self.children.len());
+            for (child, states) in self.children.iter().zip(child_states.iter_mut()) {
+                child.close_all(states, reason, &mut child_responder);
+            }
+            child_responder.into_results()
+        };
+        for result in results {
+            state.add_message(result);
         }
     }
 
@@ -121,8 +309,141 @@ impl BaseContext {
         state.open();
     }
 
-    fn close(&self, state: &mut State, responder: &mut ResponseSender) {
-        trace!("Context: closing state; uuid={}", self.uuid());
+    pub fn info(&self, states: Vec<StateInfo>) -> ContextInfo {
+        ContextInfo {
+            uuid: self.uuid,
+            name: self.name.clone(),
+            open: !states.is_empty(),
+            states: states,
+        }
+    }
+
+    pub fn state_info(&self, key: ContextKey, state: &State, now: Duration) -> StateInfo {
+        StateInfo {
+            key: key,
+            age: state.elapsed_time(now),
+            idle: state.elapsed_time_since_last_message(now),
+            message_count: state.message_count(),
+            children: self.children
+                          .iter()
+                          .zip(state.children())
+                          .map(|(child, states)| child.info(states, now))
+                          .collect(),
+        }
+    }
+
+    pub fn snapshot_state(&self, key: ContextKey, state: &State, now: Duration) -> StateSnapshot {
+        let mut snapshot = state.to_snapshot(key, now);
+        for (child, states) in self.children.iter().zip(state.children()) {
+            snapshot.children.push(child.snapshot(states, now));
+        }
+        snapshot
+    }
+
+    // The uuids of the children which are not found are added to orphans.
+    pub fn restore_state(&self,
+                         mut snapshot: StateSnapshot,
+                         now: Duration,
+                         orphans: &mut Vec<Uuid>)
+                         -> State {
+        let children = mem::replace(&mut snapshot.children, Vec::new());
+        let mut state = State::from_snapshot(snapshot, now);
+        {
+            let child_states = state.children_mut(self.children.len());
+            for i in children {
+                match self.children.iter().position(|child| child.base().uuid() == &i.uuid) {
+                    Some(index) => {
+                        self.children[index].restore(i, &mut child_states[index], now, orphans)
+                    }
+                    None => orphans.push(i.uuid),
+                }
+            }
+        }
+        state
+    }
+
+    // Moves a state of old, the context with the same uuid before a reload,
+    // to this context. The states of the children which are not found are
+    // closed by mode.
+    pub fn adopt_state(&self,
+                       old: &BaseContext,
+                       mut state: State,
+                       old_now: Duration,
+                       now: Duration,
+                       mode: CloseMode,
+                       responder: &mut ResponseSender)
+                       -> State {
+        state.rebase(old_now, now);
+        let old_children = state.take_children();
+        let results = {
+            let mut child_responder = ChildResponder::new(responder);
+            let child_states = state.children_mut(self.children.len());
+            for (old_child, mut states) in old.children.iter().zip(old_children) {
+                let uuid = old_child.base().uuid();
+                match self.children.iter().position(|child| child.base().uuid() == uuid) {
+                    Some(index) => {
+                        child_states[index] = self.children[index].adopt(old_child,
+                                                                         states,
+                                                                         old_now,
+                                                                         now,
+                                                                         mode,
+                                                                         &mut child_responder)
+                    }
+                    None => {
+                        trace!("Context: child context is removed, closing its states; uuid={} \
+                                mode={:?}",
+                               uuid,
+                               mode);
+                        if mode == CloseMode::Execute {
+                            old_child.close_all(&mut states,
+                                                CloseReason::Requested,
+                                                &mut child_responder);
+                        }
+                    }
+                }
+            }
+            child_responder.into_results()
+        };
+        for result in results {
+            state.add_message(result);
+        }
+        state
+    }
+
+    pub fn is_selected(&self,
+                       selector: &Selector,
+                       key: &ContextKey,
+                       state: &State,
+                       now: Duration)
+                       -> bool {
+        match *selector {
+            Selector::All => true,
+            Selector::Context(ref uuid) => uuid == &self.uuid,
+            Selector::Key(ref uuid, ref selected) => uuid == &self.uuid && selected == key,
+            Selector::Matching(ref predicate) => {
+                predicate(&self.uuid, &self.state_info(key.clone(), state, now))
+            }
+        }
+    }
+
+    pub fn close_with_mode(&self,
+                           state: &mut State,
+                           mode: CloseMode,
+                           reason: CloseReason,
+                           responder: &mut ResponseSender) {
+        match mode {
+            CloseMode::Execute => self.close(state, reason, responder),
+            CloseMode::Discard => {
+                trace!("Context: discarding state; uuid={}", self.uuid());
+                state.close();
+            }
+        }
+    }
+
+    pub fn close(&self, state: &mut State, reason: CloseReason, responder: &mut ResponseSender) {
+        trace!("Context: closing state; uuid={} reason={}", self.uuid(), reason);
+        state.set_close_reason(Some(reason));
+        self.close_children(state, reason, responder);
         for i in self.actions() {
             i.on_closed(state, self, responder);
         }
@@ -130,12 +451,22 @@ impl BaseContext {
     }
 }
 
+fn remaining(timeout: Duration, elapsed: Duration) -> Duration {
+    if timeout > elapsed {
+        timeout - elapsed
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
 pub struct BaseContextBuilder {
     name: Option<String>,
     uuid: Uuid,
     conditions: Conditions,
     actions: Vec<ActionType>,
-    patterns: Vec<String>
+    patterns: Vec<String>,
+    active: Option<Schedule>,
+    children: Vec<ChildContext>
 }
 
 impl BaseContextBuilder {
@@ -145,7 +476,9 @@ impl BaseContextBuilder {
             uuid: uuid,
             conditions: conditions,
             actions: Vec::new(),
-            patterns: Vec::new()
+            patterns: Vec::new(),
+            active: None,
+            children: Vec::new()
         }
     }
 
@@ -163,14 +496,28 @@ impl BaseContextBuilder {
         self.patterns = patterns;
         self
     }
+
+    pub fn active(mut self, active: Option<Schedule>) -> BaseContextBuilder {
+        self.active = active;
+        self
+    }
+
+    pub fn children(mut self, children: Vec<ChildContext>) -> BaseContextBuilder {
+        self.children = children;
+        self
+    }
+
     pub fn build(self) -> BaseContext {
-        let BaseContextBuilder {name, uuid, conditions, actions, patterns} = self;
+        let BaseContextBuilder {name, uuid, conditions, actions, patterns, active, children} = self;
         BaseContext {
             name: name,
             uuid: uuid,
             conditions: conditions,
             actions: actions,
-            patterns: patterns
+            patterns: patterns,
+            active: active,
+            children: children,
+            clock: None,
         }
     }
 }
@@ -0,0 +1,143 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::ops::{Add, Sub};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+impl Add for MemoryUsage {
+    type Output = MemoryUsage;
+
+    fn add(self, other: MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            messages: self.messages + other.messages,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+impl Sub for MemoryUsage {
+    type Output = MemoryUsage;
+
+    fn sub(self, other: MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            messages: self.messages.saturating_sub(other.messages),
+            bytes: self.bytes.saturating_sub(other.bytes),
+        }
+    }
+}
+
+// The usage of the states of every shard of a correlator, so its budget is
+// enforced on the total. Every shard replaces its own part when it checks the
+// budget.
+#[derive(Debug, Default)]
+pub struct SharedUsage {
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl SharedUsage {
+    pub fn new() -> SharedUsage {
+        SharedUsage::default()
+    }
+
+    // The current usage is added first, so the total never goes below zero.
+    pub fn update(&self, previous: MemoryUsage, current: MemoryUsage) {
+        self.messages.fetch_add(current.messages, Ordering::SeqCst);
+        self.bytes.fetch_add(current.bytes, Ordering::SeqCst);
+        self.messages.fetch_sub(previous.messages, Ordering::SeqCst);
+        self.bytes.fetch_sub(previous.bytes, Ordering::SeqCst);
+    }
+
+    pub fn total(&self) -> MemoryUsage {
+        MemoryUsage {
+            messages: self.messages.load(Ordering::SeqCst),
+            bytes: self.bytes.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shedding {
+    // Closes the oldest open states (executing their actions) until the usage
+    // goes below the limit.
+    CloseOldest,
+    // Existing states are kept, but new ones are not opened while the limit
+    // is exceeded.
+    RefuseNew,
+    // States only count their messages (and keep the last one for the
+    // closing conditions) while the limit is exceeded.
+    CountOnly,
+}
+
+impl Default for Shedding {
+    fn default() -> Shedding {
+        Shedding::CloseOldest
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub max_messages: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub shedding: Shedding,
+}
+
+impl MemoryBudget {
+    pub fn new() -> MemoryBudget {
+        MemoryBudget::default()
+    }
+
+    pub fn max_messages(mut self, max_messages: usize) -> MemoryBudget {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> MemoryBudget {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn shedding(mut self, shedding: Shedding) -> MemoryBudget {
+        self.shedding = shedding;
+        self
+    }
+
+    pub fn is_exceeded(&self, usage: &MemoryUsage) -> bool {
+        self.max_messages.map_or(false, |max| usage.messages > max) ||
+        self.max_bytes.map_or(false, |max| usage.bytes > max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryBudget, MemoryUsage, SharedUsage};
+
+    #[test]
+    fn test_given_memory_budget_when_any_of_its_limits_is_exceeded_then_it_is_exceeded() {
+        let budget = MemoryBudget::new().max_messages(10).max_bytes(1000);
+        assert_false!(budget.is_exceeded(&MemoryUsage { messages: 10, bytes: 1000 }));
+        assert_true!(budget.is_exceeded(&MemoryUsage { messages: 11, bytes: 0 }));
+        assert_true!(budget.is_exceeded(&MemoryUsage { messages: 0, bytes: 1001 }));
+        assert_false!(MemoryBudget::new().is_exceeded(&MemoryUsage { messages: 100, bytes: 100 }));
+    }
+
+    #[test]
+    fn test_given_shared_usage_when_the_shards_update_it_then_it_contains_their_total() {
+        let usage = SharedUsage::new();
+        usage.update(MemoryUsage::default(), MemoryUsage { messages: 2, bytes: 20 });
+        usage.update(MemoryUsage::default(), MemoryUsage { messages: 3, bytes: 30 });
+        usage.update(MemoryUsage { messages: 2, bytes: 20 }, MemoryUsage { messages: 1, bytes: 10 });
+        assert_eq!(MemoryUsage { messages: 4, bytes: 40 }, usage.total());
+    }
+}
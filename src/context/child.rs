@@ -0,0 +1,191 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use config::action::ActionType;
+use context::base::BaseContext;
+use context::map::key::KeyExpression;
+use context::map::map::{context_key, ContextKey};
+use control::{CloseMode, CloseReason};
+use dispatcher::Response;
+use dispatcher::response::ResponseSender;
+use introspection::ContextInfo;
+use message::Message;
+use snapshot::ContextSnapshot;
+use state::{ChildStates, State};
+
+// A context nested into an other one. Its states live in the states of its
+// parent, so they can be opened only while the parent state is open and they
+// are closed together with it. A child without context_id has one state per
+// parent state.
+pub struct ChildContext {
+    base: BaseContext,
+    context_id: Vec<KeyExpression>,
+    inherits: bool,
+}
+
+impl ChildContext {
+    pub fn new(base: BaseContext, context_id: Vec<KeyExpression>) -> ChildContext {
+        let inherits = base.actions().iter().any(|action| {
+            match *action {
+                ActionType::Message(ref action) => !action.inherit().is_empty(),
+            }
+        });
+        ChildContext {
+            base: base,
+            context_id: context_id,
+            inherits: inherits,
+        }
+    }
+
+    pub fn base(&self) -> &BaseContext {
+        &self.base
+    }
+
+    pub fn base_mut(&mut self) -> &mut BaseContext {
+        &mut self.base
+    }
+
+    // The values of the parent messages are copied into a new state only if
+    // the actions of the child inherit some of them.
+    pub fn on_message(&self,
+                      event: Arc<Message>,
+                      parent_messages: &[Arc<Message>],
+                      retaining: bool,
+                      states: &mut ChildStates,
+                      now: Duration,
+                      responder: &mut ResponseSender) {
+        if !self.base.is_interested(&event) {
+            return;
+        }
+        {
+            let key = match context_key(&self.context_id, &event) {
+                Some(key) => key,
+                None => return,
+            };
+            let state = states.entry(key).or_insert_with(|| {
+                let mut state = State::new();
+                if self.inherits {
+                    state.set_parent_values(State::values_of(parent_messages));
+                }
+                state
+            });
+            state.set_retaining(retaining);
+            self.base.on_message(event, state, now, responder);
+        }
+        ChildContext::remove_closed_states(states);
+    }
+
+    pub fn on_timer(&self, now: Duration, states: &mut ChildStates, responder: &mut ResponseSender) {
+        for state in states.values_mut() {
+            self.base.on_timer(now, state, responder);
+        }
+        ChildContext::remove_closed_states(states);
+    }
+
+    pub fn deadline(&self, states: &ChildStates, now: Duration) -> Option<Duration> {
+        states.values().filter_map(|state| self.base.deadline(state, now)).min()
+    }
+
+    pub fn close_all(&self,
+                     states: &mut ChildStates,
+                     reason: CloseReason,
+                     responder: &mut ResponseSender) {
+        for state in states.values_mut() {
+            if state.is_open() {
+                self.base.close(state, reason, responder);
+            }
+        }
+        states.clear();
+    }
+
+    pub fn info(&self, states: &ChildStates, now: Duration) -> ContextInfo {
+        self.base.info(states.iter()
+                             .map(|(key, state)| self.base.state_info(key.clone(), state, now))
+                             .collect())
+    }
+
+    pub fn snapshot(&self, states: &ChildStates, now: Duration) -> ContextSnapshot {
+        ContextSnapshot {
+            uuid: *self.base.uuid(),
+            states: states.iter()
+                          .map(|(key, state)| self.base.snapshot_state(key.clone(), state, now))
+                          .collect(),
+        }
+    }
+
+    pub fn restore(&self,
+                   snapshot: ContextSnapshot,
+                   states: &mut ChildStates,
+                   now: Duration,
+                   orphans: &mut Vec<Uuid>) {
+        for i in snapshot.states {
+            let key = i.key.clone();
+            let state = self.base.restore_state(i, now, orphans);
+            states.insert(key, state);
+        }
+    }
+
+    pub fn adopt(&self,
+                 old: &ChildContext,
+                 states: ChildStates,
+                 old_now: Duration,
+                 now: Duration,
+                 mode: CloseMode,
+                 responder: &mut ResponseSender)
+                 -> ChildStates {
+        states.into_iter()
+              .map(|(key, state)| {
+                  (key, self.base.adopt_state(&old.base, state, old_now, now, mode, responder))
+              })
+              .collect()
+    }
+
+    fn remove_closed_states(states: &mut ChildStates) {
+        let closed = states.iter()
+                           .filter(|&(_, state)| !state.is_open())
+                           .map(|(key, _)| key.clone())
+                           .collect::<Vec<ContextKey>>();
+        for key in closed {
+            let _ = states.remove(&key);
+        }
+    }
+}
+
+// Forwards the responses of the children and keeps a copy of their alerts, so
+// they can be added to the parent state.
+pub struct ChildResponder<'a> {
+    responder: &'a mut ResponseSender,
+    results: Vec<Arc<Message>>,
+}
+
+impl<'a> ChildResponder<'a> {
+    pub fn new(responder: &'a mut ResponseSender) -> ChildResponder<'a> {
+        ChildResponder {
+            responder: responder,
+            results: Vec::new(),
+        }
+    }
+
+    pub fn into_results(self) -> Vec<Arc<Message>> {
+        self.results
+    }
+}
+
+impl<'a> ResponseSender for ChildResponder<'a> {
+    fn send_response(&mut self, response: Response) {
+        if let Response::Alert(ref alert) = response {
+            self.results.push(Arc::new(alert.message.clone()));
+        }
+        self.responder.send_response(response);
+    }
+}
@@ -7,16 +7,76 @@
 // modified, or distributed except according to those terms.
 
 use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
 
+use uuid::Uuid;
+
+use clock::Clock;
 use config::ContextConfig;
-use context::Context;
+use context::{ChildContext, Context};
+use context::budget::{MemoryBudget, MemoryUsage, SharedUsage, Shedding};
+use context::shard::Shard;
+use control::{CloseMode, CloseReason, Selector, ShutdownMode};
+use dispatcher::response::ResponseSender;
+use introspection::ContextInfo;
+use message::Message;
+use snapshot::Snapshot;
+
+// The uuids of the top-level contexts affected by a reload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReloadReport {
+    pub added: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+    pub kept: Vec<Uuid>,
+}
+
+impl ReloadReport {
+    // Adds the uuids of the report of another shard.
+    pub fn merge(&mut self, other: ReloadReport) {
+        ReloadReport::extend(&mut self.added, other.added);
+        ReloadReport::extend(&mut self.removed, other.removed);
+        ReloadReport::extend(&mut self.kept, other.kept);
+    }
+
+    fn extend(uuids: &mut Vec<Uuid>, other: Vec<Uuid>) {
+        for i in other {
+            if !uuids.contains(&i) {
+                uuids.push(i);
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "contexts are reloaded; added={} removed={} kept={}",
+               self.added.len(),
+               self.removed.len(),
+               self.kept.len())
+    }
+}
 
 #[derive(Default)]
 pub struct ContextMap {
     map: HashMap<String, Vec<usize>>,
     contexts: Vec<Context>,
+    budget: Option<MemoryBudget>,
+    shared_usage: Option<Arc<SharedUsage>>,
+    reported_usage: MemoryUsage,
+    shedding: bool,
+    shed_states: u64,
+    clock: Option<Arc<Clock>>,
+    shard: Option<Shard>,
+    max_loopback_depth: Option<usize>,
+    dropped_loopbacks: u64,
 }
 
+pub const DEFAULT_MAX_LOOPBACK_DEPTH: usize = 8;
+
 impl ContextMap {
     pub fn new() -> ContextMap {
         ContextMap::default()
@@ -24,20 +84,62 @@ impl ContextMap {
 
     pub fn from_configs(configs: Vec<ContextConfig>) -> ContextMap {
         let mut context_map = ContextMap::new();
+        let uuids = configs.iter().map(|config| config.uuid).collect::<Vec<Uuid>>();
+        let mut roots = Vec::new();
+        let mut children: HashMap<Uuid, Vec<ContextConfig>> = HashMap::new();
         for i in configs {
-            context_map.insert(i.into());
+            match i.parent {
+                Some(parent) if uuids.contains(&parent) => {
+                    children.entry(parent).or_insert_with(Vec::new).push(i)
+                }
+                Some(parent) => {
+                    error!("ContextMap: parent of context is not found, using it as a top-level \
+                            context; uuid={} parent={}",
+                           i.uuid,
+                           parent);
+                    roots.push(i);
+                }
+                None => roots.push(i),
+            }
+        }
+        for i in roots {
+            let context_children = ContextMap::build_children(&i.uuid, &mut children);
+            context_map.insert(Context::with_children(i, context_children));
+        }
+        for (parent, configs) in children {
+            for i in configs {
+                error!("ContextMap: context is part of a parent cycle, ignoring it; uuid={} \
+                        parent={}",
+                       i.uuid,
+                       parent);
+            }
         }
         context_map
     }
 
-    pub fn insert(&mut self, context: Context) {
+    fn build_children(parent: &Uuid,
+                      children: &mut HashMap<Uuid, Vec<ContextConfig>>)
+                      -> Vec<ChildContext> {
+        let configs = children.remove(parent).unwrap_or_default();
+        configs.into_iter()
+               .map(|config| {
+                   let grandchildren = ContextMap::build_children(&config.uuid, children);
+                   Context::child(config, grandchildren)
+               })
+               .collect()
+    }
+
+    pub fn insert(&mut self, mut context: Context) {
+        if let Some(ref clock) = self.clock {
+            context.set_clock(clock.clone());
+        }
         self.contexts.push(context);
         let last_context = self.contexts
                                .last()
                                .expect("Failed to remove the last Context from a non empty vector");
         let index_of_last_context = self.contexts.len() - 1;
-        let patterns = last_context.patterns();
-        ContextMap::update_indices(&mut self.map, index_of_last_context, patterns);
+        let patterns = last_context.subscriptions();
+        ContextMap::update_indices(&mut self.map, index_of_last_context, &patterns);
     }
 
     fn update_indices(map: &mut HashMap<String, Vec<usize>>,
@@ -64,11 +166,378 @@ impl ContextMap {
         }
     }
 
+    // The time left until the next timer event any of the contexts needs. None
+    // means that no timer event is needed until the next message.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.contexts.iter().filter_map(|context| context.next_timeout()).min()
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        for i in &mut self.contexts {
+            i.set_clock(clock.clone());
+        }
+        self.clock = Some(clock);
+    }
+
+    // Keeps only the contexts and states owned by the shard. None removes the
+    // partitioning of the remaining contexts.
+    pub fn set_shard(&mut self, shard: Option<Shard>) {
+        let contexts = mem::replace(&mut self.contexts, Vec::new());
+        self.map.clear();
+        for mut i in contexts {
+            let is_owned = match i {
+                Context::Linear(ref context) => {
+                    shard.map_or(true, |shard| shard.owns_context(context.base().uuid()))
+                }
+                Context::Map(ref mut context) => {
+                    context.set_shard(shard);
+                    true
+                }
+            };
+            if is_owned {
+                self.insert(i);
+            }
+        }
+        self.shard = shard;
+    }
+
+    pub fn shard(&self) -> Option<Shard> {
+        self.shard
+    }
+
+    // Moves the contexts and the states of another shard into this map.
+    pub fn merge(&mut self, other: ContextMap) {
+        self.shed_states += other.shed_states;
+        self.dropped_loopbacks += other.dropped_loopbacks;
+        for i in other.contexts {
+            if self.contexts.iter().any(|context| context.uuid() == i.uuid()) {
+                let snapshot = Snapshot { contexts: vec![i.snapshot()] };
+                for orphan in self.restore(snapshot) {
+                    warn!("ContextMap: context of a merged state is not found, discarding its \
+                           states; uuid={}",
+                          orphan);
+                }
+            } else {
+                self.insert(i);
+            }
+        }
+    }
+
+    // The loopback messages which were generated more times than this in a
+    // row are dropped, so a cycle of the rules can't run forever.
+    pub fn set_max_loopback_depth(&mut self, depth: usize) {
+        self.max_loopback_depth = Some(depth);
+    }
+
+    pub fn max_loopback_depth(&self) -> usize {
+        self.max_loopback_depth.unwrap_or(DEFAULT_MAX_LOOPBACK_DEPTH)
+    }
+
+    // Returns false if the message is too deep to be handled.
+    pub fn accept_loopback(&mut self, message: &Message) -> bool {
+        if message.hops() <= self.max_loopback_depth() {
+            return true;
+        }
+        warn!("ContextMap: loopback message is dropped, the maximum loopback depth is reached; \
+               uuid={} hops={}",
+              message.uuid(),
+              message.hops());
+        self.dropped_loopbacks += 1;
+        false
+    }
+
+    // The number of loopback messages dropped by the loopback depth.
+    pub fn dropped_loopbacks(&self) -> u64 {
+        self.dropped_loopbacks
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.contexts.iter().map(|context| context.evictions()).sum()
+    }
+
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) {
+        if self.shedding {
+            self.stop_shedding();
+        }
+        self.budget = budget;
+    }
+
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.budget.as_ref()
+    }
+
+    // The budget is enforced on the usage of every map sharing the counter,
+    // like the shards of a correlator.
+    pub fn set_shared_usage(&mut self, usage: Option<Arc<SharedUsage>>) {
+        if let Some(ref shared_usage) = self.shared_usage {
+            shared_usage.update(self.reported_usage, MemoryUsage::default());
+        }
+        self.reported_usage = MemoryUsage::default();
+        self.shared_usage = usage;
+    }
+
+    // The usage the budget is enforced on.
+    fn total_usage(&mut self) -> MemoryUsage {
+        let usage = self.usage();
+        match self.shared_usage {
+            Some(ref shared_usage) => {
+                shared_usage.update(self.reported_usage, usage);
+                self.reported_usage = usage;
+                shared_usage.total()
+            }
+            None => usage,
+        }
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.contexts.iter().fold(MemoryUsage::default(), |usage, context| usage + context.usage())
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding
+    }
+
+    // The number of states closed because the memory budget was exceeded.
+    pub fn shed_states(&self) -> u64 {
+        self.shed_states
+    }
+
+    pub fn enforce_memory_budget(&mut self, responder: &mut ResponseSender) {
+        let budget = match self.budget {
+            Some(ref budget) => budget.clone(),
+            None => return,
+        };
+        let usage = self.total_usage();
+        let exceeded = budget.is_exceeded(&usage);
+        match budget.shedding {
+            Shedding::CloseOldest => {
+                if exceeded {
+                    warn!("ContextMap: memory budget is exceeded, closing the oldest states; \
+                           usage={:?}",
+                          usage);
+                }
+                while budget.is_exceeded(&self.total_usage()) {
+                    if !self.close_oldest_state(responder) {
+                        break;
+                    }
+                }
+            }
+            Shedding::RefuseNew | Shedding::CountOnly => {
+                if exceeded && !self.shedding {
+                    warn!("ContextMap: memory budget is exceeded, shedding with {:?}; usage={:?}",
+                          budget.shedding,
+                          usage);
+                    self.start_shedding(budget.shedding);
+                } else if !exceeded && self.shedding {
+                    info!("ContextMap: memory usage is back under the budget; usage={:?}",
+                          usage);
+                    self.stop_shedding();
+                }
+            }
+        }
+    }
+
+    fn start_shedding(&mut self, shedding: Shedding) {
+        for i in &mut self.contexts {
+            match shedding {
+                Shedding::RefuseNew => i.set_admitting(false),
+                Shedding::CountOnly => i.set_retaining(false),
+                Shedding::CloseOldest => {}
+            }
+        }
+        self.shedding = true;
+    }
+
+    fn stop_shedding(&mut self) {
+        for i in &mut self.contexts {
+            i.set_admitting(true);
+            i.set_retaining(true);
+        }
+        self.shedding = false;
+    }
+
+    // Returns false if there is no open state to close. The contexts keep
+    // their states ordered by age, so only their oldest states are compared.
+    fn close_oldest_state(&mut self, responder: &mut ResponseSender) -> bool {
+        let oldest = self.contexts
+                         .iter()
+                         .enumerate()
+                         .filter_map(|(index, context)| {
+                             context.oldest_state_age().map(|age| (index, age))
+                         })
+                         .max_by_key(|&(_, age)| age)
+                         .map(|(index, _)| index);
+        match oldest {
+            Some(index) => {
+                self.contexts[index].close_oldest_state(responder);
+                self.shed_states += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns the number of closed states.
+    pub fn close_states(&mut self,
+                        selector: &Selector,
+                        mode: CloseMode,
+                        responder: &mut ResponseSender)
+                        -> usize {
+        self.close_states_by(selector, mode, CloseReason::Requested, responder)
+    }
+
+    fn close_states_by(&mut self,
+                       selector: &Selector,
+                       mode: CloseMode,
+                       reason: CloseReason,
+                       responder: &mut ResponseSender)
+                       -> usize {
+        self.contexts.iter_mut().fold(0, |closed, context| {
+            closed + context.close_states(selector, mode, reason, responder)
+        })
+    }
+
+    // Empties the map. The returned snapshot contains the open states only if
+    // they are persisted.
+    pub fn shutdown(&mut self, mode: ShutdownMode, responder: &mut ResponseSender) -> Snapshot {
+        let snapshot = match mode {
+            ShutdownMode::Persist => self.snapshot(),
+            _ => Snapshot::default(),
+        };
+        let close_mode = match mode {
+            ShutdownMode::Flush => CloseMode::Execute,
+            ShutdownMode::Discard | ShutdownMode::Persist => CloseMode::Discard,
+        };
+        let closed = self.close_states_by(&Selector::All,
+                                          close_mode,
+                                          CloseReason::Shutdown,
+                                          responder);
+        trace!("ContextMap: shut down; mode={:?} closed={}", mode, closed);
+        snapshot
+    }
+
+    pub fn query(&self) -> Vec<ContextInfo> {
+        self.contexts.iter().map(|context| context.info()).collect()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            contexts: self.contexts
+                          .iter()
+                          .map(|context| context.snapshot())
+                          .filter(|context| !context.states.is_empty())
+                          .collect(),
+        }
+    }
+
+    // Returns the uuids of the contexts (or child contexts) whose states are
+    // not restored because they are not found.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Vec<Uuid> {
+        let mut orphans = Vec::new();
+        for i in snapshot.contexts {
+            match self.contexts.iter_mut().find(|context| context.uuid() == &i.uuid) {
+                Some(context) => context.restore(i, &mut orphans),
+                None => orphans.push(i.uuid),
+            }
+        }
+        orphans
+    }
+
+    // Replaces the contexts with the ones built from configs. The states of the
+    // contexts which are kept are moved to the new contexts with the same
+    // uuid, the states of the removed contexts and child contexts are closed
+    // by mode.
+    pub fn reload(&mut self,
+                  configs: Vec<ContextConfig>,
+                  mode: CloseMode,
+                  responder: &mut ResponseSender)
+                  -> ReloadReport {
+        let mut context_map = ContextMap::from_configs(configs);
+        if let Some(clock) = self.clock.take() {
+            context_map.set_clock(clock);
+        }
+        if self.shard.is_some() {
+            context_map.set_shard(self.shard);
+        }
+        let mut report = ReloadReport::default();
+        for i in &mut self.contexts {
+            let uuid = *i.uuid();
+            match context_map.contexts.iter_mut().find(|context| context.uuid() == &uuid) {
+                Some(context) => {
+                    context.adopt_states(i, mode, responder);
+                    report.kept.push(uuid);
+                }
+                None => {
+                    i.close_states(&Selector::Context(uuid),
+                                   mode,
+                                   CloseReason::Requested,
+                                   responder);
+                    report.removed.push(uuid);
+                }
+            }
+        }
+        report.added = context_map.contexts
+                                  .iter()
+                                  .map(|context| *context.uuid())
+                                  .filter(|uuid| !report.kept.contains(uuid))
+                                  .collect();
+        context_map.budget = self.budget.take();
+        context_map.shared_usage = self.shared_usage.take();
+        context_map.reported_usage = self.reported_usage;
+        context_map.shed_states = self.shed_states;
+        context_map.max_loopback_depth = self.max_loopback_depth;
+        context_map.dropped_loopbacks = self.dropped_loopbacks;
+        let shedding = self.shedding;
+        *self = context_map;
+        if shedding {
+            if let Some(shedding) = self.budget.as_ref().map(|budget| budget.shedding) {
+                self.start_shedding(shedding);
+            }
+        }
+        report
+    }
+
+    pub fn contexts(&self) -> &[Context] {
+        &self.contexts
+    }
+
     pub fn contexts_mut(&mut self) -> &mut Vec<Context> {
         &mut self.contexts
     }
 
-    pub fn contexts_iter_mut(&mut self, key: &str) -> Iterator {
+    // The indices of the contexts subscribed to key in contexts_mut().
+    pub fn subscribers(&self, key: &str) -> &[usize] {
+        self.map.get(key).map_or(&[], |ids| &ids[..])
+    }
+
+    // Splits a batch into the messages of the subscribed contexts, the
+    // subscribers of an id are looked up once. Every context gets its messages
+    // in the order of the batch, once per subscribed id like a single message.
+    pub fn group_by_context(&self, messages: Vec<Arc<Message>>) -> Vec<(usize, Vec<Arc<Message>>)> {
+        let mut by_id: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, message) in messages.iter().enumerate() {
+            for id in message.ids() {
+                by_id.entry(id).or_insert_with(Vec::new).push(index);
+            }
+        }
+        let mut slices = vec![Vec::new(); self.contexts.len()];
+        for (id, indices) in by_id {
+            for &context in self.subscribers(id) {
+                slices[context].extend_from_slice(&indices);
+            }
+        }
+        slices.into_iter()
+              .enumerate()
+              .filter(|&(_, ref indices)| !indices.is_empty())
+              .map(|(context, mut indices)| {
+                  indices.sort();
+                  let batch = indices.iter().map(|&index| messages[index].clone()).collect();
+                  (context, batch)
+              })
+              .collect()
+    }
+
+    pub fn contexts_iter_mut(&mut self, key: &str) -> Iterator<'_> {
         let ids = self.map.get(key);
         Iterator {
             ids: ids,
@@ -110,10 +579,53 @@ mod tests {
     use super::*;
 
     use conditions::ConditionsBuilder;
+    use config::{ContextConfig, ContextConfigBuilder};
+    use config::action::message::MessageActionBuilder;
     use context::{Context, LinearContext, BaseContextBuilder};
+    use context::budget::{MemoryBudget, Shedding};
+    use control::{CloseMode, Selector};
+    use dispatcher::Response;
+    use dispatcher::request::Request;
+    use message::MessageBuilder;
+    use serde_json::{from_str, to_string};
+    use snapshot::Snapshot;
+    use test_utils::MockResponseSender;
+    use timer::TimerEvent;
     use uuid::Uuid;
+    use std::sync::Arc;
     use std::time::Duration;
 
+    fn map_context_config(uuid: Uuid) -> ContextConfig {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(1000)).build();
+        let action = MessageActionBuilder::new("CLOSED", "closed").build();
+        ContextConfigBuilder::new(uuid, conditions)
+            .context_id(Some(vec!["user".into()]))
+            .actions(vec![action.into()])
+            .build()
+    }
+
+    fn budgeted_context_map(shedding: Shedding) -> ContextMap {
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(Uuid::new_v4())]);
+        context_map.set_memory_budget(Some(MemoryBudget::new().max_messages(3).shedding(shedding)));
+        context_map
+    }
+
+    fn send(context_map: &mut ContextMap, user: &str, responder: &mut MockResponseSender) {
+        let message = MessageBuilder::new("LOGIN", "login").pair("user", user).build();
+        let message = Arc::new(message);
+        for i in context_map.contexts_mut() {
+            i.on_event(Request::Message(message.clone()), responder);
+        }
+        context_map.enforce_memory_budget(responder);
+    }
+
+    fn tick(context_map: &mut ContextMap, responder: &mut MockResponseSender) {
+        for i in context_map.contexts_mut() {
+            i.on_event(Request::Timer(TimerEvent(Duration::from_millis(10))), responder);
+        }
+        context_map.enforce_memory_budget(responder);
+    }
+
     fn assert_context_map_contains_uuid(context_map: &mut ContextMap, uuid: &Uuid, key: &str) {
         let mut iter = context_map.contexts_iter_mut(key);
         let context = iter.next().expect("Failed to get back an inserted context");
@@ -142,4 +654,254 @@ mod tests {
         assert_context_map_contains_uuid(&mut context_map, &uuid, "A");
         assert_context_map_contains_uuid(&mut context_map, &uuid, "B");
     }
+
+    #[test]
+    fn test_given_context_map_when_a_context_has_a_parent_then_its_patterns_are_routed_to_the_parent
+        () {
+        let parent_uuid = Uuid::new_v4();
+        let conditions = ConditionsBuilder::new(Duration::from_millis(100)).build();
+        let configs = vec![
+            ContextConfigBuilder::new(Uuid::new_v4(), conditions.clone())
+                .patterns(vec!["C".to_owned()])
+                .parent(Some(parent_uuid))
+                .build(),
+            ContextConfigBuilder::new(parent_uuid, conditions.clone())
+                .patterns(vec!["A".to_owned()])
+                .build(),
+        ];
+        let mut context_map = ContextMap::from_configs(configs);
+        assert_eq!(context_map.contexts_mut().len(), 1);
+        assert_context_map_contains_uuid(&mut context_map, &parent_uuid, "A");
+        assert_context_map_contains_uuid(&mut context_map, &parent_uuid, "C");
+    }
+
+    #[test]
+    fn test_given_context_map_when_a_batch_is_grouped_then_every_context_gets_its_messages_in_order
+        () {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(100)).build();
+        let config = |patterns: &[&str]| {
+            ContextConfigBuilder::new(Uuid::new_v4(), conditions.clone())
+                .patterns(patterns.iter().map(|pattern| pattern.to_string()).collect())
+                .build()
+        };
+        let context_map = ContextMap::from_configs(vec![config(&["A"]),
+                                                        config(&["B"]),
+                                                        config(&["A", "B"])]);
+        let message = |uuid: &str, text: &str| Arc::new(MessageBuilder::new(uuid, text).build());
+        let batch = vec![message("A", "1"), message("B", "2"), message("A", "3"), message("C", "4")];
+        let groups = context_map.group_by_context(batch)
+                                .into_iter()
+                                .map(|(context, messages)| {
+                                    let texts = messages.iter()
+                                                        .map(|message| message.message().clone())
+                                                        .collect::<Vec<String>>();
+                                    (context, texts)
+                                })
+                                .collect::<Vec<(usize, Vec<String>)>>();
+        let texts = |texts: &[&str]| texts.iter().map(|text| text.to_string()).collect();
+        assert_eq!(vec![(0, texts(&["1", "3"])), (1, texts(&["2"])), (2, texts(&["1", "2", "3"]))],
+                   groups);
+    }
+
+    #[test]
+    fn test_given_context_map_with_memory_budget_when_it_is_exceeded_then_the_oldest_state_is_closed
+        () {
+        let mut responder = MockResponseSender::default();
+        let mut context_map = budgeted_context_map(Shedding::CloseOldest);
+        send(&mut context_map, "alice", &mut responder);
+        tick(&mut context_map, &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        assert_eq!(3, context_map.usage().messages);
+        assert_eq!(0, responder.0.len());
+        send(&mut context_map, "carol", &mut responder);
+        assert_eq!(3, context_map.usage().messages);
+        assert_eq!(1, context_map.shed_states());
+        assert_eq!(1, responder.0.len());
+        send(&mut context_map, "alice", &mut responder);
+        assert_eq!(2, context_map.shed_states());
+        assert_eq!(2, responder.0.len());
+    }
+
+    #[test]
+    fn test_given_context_map_with_memory_budget_when_it_is_exceeded_then_new_states_are_refused
+        () {
+        let mut responder = MockResponseSender::default();
+        let mut context_map = budgeted_context_map(Shedding::RefuseNew);
+        for _ in 0..4 {
+            send(&mut context_map, "alice", &mut responder);
+        }
+        assert_true!(context_map.is_shedding());
+        send(&mut context_map, "bob", &mut responder);
+        assert_eq!(4, context_map.usage().messages);
+        send(&mut context_map, "alice", &mut responder);
+        assert_eq!(5, context_map.usage().messages);
+        assert_eq!(0, responder.0.len());
+    }
+
+    #[test]
+    fn test_given_context_map_with_memory_budget_when_it_is_exceeded_then_states_only_count_messages
+        () {
+        let mut responder = MockResponseSender::default();
+        let mut context_map = budgeted_context_map(Shedding::CountOnly);
+        for _ in 0..4 {
+            send(&mut context_map, "alice", &mut responder);
+        }
+        assert_true!(context_map.is_shedding());
+        send(&mut context_map, "bob", &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        assert_eq!(5, context_map.usage().messages);
+        send(&mut context_map, "alice", &mut responder);
+        assert_eq!(2, context_map.usage().messages);
+        assert_false!(context_map.is_shedding());
+    }
+
+    #[test]
+    fn test_given_context_map_when_it_is_restored_from_a_snapshot_then_its_states_are_reattached
+        () {
+        let mut responder = MockResponseSender::default();
+        let uuid = Uuid::new_v4();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(uuid)]);
+        send(&mut context_map, "alice", &mut responder);
+        tick(&mut context_map, &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        let snapshot = context_map.snapshot();
+        assert_eq!(2, snapshot.contexts[0].states.len());
+
+        let json = to_string(&snapshot).expect("Failed to serialize a Snapshot");
+        let snapshot = from_str::<Snapshot>(&json).expect("Failed to deserialize a Snapshot");
+        let mut restored = ContextMap::from_configs(vec![map_context_config(uuid)]);
+        let orphans = restored.restore(snapshot.clone());
+        assert_true!(orphans.is_empty());
+        assert_eq!(snapshot, restored.snapshot());
+        assert_eq!(context_map.usage(), restored.usage());
+    }
+
+    #[test]
+    fn test_given_context_map_when_it_is_queried_then_we_get_the_states_of_every_context() {
+        let mut responder = MockResponseSender::default();
+        let uuid = Uuid::new_v4();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(uuid)]);
+        assert_false!(context_map.query()[0].open);
+        send(&mut context_map, "alice", &mut responder);
+        tick(&mut context_map, &mut responder);
+        send(&mut context_map, "alice", &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        let contexts = context_map.query();
+        assert_eq!(1, contexts.len());
+        assert_eq!(uuid, contexts[0].uuid);
+        assert_true!(contexts[0].open);
+        let alice = &contexts[0].states[0];
+        assert_eq!(vec![("user".to_owned(), Some("alice".to_owned()))], alice.key);
+        assert_eq!(2, alice.message_count);
+        assert_eq!(Duration::from_millis(10), alice.age);
+        assert_eq!(Duration::from_millis(0), alice.idle);
+        assert_eq!(1, contexts[0].states[1].message_count);
+    }
+
+    #[test]
+    fn test_given_context_map_when_a_key_is_closed_then_only_its_state_is_closed_with_its_actions() {
+        let mut responder = MockResponseSender::default();
+        let uuid = Uuid::new_v4();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(uuid)]);
+        send(&mut context_map, "alice", &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        let key = vec![("user".to_owned(), Some("alice".to_owned()))];
+        let closed = context_map.close_states(&Selector::Key(uuid, key), CloseMode::Execute, &mut responder);
+        assert_eq!(1, closed);
+        assert_eq!(1, responder.0.len());
+        assert_eq!(1, context_map.query()[0].states.len());
+        assert_eq!(1, context_map.usage().messages);
+    }
+
+    #[test]
+    fn test_given_context_map_when_states_are_discarded_then_their_actions_are_not_executed() {
+        let mut responder = MockResponseSender::default();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(Uuid::new_v4())]);
+        send(&mut context_map, "alice", &mut responder);
+        send(&mut context_map, "alice", &mut responder);
+        send(&mut context_map, "bob", &mut responder);
+        let selector = Selector::matching(|_, state| state.message_count > 1);
+        assert_eq!(1, context_map.close_states(&selector, CloseMode::Discard, &mut responder));
+        assert_eq!(0, responder.0.len());
+        assert_eq!(1, context_map.close_states(&Selector::All, CloseMode::Discard, &mut responder));
+        assert_true!(context_map.query()[0].states.is_empty());
+        assert_eq!(0, responder.0.len());
+    }
+
+    #[test]
+    fn test_given_context_map_when_it_is_reloaded_then_the_states_of_kept_contexts_are_moved() {
+        let mut responder = MockResponseSender::default();
+        let kept = Uuid::new_v4();
+        let removed = Uuid::new_v4();
+        let added = Uuid::new_v4();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(kept),
+                                                            map_context_config(removed)]);
+        send(&mut context_map, "alice", &mut responder);
+        let conditions = ConditionsBuilder::new(Duration::from_millis(1000)).build();
+        let action = MessageActionBuilder::new("RELOADED", "reloaded").build();
+        let changed = ContextConfigBuilder::new(kept, conditions)
+                          .context_id(Some(vec!["user".into()]))
+                          .actions(vec![action.into()])
+                          .build();
+        let report = context_map.reload(vec![changed, map_context_config(added)],
+                                        CloseMode::Execute,
+                                        &mut responder);
+        assert_eq!(vec![kept], report.kept);
+        assert_eq!(vec![removed], report.removed);
+        assert_eq!(vec![added], report.added);
+        assert_eq!(1, responder.0.len());
+        let contexts = context_map.query();
+        assert_eq!(1, contexts[0].states.len());
+        assert_eq!(1, contexts[0].states[0].message_count);
+        assert_eq!(1, context_map.usage().messages);
+        context_map.close_states(&Selector::Context(kept), CloseMode::Execute, &mut responder);
+        assert_eq!(2, responder.0.len());
+        if let Response::Alert(ref alert) = responder.0[1] {
+            assert_eq!("RELOADED", alert.message.uuid());
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_given_context_map_when_a_child_context_is_removed_by_reload_then_its_states_are_closed_by_mode() {
+        let mut responder = MockResponseSender::default();
+        let parent = Uuid::new_v4();
+        let conditions = ConditionsBuilder::new(Duration::from_millis(1000)).build();
+        let action = MessageActionBuilder::new("CHILD_CLOSED", "child closed").build();
+        let child = ContextConfigBuilder::new(Uuid::new_v4(), conditions)
+                        .patterns(vec!["LOGIN".to_owned()])
+                        .parent(Some(parent))
+                        .actions(vec![action.into()])
+                        .build();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(parent), child]);
+        send(&mut context_map, "alice", &mut responder);
+        assert_eq!(1, context_map.query()[0].states[0].children[0].states.len());
+        let report = context_map.reload(vec![map_context_config(parent)],
+                                        CloseMode::Execute,
+                                        &mut responder);
+        assert_eq!(vec![parent], report.kept);
+        assert_eq!(1, responder.0.len());
+        if let Response::Alert(ref alert) = responder.0[0] {
+            assert_eq!("CHILD_CLOSED", alert.message.uuid());
+        } else {
+            unreachable!();
+        }
+        let contexts = context_map.query();
+        assert_eq!(2, contexts[0].states[0].message_count);
+        assert_true!(contexts[0].states[0].children.is_empty());
+    }
+
+    #[test]
+    fn test_given_snapshot_when_its_context_is_not_found_then_it_is_returned_as_orphan() {
+        let mut responder = MockResponseSender::default();
+        let uuid = Uuid::new_v4();
+        let mut context_map = ContextMap::from_configs(vec![map_context_config(uuid)]);
+        send(&mut context_map, "alice", &mut responder);
+        let mut restored = ContextMap::from_configs(vec![map_context_config(Uuid::new_v4())]);
+        let orphans = restored.restore(context_map.snapshot());
+        assert_eq!(vec![uuid], orphans);
+        assert_true!(restored.snapshot().contexts.is_empty());
+    }
 }
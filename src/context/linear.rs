@@ -7,18 +7,27 @@
 // modified, or distributed except according to those terms.
 
 use uuid::Uuid;
+use std::mem;
 use std::sync::Arc;
+use std::time::Duration;
 
+use control::{CloseMode, CloseReason, Selector};
+use introspection::ContextInfo;
 use message::Message;
 use state::State;
-use timer::TimerEvent;
+use timer::{self, TimerEvent};
 use dispatcher::request::Request;
 use dispatcher::response::ResponseSender;
 use context::base::BaseContext;
+use context::budget::MemoryUsage;
+use context::map::map::ContextKey;
+use snapshot::ContextSnapshot;
 
 pub struct LinearContext {
     base: BaseContext,
     state: State,
+    admitting: bool,
+    now: Duration,
 }
 
 impl LinearContext {
@@ -26,6 +35,8 @@ impl LinearContext {
         LinearContext {
             base: base,
             state: State::new(),
+            admitting: true,
+            now: Duration::from_secs(0),
         }
     }
 
@@ -39,11 +50,141 @@ impl LinearContext {
     }
 
     pub fn on_timer(&mut self, event: &TimerEvent, responder: &mut ResponseSender) {
-        self.base.on_timer(event, &mut self.state, responder);
+        self.now = self.now + event.0;
+        let is_due = self.state.deadline().map_or(false, |deadline| deadline <= self.now);
+        if is_due {
+            self.base.on_timer(self.now, &mut self.state, responder);
+            self.schedule();
+        }
     }
 
     pub fn on_message(&mut self, event: Arc<Message>, responder: &mut ResponseSender) {
-        self.base.on_message(event, &mut self.state, responder);
+        if !self.admitting && !self.state.is_open() {
+            return;
+        }
+        self.base.on_message(event, &mut self.state, self.now, responder);
+        self.schedule();
+    }
+
+    fn schedule(&mut self) {
+        let deadline = self.base.deadline(&self.state, self.now);
+        self.state.set_deadline(deadline);
+    }
+
+    // The time left until the next timer event this context needs.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        timer::next_timeout(self.state.deadline(), self.now)
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.state.usage()
+    }
+
+    pub fn set_admitting(&mut self, admitting: bool) {
+        self.admitting = admitting;
+    }
+
+    pub fn set_retaining(&mut self, retaining: bool) {
+        self.state.set_retaining(retaining);
+    }
+
+    pub fn oldest_state_age(&self) -> Option<Duration> {
+        if self.state.is_open() {
+            Some(self.state.elapsed_time(self.now))
+        } else {
+            None
+        }
+    }
+
+    pub fn close_oldest_state(&mut self, responder: &mut ResponseSender) {
+        if self.state.is_open() {
+            self.base.close(&mut self.state, CloseReason::Evicted, responder);
+            self.schedule();
+        }
+    }
+
+    // Returns the number of closed states.
+    pub fn close_states(&mut self,
+                        selector: &Selector,
+                        mode: CloseMode,
+                        reason: CloseReason,
+                        responder: &mut ResponseSender)
+                        -> usize {
+        if self.state.is_open() &&
+           self.base.is_selected(selector, &ContextKey::new(), &self.state, self.now) {
+            self.base.close_with_mode(&mut self.state, mode, reason, responder);
+            self.schedule();
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn info(&self) -> ContextInfo {
+        let mut states = Vec::new();
+        if self.state.is_open() {
+            states.push(self.base.state_info(ContextKey::new(), &self.state, self.now));
+        }
+        self.base.info(states)
+    }
+
+    pub fn snapshot(&self) -> ContextSnapshot {
+        let mut states = Vec::new();
+        if self.state.is_open() {
+            states.push(self.base.snapshot_state(ContextKey::new(), &self.state, self.now));
+        }
+        ContextSnapshot {
+            uuid: *self.base.uuid(),
+            states: states,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: ContextSnapshot, orphans: &mut Vec<Uuid>) {
+        if snapshot.states.len() > 1 {
+            warn!("LinearContext: snapshot contains more than one state, only the first one is \
+                   restored; uuid={}",
+                  self.base.uuid());
+        }
+        if let Some(state) = snapshot.states.into_iter().next() {
+            self.state = self.base.restore_state(state, self.now, orphans);
+            self.schedule();
+        }
+    }
+
+    // Removes the open state, so it can be moved to another context by a
+    // reload.
+    pub fn take_states(&mut self) -> Vec<(ContextKey, State)> {
+        if self.state.is_open() {
+            vec![(ContextKey::new(), mem::replace(&mut self.state, State::new()))]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // The states are taken from old at old_now, see BaseContext::adopt_state().
+    // Only the first state is kept, the others are closed by mode.
+    pub fn adopt_states(&mut self,
+                        old: &BaseContext,
+                        states: Vec<(ContextKey, State)>,
+                        old_now: Duration,
+                        mode: CloseMode,
+                        responder: &mut ResponseSender) {
+        for (_, state) in states {
+            let mut state = self.base.adopt_state(old, state, old_now, self.now, mode, responder);
+            if self.state.is_open() {
+                warn!("LinearContext: more than one state is moved to the context, closing the \
+                       others; uuid={}",
+                      self.base.uuid());
+                self.base.close_with_mode(&mut state, mode, CloseReason::Requested, responder);
+            } else {
+                self.state = state;
+            }
+        }
+        self.schedule();
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
     }
 
     #[allow(dead_code)]
@@ -51,6 +192,14 @@ impl LinearContext {
         self.state.is_open()
     }
 
+    pub fn base(&self) -> &BaseContext {
+        &self.base
+    }
+
+    pub fn base_mut(&mut self) -> &mut BaseContext {
+        &mut self.base
+    }
+
     pub fn patterns(&self) -> &[String] {
         &self.base.patterns
     }
@@ -0,0 +1,160 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use super::{Case, KeyExpression, KeyExpressionBuilder, MissingPolicy};
+use serde::de::{Deserialize, Deserializer, Error, MapVisitor, Visitor};
+use regex::Regex;
+
+impl Deserialize for KeyExpression {
+    fn deserialize<D>(deserializer: &mut D) -> Result<KeyExpression, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize(KeyExpressionVisitor)
+    }
+}
+
+enum Field {
+    Name,
+    Field,
+    Concat,
+    Separator,
+    Regex,
+    Cidr,
+    Case,
+    Missing,
+    Default,
+}
+
+impl Deserialize for Field {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Field, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = Field;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<Field, E>
+                where E: Error
+            {
+                match value {
+                    "name" => Ok(Field::Name),
+                    "field" => Ok(Field::Field),
+                    "concat" => Ok(Field::Concat),
+                    "separator" => Ok(Field::Separator),
+                    "regex" => Ok(Field::Regex),
+                    "cidr" => Ok(Field::Cidr),
+                    "case" => Ok(Field::Case),
+                    "missing" => Ok(Field::Missing),
+                    "default" => Ok(Field::Default),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct KeyExpressionVisitor;
+
+impl Visitor for KeyExpressionVisitor {
+    type Value = KeyExpression;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<KeyExpression, E>
+        where E: Error
+    {
+        Ok(KeyExpression::from(value))
+    }
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<KeyExpression, V::Error>
+        where V: MapVisitor
+    {
+        let mut name: Option<String> = None;
+        let mut field: Option<String> = None;
+        let mut concat: Option<Vec<String>> = None;
+        let mut separator = String::new();
+        let mut regex: Option<String> = None;
+        let mut cidr: Option<u8> = None;
+        let mut case: Option<String> = None;
+        let mut missing: Option<String> = None;
+        let mut default: Option<String> = None;
+
+        while let Some(key) = try!(visitor.visit_key()) {
+            match key {
+                Field::Name => name = Some(try!(visitor.visit_value())),
+                Field::Field => field = Some(try!(visitor.visit_value())),
+                Field::Concat => concat = Some(try!(visitor.visit_value())),
+                Field::Separator => separator = try!(visitor.visit_value()),
+                Field::Regex => regex = Some(try!(visitor.visit_value())),
+                Field::Cidr => cidr = Some(try!(visitor.visit_value())),
+                Field::Case => case = Some(try!(visitor.visit_value())),
+                Field::Missing => missing = Some(try!(visitor.visit_value())),
+                Field::Default => default = Some(try!(visitor.visit_value())),
+            }
+        }
+
+        let mut builder = match (field, concat) {
+            (Some(field), None) => KeyExpressionBuilder::new(field),
+            (None, Some(fields)) => KeyExpressionBuilder::concat(fields, separator),
+            (Some(_), Some(_)) => {
+                return Err(V::Error::custom("Only one of 'field' and 'concat' can be used \
+                                             in a key expression"))
+            }
+            (None, None) => return visitor.missing_field("field"),
+        };
+
+        if let Some(name) = name {
+            builder = builder.name(name);
+        }
+        if let Some(regex) = regex {
+            let regex = try!(Regex::new(&regex).map_err(|error| {
+                V::Error::custom(format!("Invalid regex in key expression: {}", error))
+            }));
+            builder = builder.regex(regex);
+        }
+        if let Some(cidr) = cidr {
+            builder = builder.cidr(cidr);
+        }
+        if let Some(case) = case {
+            builder = builder.case(match &case[..] {
+                "lowercase" => Case::Lowercase,
+                "uppercase" => Case::Uppercase,
+                _ => return Err(V::Error::custom(format!("Invalid case: {}", case))),
+            });
+        }
+        let missing = match (missing, default) {
+            (None, None) => MissingPolicy::Empty,
+            (None, Some(default)) => MissingPolicy::Default(default),
+            (Some(ref policy), Some(ref default)) if policy == "default" => {
+                MissingPolicy::Default(default.clone())
+            }
+            (Some(ref policy), None) => {
+                match &policy[..] {
+                    "empty" => MissingPolicy::Empty,
+                    "skip" => MissingPolicy::Skip,
+                    "null" => MissingPolicy::Null,
+                    _ => {
+                        return Err(V::Error::custom(format!("Invalid missing key policy: {}",
+                                                            policy)))
+                    }
+                }
+            }
+            (Some(policy), Some(_)) => {
+                return Err(V::Error::custom(format!("'default' can't be used with missing \
+                                                     key policy: {}",
+                                                    policy)))
+            }
+        };
+        builder = builder.missing(missing);
+
+        try!(visitor.end());
+
+        Ok(builder.build())
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use regex::Regex;
+
+use message::Message;
+
+mod deser;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeySource {
+    Field(String),
+    Concat(Vec<String>, String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Case {
+    Lowercase,
+    Uppercase,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MissingPolicy {
+    // the value is an empty string, so these messages are grouped together
+    Empty,
+    // the message is not grouped into this context at all
+    Skip,
+    // the value is an explicit null, which can't collide with any real value
+    Null,
+    Default(String),
+}
+
+impl Default for MissingPolicy {
+    fn default() -> MissingPolicy {
+        MissingPolicy::Empty
+    }
+}
+
+// Derives one element of a context key from a message. The raw value is
+// looked up first, then the regex, CIDR and case transformations are applied
+// in this order. If any of these steps fails, the value is handled as missing.
+#[derive(Clone, Debug)]
+pub struct KeyExpression {
+    name: String,
+    source: KeySource,
+    regex: Option<Regex>,
+    cidr: Option<u8>,
+    case: Option<Case>,
+    missing: MissingPolicy,
+}
+
+pub enum KeyValue {
+    Value(Option<String>),
+    Skip,
+}
+
+impl KeyExpression {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    pub fn missing(&self) -> &MissingPolicy {
+        &self.missing
+    }
+
+    pub fn evaluate(&self, message: &Message) -> KeyValue {
+        match self.derive(message) {
+            Some(value) => KeyValue::Value(Some(value)),
+            None => {
+                match self.missing {
+                    MissingPolicy::Empty => KeyValue::Value(Some("".to_owned())),
+                    MissingPolicy::Skip => KeyValue::Skip,
+                    MissingPolicy::Null => KeyValue::Value(None),
+                    MissingPolicy::Default(ref value) => KeyValue::Value(Some(value.clone())),
+                }
+            }
+        }
+    }
+
+    fn derive(&self, message: &Message) -> Option<String> {
+        let mut value = match self.lookup(message) {
+            Some(value) => value,
+            None => return None,
+        };
+        if let Some(ref regex) = self.regex {
+            value = match KeyExpression::capture(regex, &value) {
+                Some(captured) => captured,
+                None => return None,
+            };
+        }
+        if let Some(prefix) = self.cidr {
+            value = match bucket_ip(&value, prefix) {
+                Some(network) => network,
+                None => return None,
+            };
+        }
+        match self.case {
+            Some(Case::Lowercase) => Some(value.to_lowercase()),
+            Some(Case::Uppercase) => Some(value.to_uppercase()),
+            None => Some(value),
+        }
+    }
+
+    fn lookup(&self, message: &Message) -> Option<String> {
+        match self.source {
+            KeySource::Field(ref field) => message.get(field).cloned(),
+            KeySource::Concat(ref fields, ref separator) => {
+                let mut values = Vec::new();
+                for field in fields {
+                    match message.get(field) {
+                        Some(value) => values.push(value.clone()),
+                        None => return None,
+                    }
+                }
+                Some(values.join(separator))
+            }
+        }
+    }
+
+    // the first capture group is used if there is one, the whole match otherwise
+    fn capture(regex: &Regex, value: &str) -> Option<String> {
+        regex.captures(value).and_then(|captures| {
+            captures.at(1).or_else(|| captures.at(0)).map(|captured| captured.to_owned())
+        })
+    }
+}
+
+impl PartialEq for KeyExpression {
+    fn eq(&self, other: &KeyExpression) -> bool {
+        self.name == other.name && self.source == other.source &&
+        self.regex.as_ref().map(|regex| regex.as_str()) ==
+        other.regex.as_ref().map(|regex| regex.as_str()) && self.cidr == other.cidr &&
+        self.case == other.case && self.missing == other.missing
+    }
+}
+
+impl<'a> From<&'a str> for KeyExpression {
+    fn from(field: &'a str) -> KeyExpression {
+        KeyExpressionBuilder::new(field).build()
+    }
+}
+
+impl From<String> for KeyExpression {
+    fn from(field: String) -> KeyExpression {
+        KeyExpressionBuilder::new(field).build()
+    }
+}
+
+fn bucket_ip(value: &str, prefix: u8) -> Option<String> {
+    match IpAddr::from_str(value) {
+        Ok(IpAddr::V4(address)) if prefix <= 32 => {
+            let bits = u32::from(address);
+            let mask = if prefix == 0 { 0 } else { !0u32 << (32 - prefix) };
+            Some(format!("{}/{}", Ipv4Addr::from(bits & mask), prefix))
+        }
+        Ok(IpAddr::V6(address)) if prefix <= 128 => {
+            let bits = u128::from(address);
+            let mask = if prefix == 0 { 0 } else { !0u128 << (128 - prefix) };
+            Some(format!("{}/{}", Ipv6Addr::from(bits & mask), prefix))
+        }
+        _ => None,
+    }
+}
+
+pub struct KeyExpressionBuilder {
+    name: Option<String>,
+    source: KeySource,
+    regex: Option<Regex>,
+    cidr: Option<u8>,
+    case: Option<Case>,
+    missing: MissingPolicy,
+}
+
+impl KeyExpressionBuilder {
+    pub fn new<S: Into<String>>(field: S) -> KeyExpressionBuilder {
+        KeyExpressionBuilder::from_source(KeySource::Field(field.into()))
+    }
+
+    pub fn concat<S: Into<String>>(fields: Vec<String>, separator: S) -> KeyExpressionBuilder {
+        KeyExpressionBuilder::from_source(KeySource::Concat(fields, separator.into()))
+    }
+
+    fn from_source(source: KeySource) -> KeyExpressionBuilder {
+        KeyExpressionBuilder {
+            name: None,
+            source: source,
+            regex: None,
+            cidr: None,
+            case: None,
+            missing: MissingPolicy::default(),
+        }
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> KeyExpressionBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn regex(mut self, regex: Regex) -> KeyExpressionBuilder {
+        self.regex = Some(regex);
+        self
+    }
+
+    pub fn cidr(mut self, prefix: u8) -> KeyExpressionBuilder {
+        self.cidr = Some(prefix);
+        self
+    }
+
+    pub fn case(mut self, case: Case) -> KeyExpressionBuilder {
+        self.case = Some(case);
+        self
+    }
+
+    pub fn missing(mut self, missing: MissingPolicy) -> KeyExpressionBuilder {
+        self.missing = missing;
+        self
+    }
+
+    pub fn build(self) -> KeyExpression {
+        let name = match self.name {
+            Some(name) => name,
+            None => {
+                match self.source {
+                    KeySource::Field(ref field) => field.clone(),
+                    KeySource::Concat(ref fields, ref separator) => fields.join(separator),
+                }
+            }
+        };
+        KeyExpression {
+            name: name,
+            source: self.source,
+            regex: self.regex,
+            cidr: self.cidr,
+            case: self.case,
+            missing: self.missing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Case, KeyExpression, KeyExpressionBuilder, KeyValue, MissingPolicy};
+    use message::MessageBuilder;
+    use regex::Regex;
+    use serde_json::from_str;
+
+    fn evaluate(expression: &KeyExpression, key: &str, value: &str) -> Option<Option<String>> {
+        let message = MessageBuilder::new("uuid", "message").pair(key, value).build();
+        match expression.evaluate(&message) {
+            KeyValue::Value(value) => Some(value),
+            KeyValue::Skip => None,
+        }
+    }
+
+    #[test]
+    fn test_given_key_expression_with_regex_when_it_matches_then_the_first_capture_group_is_used() {
+        let expression = KeyExpressionBuilder::new("email")
+                             .regex(Regex::new("@(.+)$").unwrap())
+                             .case(Case::Lowercase)
+                             .build();
+        assert_eq!(Some(Some("example.com".to_owned())),
+                   evaluate(&expression, "email", "linus@Example.COM"));
+        assert_eq!(Some(Some("".to_owned())),
+                   evaluate(&expression, "email", "linus"));
+    }
+
+    #[test]
+    fn test_given_key_expression_with_cidr_when_an_ip_is_evaluated_then_its_network_is_returned() {
+        let expression = KeyExpressionBuilder::new("ip").cidr(24).missing(MissingPolicy::Null).build();
+        assert_eq!(Some(Some("10.1.2.0/24".to_owned())),
+                   evaluate(&expression, "ip", "10.1.2.3"));
+        assert_eq!(Some(Some("2001:d00::/24".to_owned())),
+                   evaluate(&expression, "ip", "2001:db8::1"));
+        assert_eq!(Some(None), evaluate(&expression, "ip", "not an ip"));
+    }
+
+    #[test]
+    fn test_given_concatenating_key_expression_when_all_fields_are_present_then_they_are_joined() {
+        let message = MessageBuilder::new("uuid", "message")
+                          .pair("user", "linus")
+                          .pair("host", "kernel.org")
+                          .build();
+        let expression = KeyExpressionBuilder::concat(vec!["user".to_owned(), "host".to_owned()], "@")
+                             .missing(MissingPolicy::Skip)
+                             .build();
+        assert_eq!("user@host", expression.name());
+        match expression.evaluate(&message) {
+            KeyValue::Value(value) => assert_eq!(Some("linus@kernel.org".to_owned()), value),
+            KeyValue::Skip => unreachable!(),
+        }
+        assert_eq!(None, evaluate(&expression, "user", "linus"));
+    }
+
+    #[test]
+    fn test_given_key_expression_with_default_when_the_field_is_missing_then_the_default_is_used() {
+        let expression = KeyExpressionBuilder::new("user_name")
+                             .missing(MissingPolicy::Default("unknown".to_owned()))
+                             .build();
+        assert_eq!(Some(Some("unknown".to_owned())),
+                   evaluate(&expression, "other", "value"));
+    }
+
+    #[test]
+    fn test_given_key_expressions_in_json_when_they_are_deserialized_then_we_get_the_expected_result
+        () {
+        let json = r#"
+        [
+            "HOST",
+            { "name": "domain", "field": "email", "regex": "@(.+)$", "case": "lowercase" },
+            { "field": "src_ip", "cidr": 16, "missing": "null" },
+            { "concat": ["user", "host"], "separator": "@", "missing": "skip" },
+            { "field": "user_name", "default": "unknown" }
+        ]
+        "#;
+
+        let result = from_str::<Vec<KeyExpression>>(json);
+        println!("{:?}", &result);
+        let expressions = result.expect("Failed to deserialize valid key expressions");
+        let expected = vec![
+            KeyExpression::from("HOST"),
+            KeyExpressionBuilder::new("email")
+                .name("domain")
+                .regex(Regex::new("@(.+)$").unwrap())
+                .case(Case::Lowercase)
+                .build(),
+            KeyExpressionBuilder::new("src_ip").cidr(16).missing(MissingPolicy::Null).build(),
+            KeyExpressionBuilder::concat(vec!["user".to_owned(), "host".to_owned()], "@")
+                .missing(MissingPolicy::Skip)
+                .build(),
+            KeyExpressionBuilder::new("user_name")
+                .missing(MissingPolicy::Default("unknown".to_owned()))
+                .build(),
+        ];
+        assert_eq!(expected, expressions);
+    }
+
+    #[test]
+    fn test_given_key_expression_in_json_when_its_regex_is_invalid_then_we_get_error() {
+        let json = r#"{ "field": "email", "regex": "(" }"#;
+        let result = from_str::<KeyExpression>(json);
+        let _ = result.err().expect("Successfully deserialized a key expression with invalid regex");
+    }
+
+    #[test]
+    fn test_given_key_expression_in_json_when_it_has_no_source_then_we_get_error() {
+        let json = r#"{ "name": "domain" }"#;
+        let result = from_str::<KeyExpression>(json);
+        let _ = result.err().expect("Successfully deserialized a key expression without a field");
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use super::{EvictionPolicy, KeyLimit};
+use serde::de::{Deserialize, Deserializer, Error, MapVisitor, Visitor};
+
+impl Deserialize for EvictionPolicy {
+    fn deserialize<D>(deserializer: &mut D) -> Result<EvictionPolicy, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = EvictionPolicy;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<EvictionPolicy, E>
+                where E: Error
+            {
+                match value {
+                    "lru" => Ok(EvictionPolicy::LeastRecentlyUsed),
+                    "oldest" => Ok(EvictionPolicy::Oldest),
+                    "refuse" => Ok(EvictionPolicy::Refuse),
+                    _ => Err(E::custom(format!("Unexpected eviction policy: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+impl Deserialize for KeyLimit {
+    fn deserialize<D>(deserializer: &mut D) -> Result<KeyLimit, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("KeyLimit", &[], KeyLimitVisitor)
+    }
+}
+
+enum Field {
+    MaxKeys,
+    Eviction,
+    Actions,
+}
+
+impl Deserialize for Field {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Field, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = Field;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<Field, E>
+                where E: Error
+            {
+                match value {
+                    "max_keys" => Ok(Field::MaxKeys),
+                    "eviction" => Ok(Field::Eviction),
+                    "actions" => Ok(Field::Actions),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct KeyLimitVisitor;
+
+impl Visitor for KeyLimitVisitor {
+    type Value = KeyLimit;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<KeyLimit, V::Error>
+        where V: MapVisitor
+    {
+        let mut max_keys = None;
+        let mut eviction = EvictionPolicy::default();
+        let mut actions = Vec::new();
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                Field::MaxKeys => max_keys = Some(try!(visitor.visit_value())),
+                Field::Eviction => eviction = try!(visitor.visit_value()),
+                Field::Actions => actions = try!(visitor.visit_value()),
+            }
+        }
+
+        let max_keys = match max_keys {
+            Some(max_keys) => max_keys,
+            None => return visitor.missing_field("max_keys"),
+        };
+
+        try!(visitor.end());
+
+        Ok(KeyLimit::new(max_keys).eviction(eviction).actions(actions))
+    }
+}
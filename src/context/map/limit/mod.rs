@@ -0,0 +1,148 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::collections::BTreeMap;
+
+use config::action::ActionType;
+use context::map::map::ContextKey;
+
+mod deser;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    LeastRecentlyUsed,
+    Oldest,
+    Refuse,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> EvictionPolicy {
+        EvictionPolicy::LeastRecentlyUsed
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyLimit {
+    pub max_keys: usize,
+    pub eviction: EvictionPolicy,
+    pub actions: Vec<ActionType>,
+}
+
+impl KeyLimit {
+    pub fn new(max_keys: usize) -> KeyLimit {
+        KeyLimit {
+            max_keys: max_keys,
+            eviction: EvictionPolicy::default(),
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn eviction(mut self, eviction: EvictionPolicy) -> KeyLimit {
+        self.eviction = eviction;
+        self
+    }
+
+    pub fn actions(mut self, actions: Vec<ActionType>) -> KeyLimit {
+        self.actions = actions;
+        self
+    }
+}
+
+// Keeps the keys ordered by their last use (or creation), so the next key to
+// evict can be found without walking all the states.
+#[derive(Default)]
+pub struct KeyTracker {
+    order: BTreeMap<u64, ContextKey>,
+    positions: BTreeMap<ContextKey, u64>,
+    next: u64,
+}
+
+impl KeyTracker {
+    pub fn new() -> KeyTracker {
+        KeyTracker::default()
+    }
+
+    pub fn touch(&mut self, key: &ContextKey, policy: EvictionPolicy) {
+        if let Some(position) = self.positions.get(key).cloned() {
+            if policy != EvictionPolicy::LeastRecentlyUsed {
+                return;
+            }
+            let _ = self.order.remove(&position);
+        }
+        self.order.insert(self.next, key.clone());
+        self.positions.insert(key.clone(), self.next);
+        self.next += 1;
+    }
+
+    pub fn remove(&mut self, key: &ContextKey) {
+        if let Some(position) = self.positions.remove(key) {
+            let _ = self.order.remove(&position);
+        }
+    }
+
+    pub fn candidate(&self) -> Option<&ContextKey> {
+        self.order.values().next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EvictionPolicy, KeyLimit, KeyTracker};
+    use context::map::map::ContextKey;
+    use serde_json::from_str;
+
+    fn key(value: &str) -> ContextKey {
+        vec![("user_name".to_owned(), Some(value.to_owned()))]
+    }
+
+    #[test]
+    fn test_given_key_tracker_when_keys_are_used_again_then_lru_moves_them_to_the_back() {
+        let mut tracker = KeyTracker::new();
+        tracker.touch(&key("a"), EvictionPolicy::LeastRecentlyUsed);
+        tracker.touch(&key("b"), EvictionPolicy::LeastRecentlyUsed);
+        tracker.touch(&key("a"), EvictionPolicy::LeastRecentlyUsed);
+        assert_eq!(Some(&key("b")), tracker.candidate());
+        tracker.remove(&key("b"));
+        assert_eq!(Some(&key("a")), tracker.candidate());
+    }
+
+    #[test]
+    fn test_given_key_tracker_when_keys_are_used_again_then_the_oldest_is_kept_in_front() {
+        let mut tracker = KeyTracker::new();
+        tracker.touch(&key("a"), EvictionPolicy::Oldest);
+        tracker.touch(&key("b"), EvictionPolicy::Oldest);
+        tracker.touch(&key("a"), EvictionPolicy::Oldest);
+        assert_eq!(Some(&key("a")), tracker.candidate());
+    }
+
+    #[test]
+    fn test_given_key_limit_in_json_when_it_is_deserialized_then_we_get_the_expected_result() {
+        let json = r#"
+        {
+            "max_keys": 1000,
+            "eviction": "oldest",
+            "actions": [
+                { "message": { "uuid": "EVICTED", "message": "key evicted" } }
+            ]
+        }
+        "#;
+
+        let result = from_str::<KeyLimit>(json);
+        let limit = result.ok().expect("Failed to deserialize a valid KeyLimit");
+        assert_eq!(1000, limit.max_keys);
+        assert_eq!(EvictionPolicy::Oldest, limit.eviction);
+        assert_eq!(1, limit.actions.len());
+    }
+
+    #[test]
+    fn test_given_key_limit_in_json_when_the_eviction_policy_is_invalid_then_we_get_error() {
+        let json = r#"{ "max_keys": 1000, "eviction": "random" }"#;
+        let result = from_str::<KeyLimit>(json);
+        let _ = result.err().expect("Successfully deserialized an invalid eviction policy");
+    }
+}
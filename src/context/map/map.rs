@@ -6,33 +6,108 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
 use std::sync::Arc;
+use std::time::Duration;
 
+use uuid::Uuid;
+
+use action::Action;
+use control::{CloseMode, CloseReason, Selector};
+use introspection::ContextInfo;
 use message::Message;
+use snapshot::ContextSnapshot;
 use state::State;
-use timer::TimerEvent;
+use timer::{self, TimerEvent};
 use context::base::BaseContext;
+use context::budget::MemoryUsage;
+use context::shard::Shard;
+use context::map::key::{KeyExpression, KeyValue};
+use context::map::limit::{EvictionPolicy, KeyLimit, KeyTracker};
 use dispatcher::request::Request;
 use dispatcher::response::ResponseSender;
 
-pub type ContextKey = Vec<(String, String)>;
+pub type ContextKey = Vec<(String, Option<String>)>;
+
+// Returns None if the message should not be grouped into the context.
+pub fn context_key(context_id: &[KeyExpression], message: &Message) -> Option<ContextKey> {
+    let mut key = ContextKey::new();
+    for expression in context_id {
+        match expression.evaluate(message) {
+            KeyValue::Value(value) => key.push((expression.name().to_owned(), value)),
+            KeyValue::Skip => return None,
+        }
+    }
+    Some(key)
+}
 
+// The states are indexed by their deadlines, so a timer event visits only
+// the states which may be closed by it, and by the time they were opened at,
+// so the oldest state is found without visiting the others.
 pub struct MapContext {
     base: BaseContext,
     map: BTreeMap<ContextKey, State>,
-    context_id: Vec<String>,
+    deadlines: BTreeSet<(Duration, ContextKey)>,
+    ages: BTreeSet<(Duration, ContextKey)>,
+    now: Duration,
+    context_id: Vec<KeyExpression>,
+    key_limit: Option<KeyLimit>,
+    tracker: KeyTracker,
+    evictions: u64,
+    refused_keys: u64,
+    usage: MemoryUsage,
+    admitting: bool,
+    retaining: bool,
+    shard: Option<Shard>,
 }
 
 impl MapContext {
-    pub fn new(base: BaseContext, context_id: Vec<String>) -> MapContext {
+    pub fn new(base: BaseContext, context_id: Vec<KeyExpression>) -> MapContext {
         MapContext {
             base: base,
             map: BTreeMap::new(),
+            deadlines: BTreeSet::new(),
+            ages: BTreeSet::new(),
+            now: Duration::from_secs(0),
             context_id: context_id,
+            key_limit: None,
+            tracker: KeyTracker::new(),
+            evictions: 0,
+            refused_keys: 0,
+            usage: MemoryUsage::default(),
+            admitting: true,
+            retaining: true,
+            shard: None,
         }
     }
 
+    pub fn key_limit(mut self, key_limit: Option<KeyLimit>) -> MapContext {
+        self.key_limit = key_limit;
+        self
+    }
+
+    // The messages whose keys are owned by another shard are skipped.
+    pub fn set_shard(&mut self, shard: Option<Shard>) {
+        self.shard = shard;
+    }
+
+    pub fn context_id(&self) -> &[KeyExpression] {
+        &self.context_id
+    }
+
+    fn owns(&self, key: &ContextKey) -> bool {
+        self.shard.map_or(true, |shard| shard.owns_key(key))
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn refused_keys(&self) -> u64 {
+        self.refused_keys
+    }
+
     pub fn on_event(&mut self, event: Request, responder: &mut ResponseSender) {
         trace!("MapContext: received event");
         match event {
@@ -42,44 +117,276 @@ impl MapContext {
         }
     }
 
-    #[allow(for_kv_map)]
     pub fn on_timer(&mut self, event: &TimerEvent, responder: &mut ResponseSender) {
-        for (_, mut state) in &mut self.map {
-            self.base.on_timer(event, &mut state, responder);
+        self.now = self.now + event.0;
+        let keys = self.deadlines
+                       .iter()
+                       .take_while(|&&(deadline, _)| deadline <= self.now)
+                       .map(|&(_, ref key)| key.clone())
+                       .collect::<Vec<ContextKey>>();
+        for key in keys {
+            self.on_state_timer(&key, responder);
         }
-        self.remove_closed_states();
     }
 
-    fn get_closed_state_ids(&self) -> Vec<ContextKey> {
-        self.map
-            .iter()
-            .filter_map(|(id, state)| {
-                if state.is_open() {
-                    None
-                } else {
-                    Some(id.clone())
+    fn on_state_timer(&mut self, key: &ContextKey, responder: &mut ResponseSender) {
+        let is_open = match self.map.get_mut(key) {
+            Some(state) => {
+                let before = state.usage();
+                self.base.on_timer(self.now, state, responder);
+                self.usage = self.usage - before + state.usage();
+                state.is_open()
+            }
+            None => return,
+        };
+        if is_open {
+            self.reschedule(key);
+        } else {
+            let _ = self.remove_state(key);
+        }
+    }
+
+    fn reschedule(&mut self, key: &ContextKey) {
+        if let Some(state) = self.map.get_mut(key) {
+            // The time is constant while the state is open, it changes only
+            // if the state was reset.
+            let opened_at = Some(self.now
+                                     .checked_sub(state.elapsed_time(self.now))
+                                     .unwrap_or_else(|| Duration::from_secs(0)));
+            if opened_at != state.opened_at() {
+                if let Some(previous) = state.opened_at() {
+                    self.ages.remove(&(previous, key.clone()));
                 }
-            })
-            .collect::<Vec<ContextKey>>()
+                if let Some(opened_at) = opened_at {
+                    self.ages.insert((opened_at, key.clone()));
+                }
+                state.set_opened_at(opened_at);
+            }
+            let deadline = self.base.deadline(state, self.now);
+            if deadline == state.deadline() {
+                return;
+            }
+            if let Some(previous) = state.deadline() {
+                self.deadlines.remove(&(previous, key.clone()));
+            }
+            if let Some(deadline) = deadline {
+                self.deadlines.insert((deadline, key.clone()));
+            }
+            state.set_deadline(deadline);
+        }
     }
 
-    fn remove_closed_states(&mut self) {
-        for id in self.get_closed_state_ids() {
-            let _ = self.map.remove(&id);
+    fn remove_state(&mut self, key: &ContextKey) -> Option<State> {
+        let state = self.map.remove(key);
+        if let Some(ref state) = state {
+            if let Some(deadline) = state.deadline() {
+                self.deadlines.remove(&(deadline, key.clone()));
+            }
+            if let Some(opened_at) = state.opened_at() {
+                self.ages.remove(&(opened_at, key.clone()));
+            }
+            self.tracker.remove(key);
+            self.usage = self.usage - state.usage();
+        }
+        state
+    }
+
+    // Returns false if the new key can't be inserted.
+    fn make_room_for_new_key(&mut self, responder: &mut ResponseSender) -> bool {
+        let eviction = match self.key_limit {
+            Some(ref limit) if self.map.len() >= limit.max_keys => limit.eviction,
+            _ => return true,
+        };
+        if eviction == EvictionPolicy::Refuse {
+            trace!("MapContext: key limit is reached, refusing new key; uuid={}",
+                   self.base.uuid());
+            self.refused_keys += 1;
+            return false;
+        }
+        match self.tracker.candidate().cloned() {
+            Some(key) => {
+                self.evict(&key, responder);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict(&mut self, key: &ContextKey, responder: &mut ResponseSender) {
+        trace!("MapContext: key limit is reached, evicting key; uuid={}", self.base.uuid());
+        if let Some(mut state) = self.remove_state(key) {
+            if let Some(ref limit) = self.key_limit {
+                for i in &limit.actions {
+                    i.on_closed(&state, &self.base, responder);
+                }
+            }
+            if state.is_open() {
+                self.base.close(&mut state, CloseReason::Evicted, responder);
+            }
+            self.evictions += 1;
         }
     }
 
     pub fn on_message(&mut self, event: Arc<Message>, responder: &mut ResponseSender) {
-        self.update_state(event, responder);
-        self.remove_closed_states();
+        let key = match context_key(&self.context_id, &event) {
+            Some(key) => key,
+            None => {
+                trace!("MapContext: message is skipped because of a missing key");
+                return;
+            }
+        };
+        if !self.owns(&key) {
+            return;
+        }
+        if !self.map.contains_key(&key) {
+            if !(self.admitting && self.base.is_subscribed(&event) && self.base.is_opening(&event)) {
+                return;
+            }
+            if !self.make_room_for_new_key(responder) {
+                return;
+            }
+        }
+        if let Some(ref limit) = self.key_limit {
+            self.tracker.touch(&key, limit.eviction);
+        }
+        let is_open = {
+            let mut state = self.map.entry(key.clone()).or_insert_with(State::new);
+            let before = state.usage();
+            state.set_retaining(self.retaining);
+            self.base.on_message(event, &mut state, self.now, responder);
+            self.usage = self.usage - before + state.usage();
+            state.is_open()
+        };
+        if is_open {
+            self.reschedule(&key);
+        } else {
+            let _ = self.remove_state(&key);
+        }
     }
 
-    fn update_state(&mut self, event: Arc<Message>, responder: &mut ResponseSender) {
-        let key: ContextKey = self.context_id.iter().map(|key| {
-                (key.to_owned(), event.get(&key).map_or_else(|| "".to_owned(), |value| value.to_owned()))
-            }).collect();
-        let mut state = self.map.entry(key).or_insert_with(State::new);
-        self.base.on_message(event, &mut state, responder);
+    // The time left until the next timer event this context needs.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        timer::next_timeout(self.deadlines.iter().next().map(|&(deadline, _)| deadline),
+                            self.now)
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    pub fn set_admitting(&mut self, admitting: bool) {
+        self.admitting = admitting;
+    }
+
+    pub fn set_retaining(&mut self, retaining: bool) {
+        self.retaining = retaining;
+    }
+
+    pub fn oldest_state_age(&self) -> Option<Duration> {
+        self.ages
+            .iter()
+            .next()
+            .and_then(|&(_, ref key)| self.map.get(key))
+            .map(|state| state.elapsed_time(self.now))
+    }
+
+    pub fn close_oldest_state(&mut self, responder: &mut ResponseSender) {
+        let oldest = self.ages.iter().next().map(|&(_, ref key)| key.clone());
+        if let Some(key) = oldest {
+            if let Some(mut state) = self.remove_state(&key) {
+                self.base.close(&mut state, CloseReason::Evicted, responder);
+            }
+        }
+    }
+
+    // Returns the number of closed states.
+    pub fn close_states(&mut self,
+                        selector: &Selector,
+                        mode: CloseMode,
+                        reason: CloseReason,
+                        responder: &mut ResponseSender)
+                        -> usize {
+        let selected = self.map
+                           .iter()
+                           .filter(|&(key, state)| self.base.is_selected(selector, key, state, self.now))
+                           .map(|(key, _)| key.clone())
+                           .collect::<Vec<ContextKey>>();
+        for key in &selected {
+            if let Some(mut state) = self.remove_state(key) {
+                self.base.close_with_mode(&mut state, mode, reason, responder);
+            }
+        }
+        selected.len()
+    }
+
+    pub fn info(&self) -> ContextInfo {
+        self.base.info(self.map
+                           .iter()
+                           .map(|(key, state)| self.base.state_info(key.clone(), state, self.now))
+                           .collect())
+    }
+
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            uuid: *self.base.uuid(),
+            states: self.map
+                        .iter()
+                        .map(|(key, state)| self.base.snapshot_state(key.clone(), state, self.now))
+                        .collect(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: ContextSnapshot, orphans: &mut Vec<Uuid>) {
+        for i in snapshot.states {
+            if !self.owns(&i.key) {
+                continue;
+            }
+            let key = i.key.clone();
+            let state = self.base.restore_state(i, self.now, orphans);
+            let _ = self.remove_state(&key);
+            if let Some(ref limit) = self.key_limit {
+                self.tracker.touch(&key, limit.eviction);
+            }
+            self.usage = self.usage + state.usage();
+            self.map.insert(key.clone(), state);
+            self.reschedule(&key);
+        }
+    }
+
+    // Removes the open states, so they can be moved to another context by a
+    // reload.
+    pub fn take_states(&mut self) -> Vec<(ContextKey, State)> {
+        self.deadlines.clear();
+        self.ages.clear();
+        self.tracker = KeyTracker::new();
+        self.usage = MemoryUsage::default();
+        mem::replace(&mut self.map, BTreeMap::new()).into_iter().collect()
+    }
+
+    // The states are taken from old at old_now, see BaseContext::adopt_state().
+    pub fn adopt_states(&mut self,
+                        old: &BaseContext,
+                        states: Vec<(ContextKey, State)>,
+                        old_now: Duration,
+                        mode: CloseMode,
+                        responder: &mut ResponseSender) {
+        for (key, state) in states {
+            if !self.owns(&key) {
+                continue;
+            }
+            let state = self.base.adopt_state(old, state, old_now, self.now, mode, responder);
+            let _ = self.remove_state(&key);
+            if let Some(ref limit) = self.key_limit {
+                self.tracker.touch(&key, limit.eviction);
+            }
+            self.usage = self.usage + state.usage();
+            self.map.insert(key.clone(), state);
+            self.reschedule(&key);
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
     }
 
     #[allow(dead_code)]
@@ -87,6 +394,14 @@ impl MapContext {
         !self.map.is_empty()
     }
 
+    pub fn base(&self) -> &BaseContext {
+        &self.base
+    }
+
+    pub fn base_mut(&mut self) -> &mut BaseContext {
+        &mut self.base
+    }
+
     pub fn patterns(&self) -> &[String] {
         &self.base.patterns
     }
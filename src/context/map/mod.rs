@@ -6,8 +6,12 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+pub use self::key::KeyExpression;
+pub use self::limit::KeyLimit;
 pub use self::map::MapContext;
 
+pub mod key;
+pub mod limit;
 pub mod map;
 #[cfg(test)]
 mod test;
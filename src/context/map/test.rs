@@ -8,8 +8,15 @@
 
 use conditions::ConditionsBuilder;
 use context::{BaseContextBuilder, MapContext};
+use context::map::KeyExpression;
+use context::map::key::{Case, KeyExpressionBuilder, MissingPolicy};
+use context::map::limit::{EvictionPolicy, KeyLimit};
+use config::action::message::MessageActionBuilder;
+use dispatcher::Response;
+use message::Message;
 use timer::TimerEvent;
 use message::MessageBuilder;
+use schedule::{ScheduleBuilder, TimeSource, TimeWindow};
 use test_utils::MockResponseSender;
 
 use uuid::Uuid;
@@ -36,7 +43,7 @@ fn test_given_map_context_when_messages_have_the_same_kvpairs_then_they_go_to_th
             let conditions = ConditionsBuilder::new(timeout).build();
             BaseContextBuilder::new(uuid, conditions).patterns(patterns).build()
         };
-        let context_key = ["HOST", "PROGRAM", "PID"].iter().map(|x| (*x).to_owned()).collect::<Vec<String>>();
+        let context_key = ["HOST", "PROGRAM", "PID"].iter().map(|x| KeyExpression::from(*x)).collect::<Vec<KeyExpression>>();
         MapContext::new(base_context, context_key)
     };
     let msg1 = MessageBuilder::new(&msg_id1, "message")
@@ -67,3 +74,169 @@ fn test_given_map_context_when_messages_have_the_same_kvpairs_then_they_go_to_th
     context.on_timer(&event, &mut responder);
     assert_false!(context.is_open());
 }
+
+#[test]
+fn test_given_map_context_when_a_message_misses_a_skipped_key_then_it_is_not_grouped() {
+    let mut responder = MockResponseSender::default();
+    let msg_id = "11eaf6f8-0640-460f-aee2-a72d2f2ab258";
+    let mut context = {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(30)).build();
+        let base_context = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                               .patterns(vec![msg_id.to_owned()])
+                               .build();
+        let context_id = vec![KeyExpressionBuilder::new("user_name")
+                                  .case(Case::Lowercase)
+                                  .missing(MissingPolicy::Skip)
+                                  .build()];
+        MapContext::new(base_context, context_id)
+    };
+    let anonymous = MessageBuilder::new(msg_id, "message").build();
+    let linus = MessageBuilder::new(msg_id, "message").pair("user_name", "Linus").build();
+
+    context.on_message(Arc::new(anonymous), &mut responder);
+    assert_false!(context.is_open());
+    context.on_message(Arc::new(linus), &mut responder);
+    assert_true!(context.is_open());
+}
+
+fn limited_context(eviction: EvictionPolicy) -> MapContext {
+    let conditions = ConditionsBuilder::new(Duration::from_millis(30)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let evicted_action = MessageActionBuilder::new("EVICTED", "evicted").build();
+    let base_context = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                           .actions(vec![action.into()])
+                           .build();
+    let limit = KeyLimit::new(2).eviction(eviction).actions(vec![evicted_action.into()]);
+    MapContext::new(base_context, vec!["user_name".into()]).key_limit(Some(limit))
+}
+
+fn user_message(user_name: &str) -> Arc<Message> {
+    Arc::new(MessageBuilder::new("LOGIN", "message").pair("user_name", user_name).build())
+}
+
+fn alert_uuids(responder: &MockResponseSender) -> Vec<String> {
+    responder.0
+             .iter()
+             .map(|response| {
+                 match *response {
+                     Response::Alert(ref alert) => alert.message.uuid().clone(),
+                     _ => unreachable!(),
+                 }
+             })
+             .collect()
+}
+
+#[test]
+fn test_given_map_context_with_lru_key_limit_when_a_new_key_arrives_then_the_least_recently_used_is_evicted
+    () {
+    let mut responder = MockResponseSender::default();
+    let mut context = limited_context(EvictionPolicy::LeastRecentlyUsed);
+    context.on_message(user_message("a"), &mut responder);
+    context.on_message(user_message("b"), &mut responder);
+    context.on_message(user_message("a"), &mut responder);
+    context.on_message(user_message("c"), &mut responder);
+    assert_eq!(vec!["EVICTED", "CLOSED"], alert_uuids(&responder));
+    assert_eq!(1, context.evictions());
+    context.on_message(user_message("a"), &mut responder);
+    assert_eq!(1, context.evictions());
+}
+
+#[test]
+fn test_given_map_context_with_refusing_key_limit_when_a_new_key_arrives_then_it_is_ignored() {
+    let mut responder = MockResponseSender::default();
+    let mut context = limited_context(EvictionPolicy::Refuse);
+    context.on_message(user_message("a"), &mut responder);
+    context.on_message(user_message("b"), &mut responder);
+    context.on_message(user_message("c"), &mut responder);
+    assert_true!(responder.0.is_empty());
+    assert_eq!(0, context.evictions());
+    assert_eq!(1, context.refused_keys());
+    context.on_timer(&TimerEvent::from_millis(30), &mut responder);
+    assert_eq!(vec!["CLOSED", "CLOSED"], alert_uuids(&responder));
+    context.on_message(user_message("c"), &mut responder);
+    assert_true!(context.is_open());
+}
+
+#[test]
+fn test_given_map_context_when_its_states_have_deadlines_then_the_next_timeout_is_the_earliest_one() {
+    let mut responder = MockResponseSender::default();
+    let conditions = ConditionsBuilder::new(Duration::from_millis(100))
+                         .renew_timeout(Duration::from_millis(50))
+                         .build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let base_context = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                           .actions(vec![action.into()])
+                           .build();
+    let mut context = MapContext::new(base_context, vec!["user_name".into()]);
+    assert_eq!(None, context.next_timeout());
+    context.on_message(user_message("a"), &mut responder);
+    assert_eq!(Some(Duration::from_millis(50)), context.next_timeout());
+    context.on_timer(&TimerEvent::from_millis(40), &mut responder);
+    context.on_message(user_message("b"), &mut responder);
+    assert_eq!(Some(Duration::from_millis(10)), context.next_timeout());
+    context.on_message(user_message("a"), &mut responder);
+    assert_eq!(Some(Duration::from_millis(50)), context.next_timeout());
+    context.on_timer(&TimerEvent::from_millis(49), &mut responder);
+    assert_true!(responder.0.is_empty());
+    assert_eq!(Some(Duration::from_millis(1)), context.next_timeout());
+    context.on_timer(&TimerEvent::from_millis(1), &mut responder);
+    assert_eq!(vec!["CLOSED", "CLOSED"], alert_uuids(&responder));
+    assert_false!(context.is_open());
+    assert_eq!(None, context.next_timeout());
+}
+
+#[test]
+fn test_given_map_context_closing_on_the_window_end_when_a_state_is_open_then_the_window_end_is_its_deadline
+    () {
+    let mut responder = MockResponseSender::default();
+    // 2016-06-06 05:59:00 UTC
+    let timestamp = 1465171200 + 5 * 3600 + 59 * 60;
+    let conditions = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+    let schedule = ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 6 * 60)])
+                       .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                       .close_on_end(true)
+                       .build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let base_context = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                           .actions(vec![action.into()])
+                           .active(Some(schedule))
+                           .build();
+    let mut context = MapContext::new(base_context, vec!["user_name".into()]);
+    let message = MessageBuilder::new("uuid", "message")
+                      .pair("user_name", "a")
+                      .pair("TIMESTAMP", &timestamp.to_string())
+                      .build();
+    context.on_message(Arc::new(message), &mut responder);
+    assert_true!(context.is_open());
+    assert_eq!(Some(Duration::from_secs(60)), context.next_timeout());
+    context.on_timer(&TimerEvent::from_millis(59 * 1000), &mut responder);
+    assert_true!(responder.0.is_empty());
+    context.on_timer(&TimerEvent::from_millis(1000), &mut responder);
+    assert_eq!(vec!["CLOSED"], alert_uuids(&responder));
+    assert_false!(context.is_open());
+}
+
+#[test]
+fn test_given_map_context_when_its_oldest_state_is_closed_then_the_first_opened_state_is_closed() {
+    let mut responder = MockResponseSender::default();
+    let mut context = {
+        let conditions = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+        let base_context = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                               .patterns(vec!["LOGIN".to_owned()])
+                               .build();
+        MapContext::new(base_context, vec!["user".into()])
+    };
+    let login = |user: &str| Arc::new(MessageBuilder::new("LOGIN", "login").pair("user", user).build());
+    let step = TimerEvent(Duration::from_secs(1));
+    context.on_message(login("bob"), &mut responder);
+    context.on_timer(&step, &mut responder);
+    context.on_message(login("alice"), &mut responder);
+    context.on_timer(&step, &mut responder);
+    context.on_message(login("bob"), &mut responder);
+    assert_eq!(Some(Duration::from_secs(2)), context.oldest_state_age());
+    context.close_oldest_state(&mut responder);
+    let info = context.info();
+    assert_eq!(1, info.states.len());
+    assert_eq!(Some("alice".to_owned()), info.states[0].key[0].1);
+    assert_eq!(Some(Duration::from_secs(1)), context.oldest_state_age());
+}
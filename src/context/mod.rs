@@ -6,20 +6,38 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use clock::Clock;
 use dispatcher::request::Request;
 use dispatcher::response::ResponseSender;
 use config::ContextConfig;
+use context::budget::MemoryUsage;
+use context::map::{KeyExpression, KeyLimit};
+use context::map::map::context_key;
+use context::shard::Shard;
+use control::{CloseMode, CloseReason, Selector};
+use introspection::ContextInfo;
+use message::Message;
+use snapshot::ContextSnapshot;
 
 pub use self::linear::LinearContext;
 pub use self::map::MapContext;
 pub use self::base::BaseContext;
 pub use self::base::BaseContextBuilder;
-pub use self::context_map::ContextMap;
+pub use self::child::ChildContext;
+pub use self::context_map::{ContextMap, ReloadReport};
 
 pub mod base;
+pub mod budget;
+pub mod child;
 pub mod context_map;
 pub mod linear;
 pub mod map;
+pub mod shard;
 #[cfg(test)]
 mod test;
 
@@ -36,28 +54,200 @@ impl Context {
         }
     }
 
+    pub fn uuid(&self) -> &Uuid {
+        match *self {
+            Context::Linear(ref context) => context.base().uuid(),
+            Context::Map(ref context) => context.base().uuid(),
+        }
+    }
+
     pub fn patterns(&self) -> &[String] {
         match *self {
             Context::Linear(ref context) => context.patterns(),
             Context::Map(ref context) => context.patterns(),
         }
     }
-}
 
-impl From<ContextConfig> for Context {
-    fn from(config: ContextConfig) -> Context {
-        let ContextConfig {name, uuid, conditions, context_id, actions, patterns} = config;
+    pub fn subscriptions(&self) -> Vec<String> {
+        match *self {
+            Context::Linear(ref context) => context.base().subscriptions(),
+            Context::Map(ref context) => context.base().subscriptions(),
+        }
+    }
 
-        let base = BaseContextBuilder::new(uuid, conditions);
-        let base = base.name(name);
-        let base = base.patterns(patterns);
-        let base = base.actions(actions);
-        let base = base.build();
+    pub fn with_children(config: ContextConfig, children: Vec<ChildContext>) -> Context {
+        let (base, context_id, key_limit) = Context::split_config(config, children);
 
         if let Some(context_id) = context_id {
-            Context::Map(MapContext::new(base, context_id))
+            Context::Map(MapContext::new(base, context_id).key_limit(key_limit))
         } else {
+            if key_limit.is_some() {
+                warn!("Context: key_limit is ignored because context_id is not set; uuid={}",
+                      base.uuid());
+            }
             Context::Linear(LinearContext::new(base))
         }
     }
+
+    pub fn child(config: ContextConfig, children: Vec<ChildContext>) -> ChildContext {
+        let (base, context_id, key_limit) = Context::split_config(config, children);
+        if key_limit.is_some() {
+            warn!("Context: key_limit is not supported in child contexts; uuid={}",
+                  base.uuid());
+        }
+        ChildContext::new(base, context_id.unwrap_or_default())
+    }
+
+    pub fn evictions(&self) -> u64 {
+        match *self {
+            Context::Linear(_) => 0,
+            Context::Map(ref context) => context.evictions(),
+        }
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        match *self {
+            Context::Linear(ref context) => context.usage(),
+            Context::Map(ref context) => context.usage(),
+        }
+    }
+
+    pub fn set_admitting(&mut self, admitting: bool) {
+        match *self {
+            Context::Linear(ref mut context) => context.set_admitting(admitting),
+            Context::Map(ref mut context) => context.set_admitting(admitting),
+        }
+    }
+
+    pub fn set_retaining(&mut self, retaining: bool) {
+        match *self {
+            Context::Linear(ref mut context) => context.set_retaining(retaining),
+            Context::Map(ref mut context) => context.set_retaining(retaining),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        match *self {
+            Context::Linear(ref mut context) => context.base_mut().set_clock(clock),
+            Context::Map(ref mut context) => context.base_mut().set_clock(clock),
+        }
+    }
+
+    // The shard of count handling the message for this context. None if the
+    // message has no key in a map context.
+    pub fn shard_of(&self, message: &Message, count: usize) -> Option<Shard> {
+        match *self {
+            Context::Linear(ref context) => Some(Shard::of_context(context.base().uuid(), count)),
+            Context::Map(ref context) => {
+                context_key(context.context_id(), message).map(|key| Shard::of_key(&key, count))
+            }
+        }
+    }
+
+    pub fn next_timeout(&self) -> Option<Duration> {
+        match *self {
+            Context::Linear(ref context) => context.next_timeout(),
+            Context::Map(ref context) => context.next_timeout(),
+        }
+    }
+
+    pub fn oldest_state_age(&self) -> Option<Duration> {
+        match *self {
+            Context::Linear(ref context) => context.oldest_state_age(),
+            Context::Map(ref context) => context.oldest_state_age(),
+        }
+    }
+
+    pub fn close_oldest_state(&mut self, responder: &mut ResponseSender) {
+        match *self {
+            Context::Linear(ref mut context) => context.close_oldest_state(responder),
+            Context::Map(ref mut context) => context.close_oldest_state(responder),
+        }
+    }
+
+    pub fn close_states(&mut self,
+                        selector: &Selector,
+                        mode: CloseMode,
+                        reason: CloseReason,
+                        responder: &mut ResponseSender)
+                        -> usize {
+        match *self {
+            Context::Linear(ref mut context) => {
+                context.close_states(selector, mode, reason, responder)
+            }
+            Context::Map(ref mut context) => {
+                context.close_states(selector, mode, reason, responder)
+            }
+        }
+    }
+
+    pub fn info(&self) -> ContextInfo {
+        match *self {
+            Context::Linear(ref context) => context.info(),
+            Context::Map(ref context) => context.info(),
+        }
+    }
+
+    pub fn snapshot(&self) -> ContextSnapshot {
+        match *self {
+            Context::Linear(ref context) => context.snapshot(),
+            Context::Map(ref context) => context.snapshot(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: ContextSnapshot, orphans: &mut Vec<Uuid>) {
+        match *self {
+            Context::Linear(ref mut context) => context.restore(snapshot, orphans),
+            Context::Map(ref mut context) => context.restore(snapshot, orphans),
+        }
+    }
+
+    pub fn base(&self) -> &BaseContext {
+        match *self {
+            Context::Linear(ref context) => context.base(),
+            Context::Map(ref context) => context.base(),
+        }
+    }
+
+    // Moves the open states of old, the context with the same uuid before a
+    // reload, to this context. The states of its removed children are closed
+    // by mode.
+    pub fn adopt_states(&mut self,
+                        old: &mut Context,
+                        mode: CloseMode,
+                        responder: &mut ResponseSender) {
+        let (states, old_now) = match *old {
+            Context::Linear(ref mut context) => (context.take_states(), context.now()),
+            Context::Map(ref mut context) => (context.take_states(), context.now()),
+        };
+        let old = old.base();
+        match *self {
+            Context::Linear(ref mut context) => {
+                context.adopt_states(old, states, old_now, mode, responder)
+            }
+            Context::Map(ref mut context) => {
+                context.adopt_states(old, states, old_now, mode, responder)
+            }
+        }
+    }
+
+    fn split_config(config: ContextConfig,
+                    children: Vec<ChildContext>)
+                    -> (BaseContext, Option<Vec<KeyExpression>>, Option<KeyLimit>) {
+        let ContextConfig {name, uuid, conditions, context_id, actions, patterns, active, key_limit, ..} = config;
+
+        let base = BaseContextBuilder::new(uuid, conditions);
+        let base = base.name(name);
+        let base = base.patterns(patterns);
+        let base = base.actions(actions);
+        let base = base.active(active);
+        let base = base.children(children);
+        (base.build(), context_id, key_limit)
+    }
+}
+
+impl From<ContextConfig> for Context {
+    fn from(config: ContextConfig) -> Context {
+        Context::with_children(config, Vec::new())
+    }
 }
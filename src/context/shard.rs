@@ -0,0 +1,84 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use uuid::Uuid;
+
+use context::map::map::ContextKey;
+
+// One of count partitions of the states. Contexts without context_id are
+// owned by one shard, chosen by their uuid, the states of the other contexts
+// are partitioned by their keys. The owners don't depend on the order of the
+// contexts, so they are kept after a reload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Shard {
+    pub fn new(index: usize, count: usize) -> Shard {
+        assert!(index < count, "The index of a Shard must be less than the number of shards");
+        Shard {
+            index: index,
+            count: count,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // The shard of count owning the context.
+    pub fn of_context(uuid: &Uuid, count: usize) -> Shard {
+        Shard::of(uuid.as_bytes(), count)
+    }
+
+    // The shard of count owning the state of the key.
+    pub fn of_key(key: &ContextKey, count: usize) -> Shard {
+        Shard::of(key, count)
+    }
+
+    fn of<H: Hash + ?Sized>(value: &H, count: usize) -> Shard {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        Shard::new((hasher.finish() % count as u64) as usize, count)
+    }
+
+    pub fn owns_context(&self, uuid: &Uuid) -> bool {
+        Shard::of_context(uuid, self.count) == *self
+    }
+
+    pub fn owns_key(&self, key: &ContextKey) -> bool {
+        Shard::of_key(key, self.count) == *self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::Shard;
+
+    #[test]
+    fn test_given_shards_when_they_are_asked_then_every_key_is_owned_by_exactly_one_of_them() {
+        let shards = (0..4).map(|index| Shard::new(index, 4)).collect::<Vec<Shard>>();
+        for i in 0..100 {
+            let key = vec![("user".to_owned(), Some(format!("user{}", i)))];
+            assert_eq!(1, shards.iter().filter(|shard| shard.owns_key(&key)).count());
+            let uuid = Uuid::new_v4();
+            assert_eq!(1, shards.iter().filter(|shard| shard.owns_context(&uuid)).count());
+        }
+    }
+}
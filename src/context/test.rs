@@ -10,11 +10,14 @@ use uuid::Uuid;
 use std::sync::Arc;
 use std::time::Duration;
 
-use message::MessageBuilder;
+use message::{MessageBuilder, estimated_values_size};
 use timer::TimerEvent;
-use context::LinearContext;
+use context::{ChildContext, LinearContext, MapContext};
 use context::BaseContextBuilder;
+use config::action::message::MessageActionBuilder;
+use dispatcher::Response;
 use conditions::ConditionsBuilder;
+use schedule::{ScheduleBuilder, TimeSource, TimeWindow};
 use test_utils::MockResponseSender;
 
 #[test]
@@ -123,3 +126,257 @@ fn test_given_close_condition_with_renew_timeout_when_the_timeout_expires_with_r
     context.on_timer(&TimerEvent::from_millis(1), &mut responder);
     assert_true!(context.is_open());
 }
+
+#[test]
+fn test_given_context_with_active_schedule_when_messages_arrive_outside_the_window_then_they_do_not_open_it
+    () {
+    let mut responder = MockResponseSender::default();
+    // 2016-06-06 00:00:00 UTC
+    let midnight = 1465171200;
+    let msg_id = "11eaf6f8-0640-460f-aee2-a72d2f2ab258".to_owned();
+    let conditions = ConditionsBuilder::new(Duration::from_millis(100)).build();
+    let schedule = ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 6 * 60)])
+                       .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                       .close_on_end(true)
+                       .build();
+    let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                   .patterns(vec![msg_id.clone()])
+                   .active(Some(schedule))
+                   .build();
+    let mut context = LinearContext::new(base);
+    let at = |hour: u64| {
+        Arc::new(MessageBuilder::new(&msg_id, "message")
+                     .pair("TIMESTAMP", &(midnight + hour * 3600).to_string())
+                     .build())
+    };
+    context.on_message(at(12), &mut responder);
+    assert_false!(context.is_open());
+    context.on_message(at(23), &mut responder);
+    assert_true!(context.is_open());
+    context.on_message(at(24 + 5), &mut responder);
+    assert_true!(context.is_open());
+    context.on_message(at(24 + 7), &mut responder);
+    assert_false!(context.is_open());
+}
+
+#[test]
+fn test_given_context_with_event_time_schedule_when_its_window_ends_without_messages_then_a_timer_closes_it
+    () {
+    let mut responder = MockResponseSender::default();
+    // 2016-06-06 00:00:00 UTC
+    let midnight = 1465171200;
+    let msg_id = "11eaf6f8-0640-460f-aee2-a72d2f2ab258".to_owned();
+    let conditions = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+    let schedule = ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 6 * 60)])
+                       .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                       .close_on_end(true)
+                       .build();
+    let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                   .patterns(vec![msg_id.clone()])
+                   .active(Some(schedule))
+                   .build();
+    let mut context = LinearContext::new(base);
+    let message = MessageBuilder::new(&msg_id, "message")
+                      .pair("TIMESTAMP", &(midnight + 5 * 3600 + 59 * 60).to_string())
+                      .build();
+    context.on_message(Arc::new(message), &mut responder);
+    assert_true!(context.is_open());
+    context.on_timer(&TimerEvent::from_millis(30 * 1000), &mut responder);
+    assert_true!(context.is_open());
+    context.on_timer(&TimerEvent::from_millis(30 * 1000), &mut responder);
+    assert_false!(context.is_open());
+}
+
+#[test]
+fn test_given_parent_context_with_a_child_when_the_parent_closes_then_the_children_are_closed_first
+    () {
+    let mut responder = MockResponseSender::default();
+    let timeout = Duration::from_millis(1000);
+    let child = {
+        let conditions = ConditionsBuilder::new(timeout).first_opens(true).last_closes(true).build();
+        let action = MessageActionBuilder::new("COMMAND", "command finished")
+                         .name(Some("COMMAND"))
+                         .inherit(vec!["user_name".to_owned()])
+                         .build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["CMD_START".to_owned(), "CMD_END".to_owned()])
+                       .actions(vec![action.into()])
+                       .build();
+        ChildContext::new(base, vec!["command_id".into()])
+    };
+    let mut context = {
+        let conditions = ConditionsBuilder::new(timeout).first_opens(true).last_closes(true).build();
+        let action = MessageActionBuilder::new("SESSION", "session finished").build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["LOGIN".to_owned(), "LOGOUT".to_owned()])
+                       .actions(vec![action.into()])
+                       .children(vec![child])
+                       .build();
+        MapContext::new(base, vec!["session_id".into()])
+    };
+    let message = |uuid: &str, command_id: &str| {
+        Arc::new(MessageBuilder::new(uuid, "message")
+                     .pair("session_id", "1")
+                     .pair("command_id", command_id)
+                     .pair("user_name", "linus")
+                     .build())
+    };
+
+    context.on_message(message("CMD_START", "a"), &mut responder);
+    assert_false!(context.is_open());
+    context.on_message(message("LOGIN", ""), &mut responder);
+    context.on_message(message("CMD_START", "a"), &mut responder);
+    context.on_message(message("CMD_END", "a"), &mut responder);
+    context.on_message(message("CMD_START", "b"), &mut responder);
+    assert_eq!(1, responder.0.len());
+    context.on_message(message("LOGOUT", ""), &mut responder);
+    assert_false!(context.is_open());
+
+    let uuids = responder.0
+                         .iter()
+                         .map(|response| {
+                             match *response {
+                                 Response::Alert(ref alert) => alert.message.uuid().clone(),
+                                 _ => unreachable!(),
+                             }
+                         })
+                         .collect::<Vec<String>>();
+    assert_eq!(vec!["COMMAND", "COMMAND", "SESSION"], uuids);
+    if let Response::Alert(ref alert) = responder.0[0] {
+        assert_eq!(Some(&"linus".to_owned()), alert.message.get("user_name"));
+    }
+}
+
+#[test]
+fn test_given_parent_context_when_its_children_report_results_then_they_are_added_to_the_parent_state
+    () {
+    let mut responder = MockResponseSender::default();
+    let timeout = Duration::from_millis(1000);
+    let child = {
+        let conditions = ConditionsBuilder::new(timeout).max_size(1).build();
+        let action = MessageActionBuilder::new("COMMAND", "command finished").build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["CMD".to_owned()])
+                       .actions(vec![action.into()])
+                       .build();
+        ChildContext::new(base, Vec::new())
+    };
+    let mut context = {
+        let conditions = ConditionsBuilder::new(timeout).first_opens(true).max_size(3).build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["LOGIN".to_owned()])
+                       .children(vec![child])
+                       .build();
+        LinearContext::new(base)
+    };
+    let message = |uuid: &str| Arc::new(MessageBuilder::new(uuid, "message").build());
+
+    context.on_message(message("LOGIN"), &mut responder);
+    context.on_message(message("CMD"), &mut responder);
+    assert_true!(context.is_open());
+    context.on_message(message("CMD"), &mut responder);
+    assert_false!(context.is_open());
+}
+
+#[test]
+fn test_given_parent_context_with_renew_timeout_when_its_children_report_results_then_the_timeout_is_renewed
+    () {
+    let mut responder = MockResponseSender::default();
+    let child = {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(1000)).max_size(1).build();
+        let action = MessageActionBuilder::new("COMMAND", "command finished").build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["CMD".to_owned()])
+                       .actions(vec![action.into()])
+                       .build();
+        ChildContext::new(base, Vec::new())
+    };
+    let mut context = {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(1000))
+                             .renew_timeout(Duration::from_millis(100))
+                             .first_opens(true)
+                             .build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["LOGIN".to_owned()])
+                       .children(vec![child])
+                       .build();
+        LinearContext::new(base)
+    };
+    let message = |uuid: &str| Arc::new(MessageBuilder::new(uuid, "message").build());
+
+    context.on_message(message("LOGIN"), &mut responder);
+    context.on_timer(&TimerEvent::from_millis(90), &mut responder);
+    context.on_message(message("CMD"), &mut responder);
+    context.on_timer(&TimerEvent::from_millis(90), &mut responder);
+    assert_true!(context.is_open());
+    context.on_timer(&TimerEvent::from_millis(20), &mut responder);
+    assert_false!(context.is_open());
+}
+
+#[test]
+fn test_given_context_when_its_states_are_closed_then_the_alerts_contain_the_close_reason() {
+    let mut responder = MockResponseSender::default();
+    let conditions = ConditionsBuilder::new(Duration::from_millis(100)).max_size(2).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                   .patterns(vec!["p1".to_owned()])
+                   .actions(vec![action.into()])
+                   .build();
+    let mut context = LinearContext::new(base);
+    let event = Arc::new(MessageBuilder::new("p1", "message").build());
+    context.on_message(event.clone(), &mut responder);
+    context.on_timer(&TimerEvent::from_millis(100), &mut responder);
+    context.on_message(event.clone(), &mut responder);
+    context.on_message(event, &mut responder);
+    let reasons = responder.0
+                           .iter()
+                           .map(|response| {
+                               match *response {
+                                   Response::Alert(ref alert) => {
+                                       alert.message.get("context_close_reason").cloned()
+                                   }
+                                   _ => unreachable!(),
+                               }
+                           })
+                           .collect::<Vec<Option<String>>>();
+    assert_eq!(vec![Some("timeout".to_owned()), Some("condition".to_owned())], reasons);
+}
+
+#[test]
+fn test_given_parent_context_when_a_child_state_is_opened_then_only_an_inheriting_child_copies_the_parent_values
+    () {
+    let mut responder = MockResponseSender::default();
+    let timeout = Duration::from_millis(1000);
+    let build_context = |inherit: Vec<String>| {
+        let child = {
+            let conditions = ConditionsBuilder::new(timeout).build();
+            let action = MessageActionBuilder::new("COMMAND", "command finished")
+                             .inherit(inherit)
+                             .build();
+            let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                           .patterns(vec!["CMD".to_owned()])
+                           .actions(vec![action.into()])
+                           .build();
+            ChildContext::new(base, Vec::new())
+        };
+        let conditions = ConditionsBuilder::new(timeout).build();
+        let base = BaseContextBuilder::new(Uuid::new_v4(), conditions)
+                       .patterns(vec!["LOGIN".to_owned()])
+                       .children(vec![child])
+                       .build();
+        LinearContext::new(base)
+    };
+    let login = Arc::new(MessageBuilder::new("LOGIN", "login").pair("user_name", "linus").build());
+    let command = Arc::new(MessageBuilder::new("CMD", "command").build());
+    let messages_size = login.estimated_size() + command.estimated_size();
+    let mut context = build_context(Vec::new());
+    context.on_message(login.clone(), &mut responder);
+    context.on_message(command.clone(), &mut responder);
+    assert_eq!(2, context.usage().messages);
+    assert_eq!(messages_size, context.usage().bytes);
+    let mut context = build_context(vec!["user_name".to_owned()]);
+    context.on_message(login.clone(), &mut responder);
+    context.on_message(command.clone(), &mut responder);
+    assert_eq!(messages_size + estimated_values_size(login.values()),
+               context.usage().bytes);
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::fmt;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use context::map::map::ContextKey;
+use introspection::StateInfo;
+
+pub type Predicate = Fn(&Uuid, &StateInfo) -> bool + Send + Sync;
+
+// Selects the open states of the contexts which should be closed. The states
+// of child contexts are closed with their parent states.
+#[derive(Clone)]
+pub enum Selector {
+    All,
+    Context(Uuid),
+    Key(Uuid, ContextKey),
+    Matching(Arc<Predicate>),
+}
+
+impl Selector {
+    pub fn matching<F>(predicate: F) -> Selector
+        where F: Fn(&Uuid, &StateInfo) -> bool + Send + Sync + 'static
+    {
+        Selector::Matching(Arc::new(predicate))
+    }
+}
+
+impl fmt::Debug for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::All => write!(f, "All"),
+            Selector::Context(ref uuid) => write!(f, "Context({})", uuid),
+            Selector::Key(ref uuid, ref key) => write!(f, "Key({}, {:?})", uuid, key),
+            Selector::Matching(_) => write!(f, "Matching(..)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseMode {
+    // The states are closed like they were closed by their conditions, so
+    // their actions are executed.
+    Execute,
+    // The states are dropped without executing any actions.
+    Discard,
+}
+
+// Why a state was closed, the close alerts carry it in their values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    // The timeout or the renew timeout of the state is expired.
+    Timeout,
+    // The state reached its max size or got its closing message.
+    Condition,
+    // The state is removed to make room for a new key or to free memory.
+    Evicted,
+    // The state is selected by a close request or its context is removed by
+    // a reload.
+    Requested,
+    // The state is flushed by the shutdown of the correlator.
+    Shutdown,
+}
+
+impl CloseReason {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CloseReason::Timeout => "timeout",
+            CloseReason::Condition => "condition",
+            CloseReason::Evicted => "evicted",
+            CloseReason::Requested => "requested",
+            CloseReason::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// What happens to the open states when the correlator is shut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownMode {
+    // The states are closed and their actions are executed.
+    Flush,
+    // The states are dropped without executing any actions.
+    Discard,
+    // The states are dropped without executing any actions and returned in a
+    // snapshot, so they can be restored later.
+    Persist,
+}
@@ -0,0 +1,268 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::cmp;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clock::Clock;
+use config::ContextConfig;
+use context::ContextMap;
+use context::budget::MemoryBudget;
+use control::ShutdownMode;
+use journal::{Journal, JournalConfig};
+use queue::{OverflowPolicy, QueueConfig};
+use snapshot::{OrphanPolicy, Snapshot};
+use super::{AlertHandler, ContextError, Correlator, CorrelatorFactory, DispatcherConfig, Error};
+use super::loader;
+
+enum Contexts {
+    Map(ContextMap),
+    Configs(Vec<ContextConfig>),
+    Path(PathBuf),
+}
+
+// Collects the options of a Correlator. The options which are not set keep
+// the defaults of Correlator::new().
+pub struct CorrelatorBuilder<T> {
+    contexts: Contexts,
+    config: DispatcherConfig,
+    shards: usize,
+    journal: Option<JournalConfig>,
+    shutdown_mode: Option<ShutdownMode>,
+    snapshot: Option<(Snapshot, OrphanPolicy)>,
+    lenient: bool,
+    alert_handler: Option<Box<AlertHandler<T>>>,
+    error_handler: Option<Box<dyn FnMut(&str)>>,
+}
+
+impl<T> CorrelatorBuilder<T> {
+    pub fn new(context_map: ContextMap) -> CorrelatorBuilder<T> {
+        CorrelatorBuilder::with_contexts(Contexts::Map(context_map))
+    }
+
+    pub fn from_configs(configs: Vec<ContextConfig>) -> CorrelatorBuilder<T> {
+        CorrelatorBuilder::with_contexts(Contexts::Configs(configs))
+    }
+
+    // The file is loaded by build().
+    pub fn from_path<P: Into<PathBuf>>(path: P) -> CorrelatorBuilder<T> {
+        CorrelatorBuilder::with_contexts(Contexts::Path(path.into()))
+    }
+
+    fn with_contexts(contexts: Contexts) -> CorrelatorBuilder<T> {
+        CorrelatorBuilder {
+            contexts: contexts,
+            config: DispatcherConfig::default(),
+            shards: 1,
+            journal: None,
+            shutdown_mode: None,
+            snapshot: None,
+            lenient: false,
+            alert_handler: None,
+            error_handler: None,
+        }
+    }
+
+    pub fn clock(mut self, clock: Arc<Clock>) -> CorrelatorBuilder<T> {
+        self.config.clock = clock;
+        self
+    }
+
+    // The contexts get a timer event at least this often, even if none of
+    // their states has a deadline.
+    pub fn tick_interval(mut self, interval: Duration) -> CorrelatorBuilder<T> {
+        self.config.tick_interval = Some(interval);
+        self
+    }
+
+    pub fn request_queue(mut self, config: QueueConfig) -> CorrelatorBuilder<T> {
+        self.config.requests = config;
+        self
+    }
+
+    pub fn alert_queue(mut self, config: QueueConfig) -> CorrelatorBuilder<T> {
+        self.config.alerts = config;
+        self
+    }
+
+    // The number of dispatcher threads the states are partitioned between.
+    // More than one needs the configs of the contexts instead of a map.
+    pub fn threads(mut self, threads: usize) -> CorrelatorBuilder<T> {
+        self.shards = threads;
+        self
+    }
+
+    // A journal can't be used with more than one thread.
+    pub fn journal(mut self, config: JournalConfig) -> CorrelatorBuilder<T> {
+        self.journal = Some(config);
+        self
+    }
+
+    pub fn shutdown_mode(mut self, mode: ShutdownMode) -> CorrelatorBuilder<T> {
+        self.shutdown_mode = Some(mode);
+        self
+    }
+
+    pub fn max_loopback_depth(mut self, depth: usize) -> CorrelatorBuilder<T> {
+        self.config.max_loopback_depth = Some(depth);
+        self
+    }
+
+    // Limits the messages kept by the states, see ContextMap::set_memory_budget().
+    // With more than one thread every shard gets an even share of it.
+    pub fn memory_budget(mut self, budget: MemoryBudget) -> CorrelatorBuilder<T> {
+        self.config.memory_budget = Some(budget);
+        self
+    }
+
+    // A dispatcher which panicked is restarted at most max_restarts times, then
+    // the correlator fails. No restart is made by default.
+    pub fn max_restarts(mut self, max_restarts: usize) -> CorrelatorBuilder<T> {
+        self.config.max_restarts = max_restarts;
+        self
+    }
+
+    // A dispatcher which can be restarted takes a checkpoint of its states
+    // after every interval requests (1000 by default) and before every control
+    // request. A restart loses the requests handled since the last one.
+    pub fn checkpoint_interval(mut self, interval: u64) -> CorrelatorBuilder<T> {
+        self.config.checkpoint_interval = cmp::max(interval, 1);
+        self
+    }
+
+    // The states of the snapshot are reattached to the contexts with the same
+    // uuid. The policy decides what happens with the states of contexts
+    // which are not in the configuration any more.
+    pub fn snapshot(mut self, snapshot: Snapshot, policy: OrphanPolicy) -> CorrelatorBuilder<T> {
+        self.snapshot = Some((snapshot, policy));
+        self
+    }
+
+    // The invalid contexts of the file given to from_path() are skipped
+    // instead of failing build(), their errors are passed to the error
+    // callback. See also build_lenient().
+    pub fn lenient(mut self, lenient: bool) -> CorrelatorBuilder<T> {
+        self.lenient = lenient;
+        self
+    }
+
+    pub fn alert_handler(mut self, handler: Box<AlertHandler<T>>) -> CorrelatorBuilder<T> {
+        self.alert_handler = Some(handler);
+        self
+    }
+
+    // See Correlator::set_error_callback().
+    pub fn error_callback<F>(mut self, handler: F) -> CorrelatorBuilder<T>
+        where F: FnMut(&str) + 'static
+    {
+        self.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    pub fn build(self) -> Result<Correlator<T>, Error> {
+        self.build_with_errors().map(|(correlator, _)| correlator)
+    }
+
+    // Builds the correlator like lenient(true) and returns the errors of the
+    // skipped contexts next to it.
+    pub fn build_lenient(mut self) -> Result<(Correlator<T>, Vec<ContextError>), Error> {
+        self.lenient = true;
+        self.build_with_errors()
+    }
+
+    fn build_with_errors(self) -> Result<(Correlator<T>, Vec<ContextError>), Error> {
+        let CorrelatorBuilder { contexts, config, shards, journal, shutdown_mode, snapshot,
+                                lenient, alert_handler, mut error_handler } = self;
+        let mut context_errors = Vec::new();
+        let contexts = match contexts {
+            Contexts::Path(ref path) if lenient => {
+                let (configs, errors) = try!(loader::load_file_lenient(path));
+                for i in &errors {
+                    warn!("CorrelatorBuilder: skipping an invalid context; error={}", i);
+                    if let Some(ref mut handler) = error_handler {
+                        handler(&i.to_string());
+                    }
+                }
+                context_errors = errors;
+                Contexts::Configs(configs)
+            }
+            Contexts::Path(path) => Contexts::Configs(try!(CorrelatorFactory::load_file(path))),
+            contexts => contexts,
+        };
+        let mut correlator = if shards > 1 {
+            if journal.is_some() {
+                return Err(Error::InvalidOptions("a journal can't be used with more than one \
+                                                  thread"));
+            }
+            match contexts {
+                Contexts::Configs(configs) => {
+                    let snapshot = match snapshot {
+                        Some((snapshot, policy)) => {
+                            let mut context_map = ContextMap::from_configs(configs.clone());
+                            try!(restore(&mut context_map, snapshot.clone(), policy));
+                            Some(snapshot)
+                        }
+                        None => None,
+                    };
+                    Correlator::spawn_shards(configs, shards, config, snapshot)
+                }
+                _ => {
+                    return Err(Error::InvalidOptions("more than one thread needs the configs of \
+                                                      the contexts"))
+                }
+            }
+        } else {
+            let mut context_map = match contexts {
+                Contexts::Map(context_map) => context_map,
+                Contexts::Configs(configs) => ContextMap::from_configs(configs),
+                Contexts::Path(_) => unreachable!("The configs of the path are already loaded"),
+            };
+            if let Some((snapshot, policy)) = snapshot {
+                try!(restore(&mut context_map, snapshot, policy));
+            }
+            match journal {
+                // The journal counts the delivered alerts, an accepted alert
+                // dropped later by the queue would shift the count.
+                Some(_) if config.alerts.policy == OverflowPolicy::DropOldest &&
+                           config.alerts.capacity.is_some() => {
+                    return Err(Error::InvalidOptions("a journal can't be used with an alert \
+                                                      queue dropping the oldest alerts"))
+                }
+                Some(journal) => {
+                    let (journal, delivery_log) = try!(Journal::open(journal));
+                    Correlator::spawn(context_map, config, Some(journal), Some(delivery_log))
+                }
+                None => Correlator::spawn(context_map, config, None, None),
+            }
+        };
+        correlator.set_shutdown_mode(shutdown_mode);
+        correlator.set_alert_handler(alert_handler);
+        correlator.error_handler = error_handler;
+        Ok((correlator, context_errors))
+    }
+}
+
+fn restore(context_map: &mut ContextMap, snapshot: Snapshot, policy: OrphanPolicy) -> Result<(), Error> {
+    let orphans = context_map.restore(snapshot);
+    if orphans.is_empty() {
+        return Ok(());
+    }
+    match policy {
+        OrphanPolicy::Discard => {
+            for i in &orphans {
+                warn!("CorrelatorBuilder: context of a restored state is not found, discarding \
+                       its states; uuid={}",
+                      i);
+            }
+            Ok(())
+        }
+        OrphanPolicy::Fail => Err(Error::OrphanedStates(orphans)),
+    }
+}
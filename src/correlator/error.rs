@@ -8,7 +8,12 @@
 
 use serde_json;
 use serde_yaml;
+use std::error;
+use std::fmt;
 use std::io;
+use std::path::PathBuf;
+
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum Error {
@@ -16,7 +21,104 @@ pub enum Error {
     SerdeJson(serde_json::error::Error),
     SerdeYaml(serde_yaml::error::Error),
     UnsupportedFileExtension,
-    NotUtf8FileName
+    NotUtf8FileName,
+    OrphanedStates(Vec<Uuid>),
+    InvalidOptions(&'static str),
+    Context(ContextError),
+}
+
+// A context of a config file which can't be loaded. The location is the path
+// of the invalid value in the document, like "[2].actions[0]". The line and
+// the column are only known for some errors of JSON files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextError {
+    pub path: Option<PathBuf>,
+    pub index: usize,
+    pub uuid: Option<String>,
+    pub location: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            try!(write!(f, "{}: ", path.display()));
+        }
+        try!(write!(f, "invalid context #{}", self.index));
+        if let Some(ref uuid) = self.uuid {
+            try!(write!(f, " uuid={}", uuid));
+        }
+        try!(write!(f, " at {}", self.location));
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            try!(write!(f, " (line {} column {})", line, column));
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl error::Error for ContextError {
+    fn description(&self) -> &str {
+        "invalid context"
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "I/O error: {}", error),
+            Error::SerdeJson(ref error) => write!(f, "invalid JSON: {}", error),
+            Error::SerdeYaml(ref error) => write!(f, "invalid YAML: {}", error),
+            Error::UnsupportedFileExtension => {
+                write!(f, "unsupported file extension, expected json, yaml or yml")
+            }
+            Error::NotUtf8FileName => write!(f, "the file name is not valid UTF-8"),
+            Error::OrphanedStates(ref uuids) => {
+                try!(write!(f, "the contexts of restored states are not found; uuids="));
+                for (i, uuid) in uuids.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ","));
+                    }
+                    try!(write!(f, "{}", uuid));
+                }
+                Ok(())
+            }
+            Error::InvalidOptions(reason) => write!(f, "invalid options: {}", reason),
+            Error::Context(ref error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(ref error) => error.description(),
+            Error::SerdeJson(ref error) => error.description(),
+            Error::SerdeYaml(ref error) => error.description(),
+            Error::UnsupportedFileExtension => "unsupported file extension",
+            Error::NotUtf8FileName => "the file name is not valid UTF-8",
+            Error::OrphanedStates(_) => "orphaned states",
+            Error::InvalidOptions(reason) => reason,
+            Error::Context(ref error) => error.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            Error::SerdeJson(ref error) => Some(error),
+            Error::SerdeYaml(ref error) => Some(error),
+            Error::Context(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ContextError> for Error {
+    fn from(error: ContextError) -> Error {
+        Error::Context(error)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -6,17 +6,18 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::io::Read;
-use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use serde_json;
-use serde_yaml;
+use serde::Deserialize;
 
 use config::ContextConfig;
+use control::CloseMode;
+use snapshot::Snapshot;
 use ContextMap;
 use super::Correlator;
-use super::Error;
+use super::{ConfigWatcher, ContextError, Error};
+use super::loader::{self, Format};
 
 pub struct CorrelatorFactory;
 
@@ -26,34 +27,39 @@ impl CorrelatorFactory {
         Ok(Correlator::new(ContextMap::from_configs(contexts)))
     }
 
+    // Reloads the contexts of the correlator when the file at path changes.
+    // The watcher is checking the file until it is stopped or dropped.
+    pub fn watch<T, P: Into<PathBuf>>(correlator: &Correlator<T>,
+                                      path: P,
+                                      interval: Duration,
+                                      mode: CloseMode)
+                                      -> ConfigWatcher {
+        ConfigWatcher::new(path, interval, mode, correlator.dispatcher_input_channel.clone())
+    }
+
+    // Fails on the first invalid context with an Error::Context which tells
+    // where the context is in the file.
     pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Vec<ContextConfig>, Error> {
-        match path.as_ref().extension() {
-            Some(extension) => {
-                match try!(extension.to_str().ok_or(Error::NotUtf8FileName)) {
-                    "json" => {
-                        let content = try!(CorrelatorFactory::read(&path));
-                        serde_json::from_str::<Vec<ContextConfig>>(&content).map_err(Error::SerdeJson)
-                    },
-                    "yaml" | "yml" | "YAML" | "YML" => {
-                        let content = try!(CorrelatorFactory::read(&path));
-                        serde_yaml::from_str::<Vec<ContextConfig>>(&content).map_err(Error::SerdeYaml)
-                    },
-                    _ => Err(Error::UnsupportedFileExtension),
-                }
-            },
-            None => {
-                Err(Error::UnsupportedFileExtension)
-            }
-        }
+        let format = try!(Format::of(&path));
+        let content = try!(loader::read(&path));
+        loader::load_strict(path.as_ref(), format, &content)
+    }
 
+    // Skips the invalid contexts and returns their errors next to the valid
+    // contexts. Only the errors of the whole file are returned as Err.
+    pub fn load_file_lenient<P>(path: P) -> Result<(Vec<ContextConfig>, Vec<ContextError>), Error>
+        where P: AsRef<Path>
+    {
+        loader::load_file_lenient(path.as_ref())
     }
 
-    fn read<P: AsRef<Path>>(path: P) -> Result<String, Error> {
-        trace!("Trying to load contexts from file; path={}", path.as_ref().display());
-        let mut file = try!(File::open(path));
-        let mut buffer = String::new();
-        try!(file.read_to_string(&mut buffer));
-        Ok(buffer)
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<Snapshot, Error> {
+        CorrelatorFactory::load(path)
     }
 
+    fn load<D: Deserialize, P: AsRef<Path>>(path: P) -> Result<D, Error> {
+        let format = try!(Format::of(&path));
+        let content = try!(loader::read(&path));
+        format.parse(&content)
+    }
 }
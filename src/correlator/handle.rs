@@ -0,0 +1,101 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::Arc;
+use std::sync::mpsc::{self, SendError, TrySendError};
+
+use config::ContextConfig;
+use context::ReloadReport;
+use control::{CloseMode, Selector};
+use dispatcher::request::Request;
+use introspection::ContextInfo;
+use message::Message;
+use queue::{QueueSender, QueueStats};
+use snapshot::Snapshot;
+
+// Sends requests to the dispatcher of a Correlator. The handles can be cloned
+// and moved to other threads, the Correlator itself keeps the consumption of
+// the alerts and the shutdown. The requests of a handle are handled in the
+// order they were sent.
+#[derive(Clone)]
+pub struct CorrelatorHandle {
+    channel: QueueSender<Request>,
+}
+
+impl CorrelatorHandle {
+    pub fn new(channel: QueueSender<Request>) -> CorrelatorHandle {
+        CorrelatorHandle { channel: channel }
+    }
+
+    // The messages are limited by the capacity of the request queue, the
+    // other requests are always sent.
+    pub fn push_message(&self, message: Message) -> Result<(), TrySendError<Request>> {
+        self.channel.push(Request::Message(Arc::new(message)))
+    }
+
+    // The messages are sent in one request, which counts as one item per
+    // message against the capacity of the request queue.
+    pub fn push_messages<I>(&self, messages: I) -> Result<(), TrySendError<Request>>
+        where I: IntoIterator<Item = Message>
+    {
+        let messages = messages.into_iter().map(Arc::new).collect::<Vec<Arc<Message>>>();
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let weight = messages.len();
+        self.channel.push_weighted(Request::Batch(messages), weight)
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        self.channel.stats()
+    }
+
+    // Closes the selected states and executes their actions.
+    pub fn close(&self, selector: Selector) -> Result<(), SendError<Request>> {
+        self.channel.send(Request::Close(selector, CloseMode::Execute))
+    }
+
+    // Drops the selected states without executing their actions.
+    pub fn discard(&self, selector: Selector) -> Result<(), SendError<Request>> {
+        self.channel.send(Request::Close(selector, CloseMode::Discard))
+    }
+
+    // The next methods wait for the answer of the dispatcher, so the alerts
+    // must be handled by the owner of the Correlator in the meantime,
+    // otherwise a full alert queue with the Block policy blocks the
+    // dispatcher before it answers.
+
+    // Replaces the contexts while keeping the states of the contexts with the
+    // same uuid. The states of the removed contexts are closed by mode.
+    // Returns None if the dispatcher thread is not running.
+    pub fn reload(&self, configs: Vec<ContextConfig>, mode: CloseMode) -> Option<ReloadReport> {
+        let (tx, rx) = mpsc::channel();
+        if self.channel.send(Request::Reload(configs, mode, tx)).is_err() {
+            return None;
+        }
+        rx.recv().ok()
+    }
+
+    // Returns None if the dispatcher thread is not running.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        let (tx, rx) = mpsc::channel();
+        if self.channel.send(Request::Snapshot(tx)).is_err() {
+            return None;
+        }
+        rx.recv().ok()
+    }
+
+    // Returns None if the dispatcher thread is not running.
+    pub fn query(&self) -> Option<Vec<ContextInfo>> {
+        let (tx, rx) = mpsc::channel();
+        if self.channel.send(Request::Query(tx)).is_err() {
+            return None;
+        }
+        rx.recv().ok()
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json;
+use serde_json::error::Error as JsonError;
+use serde_json::value::{Value, from_value};
+use serde_yaml;
+
+use conditions::Conditions;
+use config::ContextConfig;
+use config::action::ActionType;
+use context::map::{KeyExpression, KeyLimit};
+use schedule::Schedule;
+use super::{ContextError, Error};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<Format, Error> {
+        match path.as_ref().extension() {
+            Some(extension) => {
+                match try!(extension.to_str().ok_or(Error::NotUtf8FileName)) {
+                    "json" => Ok(Format::Json),
+                    "yaml" | "yml" | "YAML" | "YML" => Ok(Format::Yaml),
+                    _ => Err(Error::UnsupportedFileExtension),
+                }
+            }
+            None => Err(Error::UnsupportedFileExtension),
+        }
+    }
+
+    pub fn parse<D: Deserialize>(&self, content: &str) -> Result<D, Error> {
+        match *self {
+            Format::Json => serde_json::from_str::<D>(content).map_err(Error::SerdeJson),
+            Format::Yaml => serde_yaml::from_str::<D>(content).map_err(Error::SerdeYaml),
+        }
+    }
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    trace!("Trying to load file; path={}", path.as_ref().display());
+    let mut file = try!(File::open(path));
+    let mut buffer = String::new();
+    try!(file.read_to_string(&mut buffer));
+    Ok(buffer)
+}
+
+// Skips the invalid contexts of the file and returns their errors next to the
+// valid contexts. Only the errors of the whole file are returned as Err.
+pub fn load_file_lenient(path: &Path) -> Result<(Vec<ContextConfig>, Vec<ContextError>), Error> {
+    let format = try!(Format::of(path));
+    let content = try!(read(path));
+    load_lenient(path, format, &content)
+}
+
+// Fails on the first invalid context. Only the errors of the contexts are
+// reported with their location, the errors of the document are returned as
+// they are.
+pub fn load_strict(path: &Path, format: Format, content: &str) -> Result<Vec<ContextConfig>, Error> {
+    let error = match format.parse::<Vec<ContextConfig>>(content) {
+        Ok(contexts) => return Ok(contexts),
+        Err(error) => error,
+    };
+    let values = match format.parse::<Vec<Value>>(content) {
+        Ok(values) => values,
+        Err(_) => return Err(error),
+    };
+    let (_, mut errors) = load_values(path, values);
+    if errors.is_empty() {
+        return Err(error);
+    }
+    let mut context_error = errors.remove(0);
+    set_position(&mut context_error, &error);
+    Err(Error::Context(context_error))
+}
+
+// Skips the invalid contexts and returns their errors next to the valid ones.
+pub fn load_lenient(path: &Path,
+                    format: Format,
+                    content: &str)
+                    -> Result<(Vec<ContextConfig>, Vec<ContextError>), Error> {
+    let values = try!(format.parse::<Vec<Value>>(content));
+    let (contexts, mut errors) = load_values(path, values);
+    if let Some(context_error) = errors.first_mut() {
+        // The parser stops at the first invalid context, so only its position
+        // is known.
+        if let Err(error) = format.parse::<Vec<ContextConfig>>(content) {
+            set_position(context_error, &error);
+        }
+    }
+    Ok((contexts, errors))
+}
+
+fn load_values(path: &Path, values: Vec<Value>) -> (Vec<ContextConfig>, Vec<ContextError>) {
+    let mut contexts = Vec::new();
+    let mut errors = Vec::new();
+    for (index, value) in values.into_iter().enumerate() {
+        match from_value::<ContextConfig>(value.clone()) {
+            Ok(context) => contexts.push(context),
+            Err(error) => {
+                let (location, error) = locate(index, &value).unwrap_or((format!("[{}]", index), error));
+                errors.push(ContextError {
+                    path: Some(path.to_path_buf()),
+                    index: index,
+                    uuid: value.find("uuid").and_then(Value::as_string).map(str::to_owned),
+                    location: location,
+                    line: None,
+                    column: None,
+                    message: message(&error),
+                });
+            }
+        }
+    }
+    (contexts, errors)
+}
+
+// Deserializes the fields of the context one by one to find the invalid one.
+fn locate(index: usize, value: &Value) -> Option<(String, JsonError)> {
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return None,
+    };
+    for (key, value) in object {
+        let location = format!("[{}].{}", index, key);
+        let result = match key.as_str() {
+            "conditions" => check::<Conditions>(location, value),
+            "context_id" => check_elements::<KeyExpression>(location, value),
+            "actions" => check_elements::<ActionType>(location, value),
+            "active" => check::<Schedule>(location, value),
+            "key_limit" => check::<KeyLimit>(location, value),
+            _ => None,
+        };
+        if result.is_some() {
+            return result;
+        }
+    }
+    None
+}
+
+fn check<D: Deserialize>(location: String, value: &Value) -> Option<(String, JsonError)> {
+    from_value::<D>(value.clone()).err().map(|error| (location, error))
+}
+
+fn check_elements<D: Deserialize>(location: String, value: &Value) -> Option<(String, JsonError)> {
+    match value.as_array() {
+        Some(elements) => {
+            elements.iter()
+                    .enumerate()
+                    .filter_map(|(i, element)| check::<D>(format!("{}[{}]", location, i), element))
+                    .next()
+        }
+        None => check::<Vec<D>>(location, value),
+    }
+}
+
+fn message(error: &JsonError) -> String {
+    match *error {
+        JsonError::Syntax(ref code, _, _) => format!("{:?}", code),
+        ref error => error.to_string(),
+    }
+}
+
+fn set_position(context_error: &mut ContextError, error: &Error) {
+    if let Error::SerdeJson(JsonError::Syntax(_, line, column)) = *error {
+        if line > 0 {
+            context_error.line = Some(line);
+            context_error.column = Some(column);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{Format, load_lenient, load_strict};
+    use correlator::Error;
+
+    const CONTEXTS: &'static str = r#"
+[
+    {
+        "uuid": "86ca9f93-84fb-4813-b037-6526f7a585a3",
+        "conditions": { "timeout": 100 }
+    },
+    {
+        "uuid": "185e96ad-2e6c-4e3b-9b3f-ae5ab3a2ba6b",
+        "conditions": { "timeout": 100 },
+        "actions": [
+            { "message": { "uuid": "4bbd15c4-ec44-47a2-ada3-f7fe3ff81222", "message": "m" } },
+            { "unknown": {} }
+        ]
+    },
+    {
+        "uuid": "c5ad4a03-5a3f-4c3b-8b3a-4b0a4d6e2f51"
+    }
+]
+"#;
+
+    #[test]
+    fn test_given_contexts_when_one_of_them_is_invalid_then_the_strict_load_reports_its_location() {
+        let error = load_strict(Path::new("contexts.json"), Format::Json, CONTEXTS).err().unwrap();
+        if let Error::Context(error) = error {
+            assert_eq!(1, error.index);
+            assert_eq!(Some("185e96ad-2e6c-4e3b-9b3f-ae5ab3a2ba6b".to_owned()), error.uuid);
+            assert_eq!("[1].actions[1]", error.location);
+            assert_eq!(Some(12), error.line);
+            assert_true!(error.to_string().starts_with("contexts.json: invalid context #1"));
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_given_contexts_when_they_are_loaded_leniently_then_the_invalid_ones_are_skipped() {
+        let (contexts, errors) = load_lenient(Path::new("contexts.json"), Format::Json, CONTEXTS)
+                                     .unwrap();
+        assert_eq!(1, contexts.len());
+        assert_eq!(2, errors.len());
+        assert_eq!("[1].actions[1]", errors[0].location);
+        assert_eq!(2, errors[1].index);
+        assert_eq!("[2]", errors[1].location);
+        assert_eq!(None, errors[1].line);
+        assert_true!(errors[1].message.contains("conditions"));
+    }
+
+    #[test]
+    fn test_given_yaml_contexts_when_a_condition_is_invalid_then_its_location_is_reported() {
+        let content = r#"
+- uuid: "86ca9f93-84fb-4813-b037-6526f7a585a3"
+  conditions:
+    timeout: 100
+- uuid: "185e96ad-2e6c-4e3b-9b3f-ae5ab3a2ba6b"
+  conditions:
+    timeout: 100
+    unknown: true
+"#;
+        let (contexts, errors) = load_lenient(Path::new("contexts.yaml"), Format::Yaml, content)
+                                     .unwrap();
+        assert_eq!(1, contexts.len());
+        assert_eq!(1, errors.len());
+        assert_eq!("[1].conditions", errors[0].location);
+    }
+
+    #[test]
+    fn test_given_a_document_which_is_not_a_list_when_it_is_loaded_leniently_then_it_fails() {
+        let result = load_lenient(Path::new("contexts.json"), Format::Json, "{}");
+        if let Err(Error::SerdeJson(_)) = result {
+        } else {
+            unreachable!();
+        }
+    }
+}
@@ -6,114 +6,666 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::sync::mpsc::{self, Sender};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc;
+use std::panic;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 
 use {Message, Response};
 use action::Alert;
-use context::ContextMap;
+use clock::{Clock, SystemClock};
+use config::ContextConfig;
+use context::{ContextMap, ReloadReport};
+use context::budget::{MemoryBudget, SharedUsage};
+use context::shard::Shard;
+use self::supervisor::Supervisor;
 use dispatcher::request::Request;
 use dispatcher::reactor::RequestReactor;
 use dispatcher::demux::Demultiplexer;
-use dispatcher::handlers::exit::ExitEventHandler;
-use dispatcher::handlers::timer::TimerEventHandler;
-use dispatcher::handlers::message::MessageEventHandler;
-use reactor::{Reactor, EventHandler};
-use timer::Timer;
+use dispatcher::router::Router;
+use control::{CloseMode, Selector, ShutdownMode};
+use introspection::ContextInfo;
+use journal::{DeliveryLog, Journal};
+use queue::{self, QueueConfig, QueueReceiver, QueueSender, QueueStats};
+use engine::Engine;
+use reactor::Reactor;
+use snapshot::Snapshot;
 
-const TIMER_STEP_MS: u64 = 100;
-
-pub use self::error::Error;
+pub use self::builder::CorrelatorBuilder;
+pub use self::error::{ContextError, Error};
 pub use self::factory::CorrelatorFactory;
+pub use self::handle::CorrelatorHandle;
+pub use self::supervisor::Status;
+pub use self::watcher::ConfigWatcher;
 
+mod builder;
 mod error;
 mod factory;
+mod handle;
+mod loader;
+mod supervisor;
+mod watcher;
 #[cfg(test)]
 mod test;
 
+const SYNC_POLL_INTERVAL_MS: u64 = 1;
+// The number of requests after which a supervised dispatcher takes a
+// checkpoint of its states by default, which are restored if it's restarted.
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1000;
+
+// The alerts of loopback actions are handled by the dispatcher, they are not
+// passed to the handler.
 pub trait AlertHandler<D> {
-    fn on_alert(&mut self, alert: Alert, channel: &mut Sender<Request>, extra_data: &mut D);
+    fn on_alert(&mut self, alert: Alert, channel: &mut QueueSender<Request>, extra_data: &mut D);
+}
+
+// The alerts can be routed to a channel, e.g. to be handled in another thread.
+impl<D> AlertHandler<D> for mpsc::Sender<Alert> {
+    fn on_alert(&mut self, alert: Alert, _: &mut QueueSender<Request>, _: &mut D) {
+        let _ = self.send(alert);
+    }
+}
+
+struct FnAlertHandler<F>(F);
+
+impl<D, F: FnMut(Alert)> AlertHandler<D> for FnAlertHandler<F> {
+    fn on_alert(&mut self, alert: Alert, _: &mut QueueSender<Request>, _: &mut D) {
+        (self.0)(alert)
+    }
+}
+
+// Iterates over the alerts which are already received from the dispatcher.
+pub struct Alerts<'a, T: 'a> {
+    correlator: &'a mut Correlator<T>,
+}
+
+impl<'a, T> Iterator for Alerts<'a, T> {
+    type Item = Alert;
+    fn next(&mut self) -> Option<Alert> {
+        self.correlator.try_recv_alert()
+    }
+}
+
+// What is left of a stopped correlator. The snapshot holds the states
+// persisted by ShutdownMode::Persist, the map is empty then.
+pub struct Stopped {
+    pub context_map: ContextMap,
+    pub snapshot: Option<Snapshot>,
 }
 
 pub struct Correlator<T=()> {
-    dispatcher_input_channel: mpsc::Sender<Request>,
-    dispatcher_output_channel: mpsc::Receiver<Response>,
+    dispatcher_input_channel: QueueSender<Request>,
+    handle: CorrelatorHandle,
+    dispatcher_output_channel: QueueReceiver<Response>,
+    // The responses received while waiting for the answer of a request, they
+    // are handled before the ones in the channel.
+    pending: VecDeque<Response>,
     dispatcher_thread_handle: thread::JoinHandle<ContextMap>,
-    alert_handler: Option<Box<AlertHandler<T>>>
+    alert_handler: Option<Box<AlertHandler<T>>>,
+    output_handlers: BTreeMap<String, Box<AlertHandler<T>>>,
+    error_handler: Option<Box<dyn FnMut(&str)>>,
+    supervisor: Arc<Supervisor>,
+    shutdown_mode: Option<ShutdownMode>,
+    delivery_log: Option<DeliveryLog>
+}
+
+// The options of the dispatcher threads, see CorrelatorBuilder.
+struct DispatcherConfig {
+    clock: Arc<Clock>,
+    tick_interval: Option<Duration>,
+    requests: QueueConfig,
+    alerts: QueueConfig,
+    max_loopback_depth: Option<usize>,
+    memory_budget: Option<MemoryBudget>,
+    max_restarts: usize,
+    checkpoint_interval: u64,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> DispatcherConfig {
+        DispatcherConfig {
+            clock: Arc::new(SystemClock::new()),
+            tick_interval: None,
+            requests: QueueConfig::unbounded(),
+            alerts: QueueConfig::unbounded(),
+            max_loopback_depth: None,
+            memory_budget: None,
+            max_restarts: 0,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+        }
+    }
+}
+
+impl DispatcherConfig {
+    // The shards enforce the budget on their total usage.
+    fn prepare(&self, context_map: &mut ContextMap, usage: Option<Arc<SharedUsage>>) {
+        context_map.set_clock(self.clock.clone());
+        if let Some(depth) = self.max_loopback_depth {
+            context_map.set_max_loopback_depth(depth);
+        }
+        if let Some(ref budget) = self.memory_budget {
+            context_map.set_memory_budget(Some(budget.clone()));
+            context_map.set_shared_usage(usage);
+        }
+    }
+
+    // The checkpoints are needed only by a dispatcher which can be restarted.
+    fn checkpoint_interval(&self) -> Option<u64> {
+        if self.max_restarts > 0 {
+            Some(self.checkpoint_interval)
+        } else {
+            None
+        }
+    }
+
+    fn supervisor(&self) -> Arc<Supervisor> {
+        let supervisor = Supervisor::new();
+        supervisor.set_max_restarts(self.max_restarts);
+        Arc::new(supervisor)
+    }
+
+    // The timer starts when the correlator is created, not when the
+    // dispatcher thread is scheduled.
+    fn demultiplexer(&self, rx: QueueReceiver<Request>) -> Demultiplexer<Request> {
+        let mut dmux = Demultiplexer::with_clock(rx, self.clock.clone());
+        dmux.set_tick_interval(self.tick_interval);
+        dmux
+    }
 }
 
 impl<T> Correlator<T> {
     pub fn new(context_map: ContextMap) -> Correlator<T> {
-        let (dispatcher_input_channel, rx) = mpsc::channel();
-        let (dispatcher_output_channel_tx, dispatcher_output_channel_rx) = mpsc::channel();
-        Timer::from_chan(Duration::from_millis(TIMER_STEP_MS),
-                         dispatcher_input_channel.clone());
+        Correlator::spawn(context_map, DispatcherConfig::default(), None, None)
+    }
 
+    // Partitions the states between shards, each of them is handled by its
+    // own dispatcher thread. The states of a context with context_id are
+    // partitioned by their keys, the other contexts are owned by one shard.
+    // Every shard restores its part of the snapshot.
+    fn spawn_shards(configs: Vec<ContextConfig>,
+                    shards: usize,
+                    config: DispatcherConfig,
+                    snapshot: Option<Snapshot>)
+                    -> Correlator<T> {
+        if shards <= 1 {
+            let mut context_map = ContextMap::from_configs(configs);
+            if let Some(snapshot) = snapshot {
+                let _ = context_map.restore(snapshot);
+            }
+            return Correlator::spawn(context_map, config, None, None);
+        }
+        let (dispatcher_input_channel, rx) = queue::queue_with_clock(config.requests,
+                                                                     config.clock.clone());
+        let (dispatcher_output_channel_tx, dispatcher_output_channel_rx) =
+            queue::queue(config.alerts);
+        let supervisor = config.supervisor();
+        let usage = Arc::new(SharedUsage::new());
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for i in 0..shards {
+            let mut context_map = ContextMap::from_configs(configs.clone());
+            context_map.set_shard(Some(Shard::new(i, shards)));
+            if let Some(ref snapshot) = snapshot {
+                let _ = context_map.restore(snapshot.clone());
+            }
+            config.prepare(&mut context_map, Some(usage.clone()));
+            let (tx, shard_rx) = queue::queue(config.requests);
+            handles.push(Correlator::<T>::spawn_dispatcher(context_map,
+                                                           config.demultiplexer(shard_rx),
+                                                           None,
+                                                           dispatcher_output_channel_tx.clone(),
+                                                           Some(dispatcher_input_channel.clone()),
+                                                           config.checkpoint_interval(),
+                                                           supervisor.clone()));
+            senders.push(tx);
+        }
+        let budget = config.memory_budget.clone();
+        let forwards_time = !config.clock.is_real_time();
         let handle = thread::spawn(move || {
-            let dmux = Demultiplexer::new(rx);
-            let response_sender = Box::new(dispatcher_output_channel_tx);
-
-            let exit_handler = Box::new(ExitEventHandler::default());
-            let timer_event_handler = Box::new(TimerEventHandler::default());
-            let message_event_handler = Box::new(MessageEventHandler::default());
-
-            let mut reactor = RequestReactor::new(dmux, context_map, response_sender);
-            reactor.register_handler(exit_handler);
-            reactor.register_handler(timer_event_handler);
-            reactor.register_handler(message_event_handler);
-            reactor.handle_events();
-            trace!("Correlator: dispatcher thread exited");
-            reactor.context_map
+            Router::new(configs, senders, dispatcher_output_channel_tx, forwards_time).run(rx);
+            let mut context_maps = Vec::new();
+            for i in handles {
+                match i.join() {
+                    Ok(context_map) => context_maps.push(context_map),
+                    Err(error) => panic::resume_unwind(error),
+                }
+            }
+            let mut context_maps = context_maps.into_iter();
+            let mut context_map = context_maps.next().unwrap_or_default();
+            context_map.set_shard(None);
+            context_map.set_shared_usage(None);
+            for i in context_maps {
+                context_map.merge(i);
+            }
+            if budget.is_some() {
+                context_map.set_memory_budget(budget);
+            }
+            context_map
         });
 
+        Correlator::with_dispatcher(dispatcher_input_channel,
+                                    dispatcher_output_channel_rx,
+                                    handle,
+                                    supervisor,
+                                    None)
+    }
+
+    fn spawn(mut context_map: ContextMap,
+             config: DispatcherConfig,
+             journal: Option<Journal>,
+             delivery_log: Option<DeliveryLog>)
+             -> Correlator<T> {
+        let (dispatcher_input_channel, rx) = queue::queue_with_clock(config.requests,
+                                                                     config.clock.clone());
+        let (dispatcher_output_channel_tx, dispatcher_output_channel_rx) =
+            queue::queue(config.alerts);
+        let supervisor = config.supervisor();
+        config.prepare(&mut context_map, None);
+        let handle = Correlator::<T>::spawn_dispatcher(context_map,
+                                                       config.demultiplexer(rx),
+                                                       journal,
+                                                       dispatcher_output_channel_tx,
+                                                       None,
+                                                       config.checkpoint_interval(),
+                                                       supervisor.clone());
+
+        Correlator::with_dispatcher(dispatcher_input_channel,
+                                    dispatcher_output_channel_rx,
+                                    handle,
+                                    supervisor,
+                                    delivery_log)
+    }
+
+    fn with_dispatcher(dispatcher_input_channel: QueueSender<Request>,
+                       dispatcher_output_channel: QueueReceiver<Response>,
+                       dispatcher_thread_handle: thread::JoinHandle<ContextMap>,
+                       supervisor: Arc<Supervisor>,
+                       delivery_log: Option<DeliveryLog>)
+                       -> Correlator<T> {
         Correlator {
+            handle: CorrelatorHandle::new(dispatcher_input_channel.clone()),
             dispatcher_input_channel: dispatcher_input_channel,
-            dispatcher_output_channel: dispatcher_output_channel_rx,
-            dispatcher_thread_handle: handle,
-            alert_handler: None
+            dispatcher_output_channel: dispatcher_output_channel,
+            pending: VecDeque::new(),
+            dispatcher_thread_handle: dispatcher_thread_handle,
+            alert_handler: None,
+            output_handlers: BTreeMap::new(),
+            error_handler: None,
+            supervisor: supervisor,
+            shutdown_mode: None,
+            delivery_log: delivery_log
         }
     }
 
+    fn spawn_dispatcher(context_map: ContextMap,
+                        dmux: Demultiplexer<Request>,
+                        journal: Option<Journal>,
+                        response_sender: QueueSender<Response>,
+                        loopback: Option<QueueSender<Request>>,
+                        checkpoint_interval: Option<u64>,
+                        supervisor: Arc<Supervisor>)
+                        -> thread::JoinHandle<ContextMap> {
+        thread::spawn(move || {
+            let error_sender = response_sender.clone();
+            let response_sender = Box::new(response_sender);
+            let mut engine = Engine::new(context_map);
+            engine.set_loopback_channel(loopback);
+            let mut reactor = RequestReactor::new(dmux, engine, response_sender);
+            reactor.set_journal(journal);
+            reactor.set_checkpoint_interval(checkpoint_interval);
+            // A restarted dispatcher gets the states of the last checkpoint, so
+            // a request which panicked halfway doesn't leave its changes
+            // behind. The request itself is dropped, the ones handled after the
+            // checkpoint are lost, their alerts are not sent again.
+            while let Err(error) = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                reactor.handle_events()
+            })) {
+                let message = supervisor::panic_message(&error);
+                error!("Correlator: dispatcher thread panicked; error={}", message);
+                let _ = error_sender.send(Response::Error(message.clone()));
+                if !supervisor.on_panic(message) {
+                    panic::resume_unwind(error);
+                }
+                let lost = reactor.rollback();
+                if lost > 0 {
+                    warn!("Correlator: the requests handled after the last checkpoint are lost; \
+                           lost_requests={}",
+                          lost);
+                    let error = format!("the requests handled after the last checkpoint are \
+                                         lost; lost_requests={}",
+                                        lost);
+                    let _ = error_sender.send(Response::Error(error));
+                }
+                warn!("Correlator: restarting the dispatcher; restarts={}", supervisor.restarts());
+            }
+            supervisor.on_stopped();
+            trace!("Correlator: dispatcher thread exited");
+            reactor.engine.into_context_map()
+        })
+    }
+
     pub fn set_alert_handler(&mut self,
                             handler: Option<Box<AlertHandler<T>>>) {
         self.alert_handler = handler;
     }
 
-    pub fn push_message(&mut self, message: Message) -> Result<(), mpsc::SendError<Request>> {
-        self.dispatcher_input_channel.send(Request::Message(Arc::new(message)))
+    // The alerts of the actions with this output are passed to the handler
+    // instead of the default alert handler. None removes the handler of the
+    // output.
+    pub fn set_output_handler<S: Into<String>>(&mut self,
+                                               output: S,
+                                               handler: Option<Box<AlertHandler<T>>>) {
+        let output = output.into();
+        match handler {
+            Some(handler) => {
+                self.output_handlers.insert(output, handler);
+            }
+            None => {
+                self.output_handlers.remove(&output);
+            }
+        }
     }
 
-    fn handle_event(&mut self, event: Response, external_handler_data: &mut T) {
+    pub fn set_output_callback<S, F>(&mut self, output: S, handler: F)
+        where S: Into<String>,
+              F: FnMut(Alert) + 'static
+    {
+        self.set_output_handler(output, Some(Box::new(FnAlertHandler(handler))));
+    }
+
+    pub fn status(&self) -> Status {
+        self.supervisor.status()
+    }
+
+    // The number of times a panicked dispatcher was restarted.
+    pub fn restarts(&self) -> usize {
+        self.supervisor.restarts()
+    }
+
+    // The message of the last panic of the dispatcher.
+    pub fn last_error(&self) -> Option<String> {
+        self.supervisor.last_error()
+    }
+
+    // The handler is called with the errors of the dispatcher by
+    // handle_events() and the other methods receiving alerts.
+    pub fn set_error_callback<F>(&mut self, handler: F)
+        where F: FnMut(&str) + 'static
+    {
+        self.error_handler = Some(Box::new(handler));
+    }
+
+    // The handle can send messages and control requests from other threads.
+    pub fn handle(&self) -> CorrelatorHandle {
+        self.handle.clone()
+    }
+
+    pub fn push_message(&mut self, message: Message) -> Result<(), mpsc::TrySendError<Request>> {
+        self.handle.push_message(message)
+    }
+
+    pub fn push_messages<I>(&mut self, messages: I) -> Result<(), mpsc::TrySendError<Request>>
+        where I: IntoIterator<Item = Message>
+    {
+        self.handle.push_messages(messages)
+    }
+
+    // Waits until every request sent before is handled by the dispatcher, then
+    // handles the resulting alerts. The time a ManualClock was advanced by is
+    // applied before, so the timeouts can be tested deterministically.
+    pub fn sync(&mut self, external_handler_data: &mut T) {
+        let (tx, rx) = mpsc::channel();
+        if self.dispatcher_input_channel.send(Request::Sync(tx)).is_ok() {
+            let _ = self.wait_for(rx, external_handler_data);
+        }
+        self.handle_events(external_handler_data);
+    }
+
+    // The alerts are handled while waiting, so the dispatcher is not blocked
+    // by a full alert queue.
+    fn wait_for<R>(&mut self, rx: mpsc::Receiver<R>, external_handler_data: &mut T) -> Option<R> {
+        loop {
+            self.handle_events(external_handler_data);
+            match rx.recv_timeout(Duration::from_millis(SYNC_POLL_INTERVAL_MS)) {
+                Ok(value) => return Some(value),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    // Like wait_for(), but the responses are kept for the next call which
+    // handles or returns the alerts.
+    fn wait_draining<R>(&mut self, rx: mpsc::Receiver<R>) -> Option<R> {
+        loop {
+            while let Ok(event) = self.dispatcher_output_channel.try_recv() {
+                self.pending.push_back(event);
+            }
+            match rx.recv_timeout(Duration::from_millis(SYNC_POLL_INTERVAL_MS)) {
+                Ok(value) => return Some(value),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Option<Response> {
+        self.pending.pop_front().or_else(|| self.dispatcher_output_channel.try_recv().ok())
+    }
+
+    pub fn request_queue_stats(&self) -> QueueStats {
+        self.dispatcher_input_channel.stats()
+    }
+
+    pub fn alert_queue_stats(&self) -> QueueStats {
+        self.dispatcher_output_channel.stats()
+    }
+
+    // Returns None if the dispatcher thread is not running. The alerts
+    // received while waiting are kept, so a full alert queue doesn't block the
+    // dispatcher.
+    pub fn snapshot(&mut self) -> Option<Snapshot> {
+        let (tx, rx) = mpsc::channel();
+        if self.dispatcher_input_channel.send(Request::Snapshot(tx)).is_err() {
+            return None;
+        }
+        self.wait_draining(rx)
+    }
+
+    // Closes the selected states and executes their actions.
+    pub fn close(&mut self, selector: Selector) -> Result<(), mpsc::SendError<Request>> {
+        self.handle.close(selector)
+    }
+
+    // Drops the selected states without executing their actions.
+    pub fn discard(&mut self, selector: Selector) -> Result<(), mpsc::SendError<Request>> {
+        self.handle.discard(selector)
+    }
+
+    // Replaces the contexts while keeping the states of the contexts with the
+    // same uuid. The states of the removed contexts are closed by mode.
+    // Returns None if the dispatcher thread is not running.
+    pub fn reload(&mut self, configs: Vec<ContextConfig>, mode: CloseMode) -> Option<ReloadReport> {
+        let (tx, rx) = mpsc::channel();
+        if self.dispatcher_input_channel.send(Request::Reload(configs, mode, tx)).is_err() {
+            return None;
+        }
+        self.wait_draining(rx)
+    }
+
+    // Returns None if the dispatcher thread is not running.
+    pub fn query(&mut self) -> Option<Vec<ContextInfo>> {
+        let (tx, rx) = mpsc::channel();
+        if self.dispatcher_input_channel.send(Request::Query(tx)).is_err() {
+            return None;
+        }
+        self.wait_draining(rx)
+    }
+
+    // Handles the Exit responses and returns the alerts.
+    fn receive(&mut self, event: Response) -> Option<Alert> {
         match event {
             Response::Exit => {
                 let _ = self.dispatcher_input_channel.send(Request::Exit);
-            },
+                None
+            }
+            Response::Error(error) => {
+                error!("Correlator: dispatcher failed; error={}", error);
+                if let Some(handler) = self.error_handler.as_mut() {
+                    handler(&error);
+                }
+                None
+            }
             Response::Alert(alert) => {
-                if let Some(handler) = self.alert_handler.as_mut() {
-                    handler.on_alert(alert, &mut self.dispatcher_input_channel, external_handler_data);
-                } else {
-                    trace!("No Alert handler is registereted in Correlator but an alert is received");
+                if let Some(delivery_log) = self.delivery_log.as_mut() {
+                    delivery_log.on_delivered();
                 }
+                Some(alert)
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Response, external_handler_data: &mut T) {
+        let alert = match event {
+            Response::Alert(alert) => alert,
+            event => {
+                let _ = self.receive(event);
+                return;
+            }
+        };
+        // The delivery is recorded before the alert is handed over, so it is
+        // not sent again after a crash.
+        if let Some(delivery_log) = self.delivery_log.as_mut() {
+            delivery_log.on_delivered();
+        }
+        // The alerts of unknown outputs are handled by the default handler.
+        let handler = match alert.output {
+            Some(ref output) if self.output_handlers.contains_key(output) => {
+                self.output_handlers.get_mut(output)
             }
+            _ => self.alert_handler.as_mut(),
+        };
+        if let Some(handler) = handler {
+            handler.on_alert(alert, &mut self.dispatcher_input_channel, external_handler_data);
+        } else {
+            trace!("No Alert handler is registereted in Correlator but an alert is received");
         }
     }
 
+    // Passes every alert received so far to the alert handler.
     pub fn handle_events(&mut self, external_handler_data: &mut T) {
-        for i in self.dispatcher_output_channel.try_recv() {
-            self.handle_event(i, external_handler_data);
+        while let Some(event) = self.next_event() {
+            self.handle_event(event, external_handler_data);
+        }
+    }
+
+    // The alerts are passed to the handler instead of an AlertHandler.
+    pub fn set_alert_callback<F>(&mut self, handler: F)
+        where F: FnMut(Alert) + 'static
+    {
+        self.alert_handler = Some(Box::new(FnAlertHandler(handler)));
+    }
+
+    // Returns None if no alert is received yet. The alert handler is not
+    // called for the returned alerts.
+    pub fn try_recv_alert(&mut self) -> Option<Alert> {
+        while let Some(event) = self.next_event() {
+            if let Some(alert) = self.receive(event) {
+                return Some(alert);
+            }
+        }
+        None
+    }
+
+    // Waits at most timeout for the next alert. Returns None if no alert is
+    // received in time or the dispatcher stopped.
+    pub fn recv_alert_timeout(&mut self, timeout: Duration) -> Option<Alert> {
+        if let Some(alert) = self.try_recv_alert() {
+            return Some(alert);
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            let remaining = if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            };
+            match self.dispatcher_output_channel.recv_timeout(remaining) {
+                Ok(event) => {
+                    if let Some(alert) = self.receive(event) {
+                        return Some(alert);
+                    }
+                }
+                Err(_) => return None,
+            }
         }
     }
 
-    pub fn stop(mut self, external_handler_data: &mut T) -> thread::Result<ContextMap> {
+    // Returns every alert received so far.
+    pub fn drain_alerts(&mut self) -> Vec<Alert> {
+        self.alerts().collect()
+    }
+
+    pub fn alerts(&mut self) -> Alerts<T> {
+        Alerts { correlator: self }
+    }
+
+    // The open states are left in the returned map, unless a shutdown mode is
+    // set which flushes, discards or persists them.
+    pub fn stop(mut self, external_handler_data: &mut T) -> thread::Result<Stopped> {
+        let snapshot = match self.shutdown_mode {
+            Some(mode) => self.empty(mode, external_handler_data),
+            None => None,
+        };
         self.handle_events(external_handler_data);
         self.stop_dispatcher(external_handler_data);
-        self.dispatcher_thread_handle.join()
+        let context_map = try!(self.dispatcher_thread_handle.join());
+        Ok(Stopped {
+            context_map: context_map,
+            snapshot: match self.shutdown_mode {
+                Some(ShutdownMode::Persist) => Some(snapshot.unwrap_or_default()),
+                _ => None,
+            },
+        })
+    }
+
+    // The mode is applied by stop(). Persist returns the states in the
+    // snapshot of Stopped.
+    pub fn set_shutdown_mode(&mut self, mode: Option<ShutdownMode>) {
+        self.shutdown_mode = mode;
+    }
+
+    // Empties the contexts by mode before stopping the dispatcher. The alerts
+    // of the flushed states are handled before this returns, the persisted
+    // states are returned in a snapshot, which can be serialized and loaded
+    // by a later correlator.
+    pub fn shutdown(mut self,
+                    mode: ShutdownMode,
+                    external_handler_data: &mut T)
+                    -> thread::Result<Snapshot> {
+        let snapshot = self.empty(mode, external_handler_data);
+        self.shutdown_mode = None;
+        try!(self.stop(external_handler_data));
+        Ok(snapshot.unwrap_or_default())
+    }
+
+    fn empty(&mut self, mode: ShutdownMode, external_handler_data: &mut T) -> Option<Snapshot> {
+        let (tx, rx) = mpsc::channel();
+        if self.dispatcher_input_channel.send(Request::Shutdown(mode, tx)).is_ok() {
+            self.wait_for(rx, external_handler_data)
+        } else {
+            None
+        }
     }
 
     fn stop_dispatcher(&mut self, external_handler_data: &mut T) {
         let _ = self.dispatcher_input_channel.send(Request::Exit);
+        while let Some(event) = self.pending.pop_front() {
+            self.handle_event(event, external_handler_data);
+        }
         while let Ok(event) = self.dispatcher_output_channel.recv() {
             self.handle_event(event, external_handler_data);
         }
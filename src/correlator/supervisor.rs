@@ -0,0 +1,100 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::any::Any;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    // The dispatcher thread panicked and it wasn't restarted, so the requests
+    // are not handled any more.
+    Failed(String),
+    Stopped,
+}
+
+struct Health {
+    status: Status,
+    restarts: usize,
+    max_restarts: usize,
+    last_error: Option<String>,
+}
+
+// The health of the dispatcher threads of a Correlator, shared with them.
+pub struct Supervisor {
+    health: Mutex<Health>,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            health: Mutex::new(Health {
+                status: Status::Running,
+                restarts: 0,
+                max_restarts: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.health.lock().unwrap().status.clone()
+    }
+
+    pub fn restarts(&self) -> usize {
+        self.health.lock().unwrap().restarts
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.health.lock().unwrap().last_error.clone()
+    }
+
+    pub fn max_restarts(&self) -> usize {
+        self.health.lock().unwrap().max_restarts
+    }
+
+    pub fn set_max_restarts(&self, max_restarts: usize) {
+        self.health.lock().unwrap().max_restarts = max_restarts;
+    }
+
+    // Returns true if the dispatcher should be restarted.
+    pub fn on_panic(&self, error: String) -> bool {
+        let mut health = self.health.lock().unwrap();
+        health.last_error = Some(error.clone());
+        if health.restarts < health.max_restarts {
+            health.restarts += 1;
+            true
+        } else {
+            health.status = Status::Failed(error);
+            false
+        }
+    }
+
+    pub fn on_stopped(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.status == Status::Running {
+            health.status = Status::Stopped;
+        }
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Supervisor {
+        Supervisor::new()
+    }
+}
+
+pub fn panic_message(error: &Box<Any + Send>) -> String {
+    if let Some(message) = error.downcast_ref::<&'static str>() {
+        (*message).to_owned()
+    } else if let Some(message) = error.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
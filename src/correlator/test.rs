@@ -7,19 +7,28 @@
 // modified, or distributed except according to those terms.
 
 use config::{ContextConfigBuilder, ContextConfig};
-use config::action::message::MessageActionBuilder;
+use config::action::message::{InjectMode, MessageActionBuilder};
 use conditions::ConditionsBuilder;
-use correlator::Correlator;
+use correlator::{Correlator, CorrelatorBuilder, Error, Status};
 use context::ContextMap;
+use control::{CloseMode, Selector, ShutdownMode};
+use correlator::CorrelatorFactory;
+use journal::JournalConfig;
 use message::MessageBuilder;
 use action::Alert;
 
 use uuid::Uuid;
 use serde_json::from_str;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use test_utils::MockAlertHandler;
+use test_utils::{ManualClock, MockAlertHandler};
 
 const JSON_CONFIG: &'static str = r#"
       [
@@ -186,3 +195,784 @@ fn test_given_correlator_when_it_is_built_from_json_then_it_produces_the_expecte
     println!("{:?}", &responses);
     assert_eq!(5, responses.len());
 }
+
+#[test]
+fn test_given_correlator_when_a_snapshot_is_requested_then_it_contains_the_open_states() {
+    let uuid = Uuid::new_v4();
+    let condition = ConditionsBuilder::new(Duration::from_millis(10000)).build();
+    let contexts = vec![ContextConfigBuilder::new(uuid, condition).patterns(vec!["p1".to_owned()]).build()];
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    let snapshot = correlator.snapshot().expect("Failed to get a snapshot from a running Correlator");
+    assert_eq!(1, snapshot.contexts.len());
+    assert_eq!(uuid, snapshot.contexts[0].uuid);
+    assert_eq!(1, snapshot.contexts[0].states[0].messages.len());
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_correlator_when_it_is_queried_then_it_returns_the_open_contexts() {
+    let uuid = Uuid::new_v4();
+    let condition = ConditionsBuilder::new(Duration::from_millis(10000)).build();
+    let contexts = vec![ContextConfigBuilder::new(uuid, condition)
+                            .name("LOGIN".to_owned())
+                            .patterns(vec!["p1".to_owned()])
+                            .build()];
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    let contexts = correlator.query().expect("Failed to query a running Correlator");
+    assert_eq!(1, contexts.len());
+    assert_eq!(Some("LOGIN".to_owned()), contexts[0].name);
+    assert_true!(contexts[0].open);
+    assert_eq!(1, contexts[0].states[0].message_count);
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_correlator_when_a_context_is_closed_then_its_actions_are_executed() {
+    let uuid = Uuid::new_v4();
+    let condition = ConditionsBuilder::new(Duration::from_millis(10000)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let contexts = vec![ContextConfigBuilder::new(uuid, condition)
+                            .patterns(vec!["p1".to_owned()])
+                            .actions(vec![action.into()])
+                            .build()];
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    let _ = correlator.close(Selector::Context(uuid));
+    let contexts = correlator.query().expect("Failed to query a running Correlator");
+    assert_false!(contexts[0].open);
+    let _ = correlator.stop(&mut responses);
+    assert_eq!(1, responses.len());
+}
+
+fn reload_config(uuid: &Uuid, pattern: &str) -> String {
+    format!(r#"[{{"uuid": "{}", "patterns": ["{}"], "conditions": {{"timeout": 10000}}}}]"#,
+            uuid.to_simple_string(),
+            pattern)
+}
+
+#[test]
+fn test_given_correlator_when_it_is_reloaded_then_the_open_states_are_kept() {
+    let uuid = Uuid::new_v4();
+    let condition = ConditionsBuilder::new(Duration::from_millis(10000)).build();
+    let contexts = vec![ContextConfigBuilder::new(uuid, condition).patterns(vec!["p1".to_owned()]).build()];
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    let configs = from_str::<Vec<ContextConfig>>(&reload_config(&uuid, "p2"))
+                      .expect("Failed to deserialize a config::ContextConfig from JSON");
+    let report = correlator.reload(configs, CloseMode::Execute).expect("Failed to reload a running Correlator");
+    assert_eq!(vec![uuid], report.kept);
+    let _ = correlator.push_message(MessageBuilder::new("p2", "message").build());
+    let contexts = correlator.query().expect("Failed to query a running Correlator");
+    assert_eq!(2, contexts[0].states[0].message_count);
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_config_watcher_when_the_config_file_changes_then_the_correlator_is_reloaded() {
+    let path = env::temp_dir().join(format!("correlation-{}.json", Uuid::new_v4().to_simple_string()));
+    let uuid = Uuid::new_v4();
+    let added = Uuid::new_v4();
+    write_file(&path, &reload_config(&uuid, "p1"));
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorFactory::from_path(&path)
+                                                     .expect("Failed to load the configuration");
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors_clone = errors.clone();
+    correlator.set_error_callback(move |error| errors_clone.lock().unwrap().push(error.to_owned()));
+    let watcher = CorrelatorFactory::watch(&correlator, &path, Duration::from_millis(10), CloseMode::Execute);
+    thread::sleep(Duration::from_millis(50));
+    write_file(&path, &reload_config(&added, "p1"));
+    let mut reloaded = false;
+    for _ in 0..100 {
+        let contexts = correlator.query().expect("Failed to query a running Correlator");
+        if contexts[0].uuid == added {
+            reloaded = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    watcher.stop();
+    correlator.handle_events(&mut responses);
+    let _ = correlator.stop(&mut responses);
+    let _ = fs::remove_file(&path);
+    assert_true!(reloaded);
+    assert_eq!(vec!["contexts are reloaded; added=1 removed=1 kept=0".to_owned()],
+               *errors.lock().unwrap());
+}
+
+#[test]
+fn test_given_config_watcher_when_it_is_stopped_then_it_does_not_wait_for_the_interval() {
+    let path = env::temp_dir().join(format!("correlation-{}.json", Uuid::new_v4().to_simple_string()));
+    write_file(&path, &reload_config(&Uuid::new_v4(), "p1"));
+    let mut responses = Vec::new();
+    let correlator: Correlator<Vec<Alert>> = CorrelatorFactory::from_path(&path)
+                                                 .expect("Failed to load the configuration");
+    let watcher = CorrelatorFactory::watch(&correlator, &path, Duration::from_secs(3600), CloseMode::Execute);
+    let started = Instant::now();
+    watcher.stop();
+    assert_true!(started.elapsed() < Duration::from_secs(10));
+    let _ = correlator.stop(&mut responses);
+    let _ = fs::remove_file(&path);
+}
+
+fn write_file(path: &Path, content: &str) {
+    let mut file = File::create(path).expect("Failed to create the configuration file");
+    file.write_all(content.as_bytes()).expect("Failed to write the configuration file");
+}
+
+#[test]
+fn test_given_correlator_with_manual_clock_when_the_clock_is_advanced_then_the_timeouts_expire() {
+    let uuid = Uuid::new_v4();
+    let condition = ConditionsBuilder::new(Duration::from_millis(100)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let contexts = vec![ContextConfigBuilder::new(uuid, condition)
+                            .patterns(vec!["p1".to_owned()])
+                            .actions(vec![action.into()])
+                            .build()];
+    let clock = ManualClock::new();
+    let mut responses = Vec::new();
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(contexts).clock(Arc::new(clock.clone())).build().unwrap();
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    clock.advance(Duration::from_millis(99));
+    correlator.sync(&mut responses);
+    assert_true!(responses.is_empty());
+    clock.advance(Duration::from_millis(1));
+    correlator.sync(&mut responses);
+    assert_eq!(1, responses.len());
+    let _ = correlator.stop(&mut responses);
+}
+
+fn sharded_contexts(linear: Uuid, map: Uuid) -> Vec<ContextConfig> {
+    let condition = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    vec![ContextConfigBuilder::new(linear, condition.clone())
+             .patterns(vec!["LOGIN".to_owned()])
+             .actions(vec![action.clone().into()])
+             .build(),
+         ContextConfigBuilder::new(map, condition)
+             .patterns(vec!["LOGIN".to_owned()])
+             .context_id(Some(vec!["user".into()]))
+             .actions(vec![action.into()])
+             .build()]
+}
+
+#[test]
+fn test_given_sharded_correlator_when_it_is_queried_then_the_states_of_the_shards_are_merged() {
+    let linear = Uuid::new_v4();
+    let map = Uuid::new_v4();
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(sharded_contexts(linear, map)).threads(4).build().unwrap();
+    for i in 0..20 {
+        let user = format!("user{}", i);
+        let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login")
+                                            .pair("user", &user)
+                                            .build());
+    }
+    let contexts = correlator.query().expect("Failed to query the correlator");
+    assert_eq!(2, contexts.len());
+    let linear_info = contexts.iter().find(|context| context.uuid == linear).unwrap();
+    assert_eq!(1, linear_info.states.len());
+    assert_eq!(20, linear_info.states[0].message_count);
+    let map_info = contexts.iter().find(|context| context.uuid == map).unwrap();
+    assert_eq!(20, map_info.states.len());
+    assert_eq!(21, correlator.snapshot().unwrap().contexts.iter().map(|context| context.states.len()).sum::<usize>());
+    let mut responses = Vec::new();
+    let context_map = correlator.stop(&mut responses).expect("Failed to stop the correlator").context_map;
+    assert_true!(responses.is_empty());
+    assert_eq!(21, context_map.snapshot().contexts.iter().map(|context| context.states.len()).sum::<usize>());
+}
+
+#[test]
+fn test_given_sharded_correlator_when_its_states_are_closed_then_the_alerts_of_every_shard_are_received
+    () {
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(sharded_contexts(Uuid::new_v4(), Uuid::new_v4()))
+            .threads(3)
+            .build()
+            .unwrap();
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    for i in 0..10 {
+        let user = format!("user{}", i);
+        let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login")
+                                            .pair("user", &user)
+                                            .build());
+    }
+    let _ = correlator.close(Selector::All);
+    let mut responses = Vec::new();
+    correlator.sync(&mut responses);
+    assert_eq!(11, responses.len());
+    let _ = correlator.stop(&mut responses);
+    assert_eq!(11, responses.len());
+}
+
+fn login_contexts() -> Vec<ContextConfig> {
+    let condition = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+             .patterns(vec!["LOGIN".to_owned()])
+             .context_id(Some(vec!["user".into()]))
+             .actions(vec![action.into()])
+             .build()]
+}
+
+// Sends a message for every user, then closes their states. The alerts are
+// in the channel when the query returns.
+fn close_logins(correlator: &mut Correlator<Vec<Alert>>, users: usize) {
+    for i in 0..users {
+        let user = format!("user{}", i);
+        let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login")
+                                            .pair("user", &user)
+                                            .build());
+    }
+    let _ = correlator.close(Selector::All);
+    let _ = correlator.query();
+}
+
+#[test]
+fn test_given_correlator_with_pending_alerts_when_events_are_handled_then_every_alert_is_handled() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    close_logins(&mut correlator, 3);
+    let mut responses = Vec::new();
+    correlator.handle_events(&mut responses);
+    assert_eq!(3, responses.len());
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_correlator_with_pending_alerts_when_they_are_drained_then_every_alert_is_returned() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    close_logins(&mut correlator, 3);
+    assert_eq!(3, correlator.drain_alerts().len());
+    assert_true!(correlator.try_recv_alert().is_none());
+    close_logins(&mut correlator, 2);
+    assert_eq!(2, correlator.alerts().count());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_correlator_when_an_alert_is_waited_for_then_it_is_returned_when_it_arrives() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    assert_true!(correlator.recv_alert_timeout(Duration::from_millis(10)).is_none());
+    let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login").pair("user", "alice").build());
+    let _ = correlator.close(Selector::All);
+    let alert = correlator.recv_alert_timeout(Duration::from_secs(5)).expect("Failed to receive an alert");
+    assert_eq!("CLOSED", alert.message.uuid());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_correlator_with_alert_callback_when_events_are_handled_then_the_callback_is_called() {
+    use std::sync::Mutex;
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut correlator: Correlator<()> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    {
+        let received = received.clone();
+        correlator.set_alert_callback(move |alert| received.lock().unwrap().push(alert));
+    }
+    for i in 0..2 {
+        let user = format!("user{}", i);
+        let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login").pair("user", &user).build());
+    }
+    let _ = correlator.close(Selector::All);
+    let _ = correlator.stop(&mut ());
+    assert_eq!(2, received.lock().unwrap().len());
+}
+
+#[test]
+fn test_given_correlator_handles_when_messages_are_pushed_from_many_threads_then_every_message_is_handled
+    () {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let threads = (0..4)
+                      .map(|i| {
+                          let handle = correlator.handle();
+                          thread::spawn(move || {
+                              for j in 0..10 {
+                                  let user = format!("user{}-{}", i, j);
+                                  let message = MessageBuilder::new("LOGIN", "login")
+                                                    .pair("user", &user)
+                                                    .build();
+                                  handle.push_message(message).expect("Failed to push a message");
+                              }
+                          })
+                      })
+                      .collect::<Vec<thread::JoinHandle<()>>>();
+    for i in threads {
+        i.join().expect("Failed to join a producer thread");
+    }
+    let handle = correlator.handle();
+    assert_eq!(40, handle.query().unwrap()[0].states.len());
+    let _ = handle.close(Selector::All);
+    let mut responses = Vec::new();
+    let _ = correlator.stop(&mut responses);
+    assert_eq!(40, responses.len());
+}
+
+#[test]
+fn test_given_correlator_with_bounded_alert_queue_when_it_is_full_then_the_new_alerts_are_dropped() {
+    use queue::{OverflowPolicy, QueueConfig};
+
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(login_contexts())
+            .alert_queue(QueueConfig::bounded(2, OverflowPolicy::DropNewest))
+            .build()
+            .unwrap();
+    close_logins(&mut correlator, 5);
+    assert_eq!(3, correlator.alert_queue_stats().dropped);
+    assert_eq!(2, correlator.drain_alerts().len());
+    assert_eq!(0, correlator.request_queue_stats().dropped);
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_correlator_with_full_blocking_alert_queue_when_a_snapshot_is_requested_then_it_is_answered() {
+    use queue::{OverflowPolicy, QueueConfig};
+
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(login_contexts())
+            .alert_queue(QueueConfig::bounded(1, OverflowPolicy::Block))
+            .build()
+            .unwrap();
+    close_logins(&mut correlator, 5);
+    let snapshot = correlator.snapshot().expect("Failed to get a snapshot from a running Correlator");
+    assert_true!(snapshot.contexts.iter().all(|context| context.states.is_empty()));
+    assert_eq!(5, correlator.drain_alerts().len());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_correlator_behind_its_queue_when_a_state_expired_before_a_message_then_it_is_closed_before_the_message
+    () {
+    let condition = ConditionsBuilder::new(Duration::from_millis(100)).build();
+    let action = MessageActionBuilder::new("CLOSED", "closed").build();
+    let contexts = vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                            .patterns(vec!["LOGIN".to_owned()])
+                            .actions(vec![action.into()])
+                            .build()];
+    let clock = ManualClock::new();
+    let mut correlator: Correlator<Vec<Alert>> =
+        CorrelatorBuilder::from_configs(contexts).clock(Arc::new(clock.clone())).build().unwrap();
+    let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login").build());
+    clock.advance(Duration::from_millis(150));
+    let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login").build());
+    let mut responses = Vec::new();
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    correlator.sync(&mut responses);
+    assert_eq!(1, responses.len());
+    let contexts = correlator.query().unwrap();
+    assert_eq!(1, contexts[0].states.len());
+    assert_eq!(1, contexts[0].states[0].message_count);
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_correlator_when_messages_are_pushed_in_a_batch_then_they_are_handled_in_order() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let messages = (0..1000).map(|i| {
+        let user = format!("user{}", i % 10);
+        MessageBuilder::new("LOGIN", "login").pair("user", &user).build()
+    });
+    let _ = correlator.push_messages(messages);
+    let contexts = correlator.query().unwrap();
+    assert_eq!(10, contexts[0].states.len());
+    assert_true!(contexts[0].states.iter().all(|state| state.message_count == 100));
+    let _ = correlator.close(Selector::All);
+    let mut responses = Vec::new();
+    let _ = correlator.stop(&mut responses);
+    assert_eq!(10, responses.len());
+}
+
+fn push_logins(correlator: &mut Correlator<Vec<Alert>>, users: usize) {
+    for i in 0..users {
+        let user = format!("user{}", i);
+        let _ = correlator.push_message(MessageBuilder::new("LOGIN", "login")
+                                            .pair("user", &user)
+                                            .build());
+    }
+}
+
+#[test]
+fn test_given_correlator_with_open_states_when_it_is_flushed_then_their_actions_are_executed() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    push_logins(&mut correlator, 3);
+    let mut responses = Vec::new();
+    let snapshot = correlator.shutdown(ShutdownMode::Flush, &mut responses).unwrap();
+    assert_true!(snapshot.contexts.is_empty());
+    assert_eq!(3, responses.len());
+    assert_true!(responses.iter()
+                          .all(|alert| alert.message.get("context_close_reason") == Some(&"shutdown".to_owned())));
+}
+
+#[test]
+fn test_given_correlator_with_open_states_when_they_are_discarded_at_shutdown_then_no_action_is_executed() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    push_logins(&mut correlator, 3);
+    let mut responses = Vec::new();
+    let snapshot = correlator.shutdown(ShutdownMode::Discard, &mut responses).unwrap();
+    assert_true!(snapshot.contexts.is_empty());
+    assert_true!(responses.is_empty());
+}
+
+#[test]
+fn test_given_correlator_with_open_states_when_they_are_persisted_then_they_can_be_restored() {
+    let contexts = login_contexts();
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts.clone()));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    push_logins(&mut correlator, 3);
+    let mut responses = Vec::new();
+    let snapshot = correlator.shutdown(ShutdownMode::Persist, &mut responses).unwrap();
+    assert_true!(responses.is_empty());
+    assert_eq!(3, snapshot.contexts[0].states.len());
+    let mut context_map = ContextMap::from_configs(contexts);
+    assert_true!(context_map.restore(snapshot).is_empty());
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(context_map);
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let _ = correlator.shutdown(ShutdownMode::Flush, &mut responses);
+    assert_eq!(3, responses.len());
+}
+
+#[test]
+fn test_given_sharded_correlator_when_a_loopback_alert_is_generated_then_every_shard_receives_it() {
+    let login = {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(1).build();
+        let action = MessageActionBuilder::new("SEEN", "seen").inject_mode(InjectMode::Loopback).build();
+        ContextConfigBuilder::new(Uuid::new_v4(), condition)
+            .patterns(vec!["LOGIN".to_owned()])
+            .context_id(Some(vec!["user".into()]))
+            .actions(vec![action.into()])
+            .build()
+    };
+    let seen = {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(3).build();
+        let action = MessageActionBuilder::new("DONE", "done").build();
+        ContextConfigBuilder::new(Uuid::new_v4(), condition)
+            .patterns(vec!["SEEN".to_owned()])
+            .actions(vec![action.into()])
+            .build()
+    };
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(vec![login, seen])
+                                                     .threads(4)
+                                                     .build()
+                                                     .unwrap();
+    push_logins(&mut correlator, 3);
+    let alert = correlator.recv_alert_timeout(Duration::from_secs(10)).expect("The loopback messages are lost");
+    assert_eq!("DONE", alert.message.uuid());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_correlator_with_output_handlers_when_alerts_are_generated_then_they_are_routed_by_their_output() {
+    let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(1).build();
+    let actions = vec![MessageActionBuilder::new("PAGE", "page").output(Some("pager")).build().into(),
+                       MessageActionBuilder::new("ARCHIVE", "archive").output(Some("archive")).build().into(),
+                       MessageActionBuilder::new("LOG", "log").build().into()];
+    let contexts = vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                            .patterns(vec!["p1".to_owned()])
+                            .actions(actions)
+                            .build()];
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(contexts));
+    correlator.set_alert_handler(Some(Box::new(MockAlertHandler)));
+    let (tx, pager) = mpsc::channel();
+    correlator.set_output_handler("pager", Some(Box::new(tx)));
+    let _ = correlator.push_message(MessageBuilder::new("p1", "message").build());
+    let mut responses = Vec::new();
+    let _ = correlator.stop(&mut responses);
+    let pages = pager.try_iter().collect::<Vec<Alert>>();
+    assert_eq!(1, pages.len());
+    assert_eq!("PAGE", pages[0].message.uuid());
+    let uuids = responses.iter().map(|alert| alert.message.uuid().clone()).collect::<Vec<String>>();
+    assert_eq!(vec!["ARCHIVE".to_owned(), "LOG".to_owned()], uuids);
+}
+
+fn panicking_selector() -> Selector {
+    Selector::matching(|_, _| panic!("selector failed"))
+}
+
+#[test]
+fn test_given_correlator_when_its_dispatcher_panics_then_the_failure_is_reported() {
+    let mut correlator: Correlator<Vec<Alert>> = Correlator::new(ContextMap::from_configs(login_contexts()));
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors_clone = errors.clone();
+    correlator.set_error_callback(move |error| errors_clone.lock().unwrap().push(error.to_owned()));
+    push_logins(&mut correlator, 1);
+    assert_eq!(Status::Running, correlator.status());
+    let _ = correlator.close(panicking_selector());
+    let mut responses = Vec::new();
+    correlator.sync(&mut responses);
+    assert_eq!(Status::Failed("selector failed".to_owned()), correlator.status());
+    assert_eq!(vec!["selector failed".to_owned()], *errors.lock().unwrap());
+    assert_true!(correlator.query().is_none());
+    assert_true!(correlator.push_message(MessageBuilder::new("LOGIN", "login").build()).is_err());
+    assert_true!(correlator.stop(&mut responses).is_err());
+}
+
+#[test]
+fn test_given_correlator_with_restarts_when_its_dispatcher_panics_then_it_keeps_its_states() {
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(login_contexts())
+                                                     .max_restarts(1)
+                                                     .build()
+                                                     .unwrap();
+    push_logins(&mut correlator, 2);
+    let _ = correlator.close(panicking_selector());
+    let contexts = correlator.query().expect("The dispatcher is not restarted");
+    assert_eq!(2, contexts[0].states.len());
+    assert_eq!(Status::Running, correlator.status());
+    assert_eq!(1, correlator.restarts());
+    assert_eq!(Some("selector failed".to_owned()), correlator.last_error());
+    let _ = correlator.close(panicking_selector());
+    let mut responses = Vec::new();
+    correlator.sync(&mut responses);
+    assert_eq!(Status::Failed("selector failed".to_owned()), correlator.status());
+    assert_true!(correlator.stop(&mut responses).is_err());
+}
+
+#[test]
+fn test_given_correlator_with_restarts_when_a_close_panics_halfway_then_its_changes_are_rolled_back() {
+    let mut contexts = login_contexts();
+    contexts.extend(login_contexts());
+    let failing = contexts[1].uuid;
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts)
+                                                     .max_restarts(1)
+                                                     .build()
+                                                     .unwrap();
+    push_logins(&mut correlator, 2);
+    let _ = correlator.close(Selector::matching(move |uuid, _| {
+        if *uuid == failing {
+            panic!("selector failed");
+        }
+        true
+    }));
+    let contexts = correlator.query().expect("The dispatcher is not restarted");
+    assert_eq!(2, contexts[0].states.len());
+    assert_eq!(2, contexts[1].states.len());
+    assert_eq!(1, correlator.restarts());
+    let context_map = correlator.stop(&mut Vec::new()).expect("Failed to stop the correlator").context_map;
+    assert_eq!(4, context_map.snapshot().contexts.iter().map(|context| context.states.len()).sum::<usize>());
+}
+
+#[test]
+fn test_given_correlator_when_a_loopback_alert_is_dropped_then_the_failure_is_reported() {
+    let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(1).build();
+    let action = MessageActionBuilder::new("LOOP", "loop").inject_mode(InjectMode::Loopback).build();
+    let contexts = vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                            .patterns(vec!["LOOP".to_owned()])
+                            .actions(vec![action.into()])
+                            .build()];
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts)
+                                                     .max_loopback_depth(3)
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors_clone = errors.clone();
+    correlator.set_error_callback(move |error| errors_clone.lock().unwrap().push(error.to_owned()));
+    let _ = correlator.push_message(MessageBuilder::new("LOOP", "message").build());
+    let mut responses = Vec::new();
+    correlator.sync(&mut responses);
+    let errors = errors.lock().unwrap();
+    assert_eq!(1, errors.len());
+    assert_true!(errors[0].contains("maximum loopback depth"));
+    assert_eq!(Status::Running, correlator.status());
+    let _ = correlator.stop(&mut responses);
+}
+
+#[test]
+fn test_given_correlator_with_persist_shutdown_mode_when_it_is_stopped_then_the_states_are_returned
+    () {
+    use snapshot::OrphanPolicy;
+
+    let contexts = login_contexts();
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts.clone())
+                                                     .shutdown_mode(ShutdownMode::Persist)
+                                                     .alert_handler(Box::new(MockAlertHandler))
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    push_logins(&mut correlator, 3);
+    let mut responses = Vec::new();
+    let stopped = correlator.stop(&mut responses).expect("Failed to stop the correlator");
+    assert_true!(responses.is_empty());
+    assert_true!(stopped.context_map.snapshot().contexts.is_empty());
+    let snapshot = stopped.snapshot.expect("The states are not persisted");
+    assert_eq!(3, snapshot.contexts[0].states.len());
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts)
+                                                     .snapshot(snapshot, OrphanPolicy::Fail)
+                                                     .shutdown_mode(ShutdownMode::Flush)
+                                                     .alert_handler(Box::new(MockAlertHandler))
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    assert_eq!(3, correlator.query().unwrap()[0].states.len());
+    let stopped = correlator.stop(&mut responses).expect("Failed to stop the correlator");
+    assert_eq!(3, responses.len());
+    assert_eq!(None, stopped.snapshot);
+}
+
+#[test]
+fn test_given_correlator_builder_when_it_is_built_then_the_correlator_gets_the_options() {
+    use queue::{OverflowPolicy, QueueConfig};
+
+    let requests = QueueConfig::bounded(100, OverflowPolicy::Block);
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(login_contexts())
+                                                     .threads(2)
+                                                     .request_queue(requests)
+                                                     .shutdown_mode(ShutdownMode::Flush)
+                                                     .alert_handler(Box::new(MockAlertHandler))
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    push_logins(&mut correlator, 3);
+    assert_eq!(3, correlator.query().unwrap()[0].states.len());
+    let mut responses = Vec::new();
+    let context_map = correlator.stop(&mut responses).expect("Failed to stop the correlator").context_map;
+    assert_eq!(3, responses.len());
+    assert_true!(context_map.snapshot().contexts.is_empty());
+}
+
+#[test]
+fn test_given_correlator_builder_when_its_options_are_invalid_then_it_returns_an_error() {
+    let result: Result<Correlator, Error> = CorrelatorBuilder::new(ContextMap::from_configs(login_contexts()))
+                                                .threads(2)
+                                                .build();
+    match result {
+        Err(Error::InvalidOptions(_)) => {}
+        _ => unreachable!(),
+    }
+    let result: Result<Correlator, Error> = CorrelatorBuilder::from_configs(login_contexts())
+                                                .threads(2)
+                                                .journal(JournalConfig::new("/nonexistent/journal"))
+                                                .build();
+    match result {
+        Err(Error::InvalidOptions(_)) => {}
+        _ => unreachable!(),
+    }
+    let result: Result<Correlator, Error> = CorrelatorBuilder::from_path("/nonexistent/contexts.json").build();
+    match result {
+        Err(Error::Io(_)) => {}
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_given_correlator_builder_with_snapshot_when_it_is_built_then_the_states_are_restored() {
+    use snapshot::OrphanPolicy;
+
+    let contexts = login_contexts();
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts.clone())
+                                                     .build()
+                                                     .unwrap();
+    push_logins(&mut correlator, 5);
+    let snapshot = correlator.snapshot().unwrap();
+    let _ = correlator.stop(&mut Vec::new());
+    for threads in 1..3 {
+        let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts.clone())
+                                                         .threads(threads)
+                                                         .snapshot(snapshot.clone(), OrphanPolicy::Fail)
+                                                         .build()
+                                                         .expect("Failed to build a correlator");
+        assert_eq!(5, correlator.query().unwrap()[0].states.len());
+        let _ = correlator.stop(&mut Vec::new());
+    }
+    let result: Result<Correlator, Error> = CorrelatorBuilder::from_configs(login_contexts())
+                                                .snapshot(snapshot.clone(), OrphanPolicy::Fail)
+                                                .build();
+    match result {
+        Err(Error::OrphanedStates(uuids)) => assert_eq!(vec![contexts[0].uuid], uuids),
+        _ => unreachable!(),
+    }
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(login_contexts())
+                                                     .snapshot(snapshot, OrphanPolicy::Discard)
+                                                     .build()
+                                                     .unwrap();
+    assert_true!(correlator.query().unwrap()[0].states.is_empty());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_sharded_correlator_with_memory_budget_when_it_is_exceeded_then_the_oldest_states_are_closed() {
+    use context::budget::MemoryBudget;
+
+    let budget = MemoryBudget::new().max_messages(4);
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(login_contexts())
+                                                     .threads(2)
+                                                     .memory_budget(budget)
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    push_logins(&mut correlator, 20);
+    let states = correlator.query().unwrap()[0].states.len();
+    assert_true!(states <= 4);
+    assert_eq!(20 - states, correlator.drain_alerts().len());
+    let context_map = correlator.stop(&mut Vec::new()).expect("Failed to stop the correlator").context_map;
+    assert_eq!(Some(4), context_map.memory_budget().and_then(|budget| budget.max_messages));
+}
+
+#[test]
+fn test_given_sharded_correlator_with_memory_budget_when_one_shard_has_every_state_then_the_budget_is_not_split() {
+    use context::budget::MemoryBudget;
+    use context::shard::Shard;
+
+    let contexts = login_contexts();
+    let routes = ContextMap::from_configs(contexts.clone());
+    let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(contexts)
+                                                     .threads(2)
+                                                     .memory_budget(MemoryBudget::new().max_messages(4))
+                                                     .build()
+                                                     .expect("Failed to build a correlator");
+    let users = (0..)
+                    .map(|i| MessageBuilder::new("LOGIN", "login").pair("user", &format!("user{}", i)).build())
+                    .filter(|message| routes.contexts()[0].shard_of(message, 2) == Some(Shard::new(0, 2)))
+                    .take(4)
+                    .collect::<Vec<_>>();
+    for i in users {
+        let _ = correlator.push_message(i);
+    }
+    assert_eq!(4, correlator.query().unwrap()[0].states.len());
+    assert_true!(correlator.drain_alerts().is_empty());
+    let _ = correlator.stop(&mut Vec::new());
+}
+
+#[test]
+fn test_given_sharded_correlator_with_blocking_request_queue_when_loopback_alerts_are_generated_under_load_then_it_does_not_deadlock
+    () {
+    use queue::{OverflowPolicy, QueueConfig};
+
+    let login = {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(1).build();
+        let action = MessageActionBuilder::new("SEEN", "seen").inject_mode(InjectMode::Loopback).build();
+        ContextConfigBuilder::new(Uuid::new_v4(), condition)
+            .patterns(vec!["LOGIN".to_owned()])
+            .context_id(Some(vec!["user".into()]))
+            .actions(vec![action.into()])
+            .build()
+    };
+    let seen = {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).max_size(1).build();
+        ContextConfigBuilder::new(Uuid::new_v4(), condition)
+            .patterns(vec!["SEEN".to_owned()])
+            .build()
+    };
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut correlator: Correlator<Vec<Alert>> = CorrelatorBuilder::from_configs(vec![login, seen])
+                                                         .threads(2)
+                                                         .request_queue(QueueConfig::bounded(2, OverflowPolicy::Block))
+                                                         .build()
+                                                         .unwrap();
+        push_logins(&mut correlator, 500);
+        let mut responses = Vec::new();
+        correlator.sync(&mut responses);
+        let _ = correlator.stop(&mut responses);
+        let _ = tx.send(());
+    });
+    rx.recv_timeout(Duration::from_secs(30)).expect("The correlator is deadlocked");
+}
@@ -0,0 +1,84 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use control::CloseMode;
+use dispatcher::request::Request;
+use queue::QueueSender;
+use super::CorrelatorFactory;
+
+// Polls the modification time of a configuration file and reloads the
+// contexts of the correlator when it changes. If the file can't be loaded,
+// the current contexts are kept. The reports of the reloads are passed to the
+// error callback of the correlator.
+pub struct ConfigWatcher {
+    stop: mpsc::Sender<()>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P,
+                                 interval: Duration,
+                                 mode: CloseMode,
+                                 channel: QueueSender<Request>)
+                                 -> ConfigWatcher {
+        let path = path.into();
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut modified = modification_time(&path);
+            // The wait ends early when the watcher is stopped or dropped.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let current = modification_time(&path);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                match CorrelatorFactory::load_file(&path) {
+                    Ok(configs) => {
+                        let (tx, _) = mpsc::channel();
+                        if channel.send(Request::Reload(configs, mode, tx)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => {
+                        error!("ConfigWatcher: failed to load configuration, keeping the current \
+                                contexts; path={} error={}",
+                               path.display(),
+                               error);
+                    }
+                }
+            }
+        });
+        ConfigWatcher {
+            stop: stop,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn stop(mut self) {
+        let _ = self.stop.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
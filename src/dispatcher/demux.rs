@@ -6,21 +6,101 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::sync::mpsc::Receiver;
+use std::cmp;
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
+use clock::{Clock, SystemClock};
 use dispatcher::request::Request;
+use queue::QueueReceiver;
 use reactor::EventDemultiplexer;
+use timer::Timer;
 
+// Besides the received requests, it generates the timer events: one is
+// returned when the timeout set by the reactor expires, and one before every
+// other request (if at least a millisecond passed). The event before a
+// request moves the time to the time it was queued at (if the queue stamps
+// its requests), so the timeouts which expired before a request was sent are
+// handled before it, even if the dispatcher is behind the queue. An expired
+// timeout moves the time only to the time the next request was queued at.
+// If the clock is not real time, the time passes only by the requests.
 pub struct Demultiplexer<T> {
-    channel: Receiver<T>,
+    channel: QueueReceiver<T>,
     stops: u32,
+    timer: Timer,
+    timeout: Option<Duration>,
+    tick_interval: Option<Duration>,
+    pending: Option<T>,
 }
 
 impl<T> Demultiplexer<T> {
-    pub fn new(channel: Receiver<T>) -> Demultiplexer<T> {
+    #[allow(dead_code)]
+    pub fn new(channel: QueueReceiver<T>) -> Demultiplexer<T> {
+        Demultiplexer::with_clock(channel, Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(channel: QueueReceiver<T>, clock: Arc<Clock>) -> Demultiplexer<T> {
         Demultiplexer {
             channel: channel,
             stops: 0,
+            timer: Timer::new(clock),
+            timeout: None,
+            tick_interval: None,
+            pending: None,
+        }
+    }
+
+    // The time after the last timer event when the next one is needed. None
+    // means that no timer event is needed until the next request.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        let timeout = match (timeout, self.tick_interval) {
+            (Some(timeout), Some(interval)) => Some(cmp::min(timeout, interval)),
+            (timeout, interval) => timeout.or(interval),
+        };
+        // A zero timeout would spin until the first millisecond passes.
+        self.timeout = timeout.map(|timeout| cmp::max(timeout, Duration::from_millis(1)));
+    }
+
+    // A timer event is generated at least this often, even if no state has a
+    // deadline.
+    pub fn set_tick_interval(&mut self, interval: Option<Duration>) {
+        self.tick_interval = interval;
+    }
+}
+
+impl Demultiplexer<Request> {
+    fn receive(&mut self) -> Option<Request> {
+        if !self.timer.clock().is_real_time() {
+            return self.channel.recv().ok();
+        }
+        loop {
+            let timeout = match self.timeout {
+                Some(timeout) => timeout,
+                None => return self.channel.recv().ok(),
+            };
+            let elapsed = self.timer.elapsed();
+            if elapsed >= timeout {
+                // The requests queued before now are handled at their time.
+                let event = match self.channel.next_stamp() {
+                    Some(stamp) => self.timer.tick_until(stamp),
+                    None => self.timer.tick(),
+                };
+                if let Some(event) = event {
+                    self.timeout = None;
+                    return Some(Request::Timer(event));
+                }
+            }
+            let remaining = if timeout > elapsed {
+                timeout - elapsed
+            } else {
+                Duration::from_millis(1)
+            };
+            match self.channel.recv_timeout(remaining) {
+                Ok(request) => return Some(request),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
         }
     }
 }
@@ -28,7 +108,27 @@ impl<T> Demultiplexer<T> {
 impl EventDemultiplexer for Demultiplexer<Request> {
     type Event = Request;
     fn select(&mut self) -> Option<Self::Event> {
-        let data = self.channel.recv().ok();
+        let data = match self.pending.take() {
+            Some(request) => Some(request),
+            None => {
+                let data = self.receive();
+                match data {
+                    Some(Request::Timer(_)) | Some(Request::Exit) | None => {}
+                    Some(request) => {
+                        let event = match self.channel.last_stamp() {
+                            Some(stamp) => self.timer.tick_until(stamp),
+                            None => self.timer.tick(),
+                        };
+                        if let Some(event) = event {
+                            self.pending = Some(request);
+                            return Some(Request::Timer(event));
+                        }
+                        return Some(request);
+                    }
+                }
+                data
+            }
+        };
 
         if let Some(Request::Exit) = data {
             if self.stops >= 1 {
@@ -42,3 +142,120 @@ impl EventDemultiplexer for Demultiplexer<Request> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use dispatcher::request::Request;
+    use message::MessageBuilder;
+    use clock::SystemClock;
+    use queue::{queue, queue_with_clock, QueueConfig};
+    use reactor::EventDemultiplexer;
+    use std::sync::Arc;
+    use std::thread;
+    use super::Demultiplexer;
+    use test_utils::ManualClock;
+    use timer::TimerEvent;
+
+    #[test]
+    fn test_given_demultiplexer_when_its_timeout_expires_then_it_returns_a_timer_event() {
+        let (tx, rx) = queue(QueueConfig::unbounded());
+        let mut demultiplexer = Demultiplexer::new(rx);
+        demultiplexer.set_timeout(Some(Duration::from_millis(20)));
+        match demultiplexer.select() {
+            Some(Request::Timer(event)) => assert_true!(event.0 >= Duration::from_millis(20)),
+            _ => unreachable!(),
+        }
+        let _ = tx.send(Request::Exit);
+    }
+
+    #[test]
+    fn test_given_demultiplexer_with_tick_interval_when_no_timeout_is_set_then_it_returns_timer_events() {
+        let (tx, rx) = queue(QueueConfig::unbounded());
+        let mut demultiplexer = Demultiplexer::new(rx);
+        demultiplexer.set_tick_interval(Some(Duration::from_millis(20)));
+        demultiplexer.set_timeout(None);
+        match demultiplexer.select() {
+            Some(Request::Timer(event)) => assert_true!(event.0 >= Duration::from_millis(20)),
+            _ => unreachable!(),
+        }
+        let _ = tx.send(Request::Exit);
+    }
+
+    #[test]
+    fn test_given_demultiplexer_when_a_message_is_received_later_then_a_timer_event_is_returned_before_it
+        () {
+        let (tx, rx) = queue(QueueConfig::unbounded());
+        let mut demultiplexer = Demultiplexer::new(rx);
+        thread::sleep(Duration::from_millis(10));
+        let _ = tx.send(Request::Message(Arc::new(MessageBuilder::new("LOGIN", "login").build())));
+        match demultiplexer.select() {
+            Some(Request::Timer(event)) => assert_true!(event.0 >= Duration::from_millis(10)),
+            _ => unreachable!(),
+        }
+        match demultiplexer.select() {
+            Some(Request::Message(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn login() -> Request {
+        Request::Message(Arc::new(MessageBuilder::new("LOGIN", "login").build()))
+    }
+
+    fn assert_timer_event(expected: u64, request: Option<Request>) {
+        match request {
+            Some(Request::Timer(TimerEvent(elapsed))) => {
+                assert_eq!(Duration::from_millis(expected), elapsed)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_given_demultiplexer_behind_its_queue_when_the_requests_are_selected_then_the_time_moves_to_the_time_they_were_queued_at
+        () {
+        let clock = ManualClock::new();
+        let (tx, rx) = queue_with_clock(QueueConfig::unbounded(), Arc::new(clock.clone()));
+        let mut demultiplexer = Demultiplexer::with_clock(rx, Arc::new(clock.clone()));
+        clock.advance(Duration::from_millis(50));
+        let _ = tx.push(login());
+        clock.advance(Duration::from_millis(100));
+        let _ = tx.push(login());
+        clock.advance(Duration::from_millis(1000));
+        assert_timer_event(50, demultiplexer.select());
+        match demultiplexer.select() {
+            Some(Request::Message(_)) => {}
+            _ => unreachable!(),
+        }
+        assert_timer_event(100, demultiplexer.select());
+        match demultiplexer.select() {
+            Some(Request::Message(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_given_demultiplexer_behind_its_queue_when_its_timeout_expires_then_the_time_moves_to_the_next_queued_request
+        () {
+        let clock = Arc::new(SystemClock::new());
+        let (tx, rx) = queue_with_clock(QueueConfig::unbounded(), clock.clone());
+        let mut demultiplexer = Demultiplexer::with_clock(rx, clock);
+        demultiplexer.set_timeout(Some(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+        let _ = tx.push(login());
+        thread::sleep(Duration::from_millis(200));
+        match demultiplexer.select() {
+            Some(Request::Timer(event)) => {
+                assert_true!(event.0 >= Duration::from_millis(20));
+                assert_true!(event.0 < Duration::from_millis(200));
+            }
+            _ => unreachable!(),
+        }
+        match demultiplexer.select() {
+            Some(Request::Message(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+}
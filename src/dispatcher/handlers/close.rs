@@ -0,0 +1,30 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use dispatcher::request::{Request, RequestHandle};
+use reactor::{EventHandler, SharedData};
+
+#[derive(Default)]
+pub struct CloseEventHandler;
+
+impl<'a> EventHandler<Request, SharedData<'a>> for CloseEventHandler {
+    fn handle_event(&mut self, event: Request, data: &mut SharedData) {
+        if let Request::Close(selector, mode) = event {
+            let closed = data.map.close_states(&selector, mode, data.responder);
+            trace!("CloseEventHandler: states are closed; selector={:?} mode={:?} closed={}",
+                   selector,
+                   mode,
+                   closed);
+        } else {
+            unreachable!("CloseEventHandler should only handle Close events");
+        }
+    }
+    fn handle(&self) -> RequestHandle {
+        RequestHandle::Close
+    }
+}
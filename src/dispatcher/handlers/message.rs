@@ -16,15 +16,28 @@ pub struct MessageEventHandler;
 impl<'a> EventHandler<Request, SharedData<'a>> for MessageEventHandler {
     fn handle_event(&mut self, event: Request, data: &mut SharedData) {
         trace!("MessageEventHandler: handle_event()");
-        if let Request::Message(event) = event {
-            for i in event.ids() {
-                let mut iter = data.map.contexts_iter_mut(i);
-                while let Some(context) = iter.next() {
-                    context.on_event(Request::Message(event.clone()), data.responder);
+        match event {
+            Request::Message(event) => {
+                for i in event.ids() {
+                    let mut iter = data.map.contexts_iter_mut(i);
+                    while let Some(context) = iter.next() {
+                        context.on_event(Request::Message(event.clone()), data.responder);
+                    }
                 }
+                data.map.enforce_memory_budget(data.responder);
             }
-        } else {
-            unreachable!("MessageEventHandler should only handle Message events");
+            Request::Batch(events) => {
+                // The contexts handle their messages one after the other, the
+                // budget is enforced at the end of the batch.
+                for (index, events) in data.map.group_by_context(events) {
+                    let context = &mut data.map.contexts_mut()[index];
+                    for event in events {
+                        context.on_event(Request::Message(event), data.responder);
+                    }
+                }
+                data.map.enforce_memory_budget(data.responder);
+            }
+            _ => unreachable!("MessageEventHandler should only handle Message and Batch events"),
         }
     }
     fn handle(&self) -> RequestHandle {
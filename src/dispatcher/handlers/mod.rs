@@ -7,5 +7,11 @@
 // modified, or distributed except according to those terms.
 
 pub mod exit;
+pub mod close;
 pub mod message;
+pub mod query;
+pub mod reload;
+pub mod shutdown;
+pub mod snapshot;
+pub mod sync;
 pub mod timer;
@@ -0,0 +1,26 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use dispatcher::request::{Request, RequestHandle};
+use reactor::{EventHandler, SharedData};
+
+#[derive(Default)]
+pub struct QueryEventHandler;
+
+impl<'a> EventHandler<Request, SharedData<'a>> for QueryEventHandler {
+    fn handle_event(&mut self, event: Request, data: &mut SharedData) {
+        if let Request::Query(channel) = event {
+            let _ = channel.send(data.map.query());
+        } else {
+            unreachable!("QueryEventHandler should only handle Query events");
+        }
+    }
+    fn handle(&self) -> RequestHandle {
+        RequestHandle::Query
+    }
+}
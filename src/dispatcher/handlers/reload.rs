@@ -0,0 +1,35 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::mpsc::SendError;
+
+use dispatcher::Response;
+use dispatcher::request::{Request, RequestHandle};
+use reactor::{EventHandler, SharedData};
+
+#[derive(Default)]
+pub struct ReloadEventHandler;
+
+impl<'a> EventHandler<Request, SharedData<'a>> for ReloadEventHandler {
+    fn handle_event(&mut self, event: Request, data: &mut SharedData) {
+        if let Request::Reload(configs, mode, channel) = event {
+            let report = data.map.reload(configs, mode, data.responder);
+            info!("ReloadEventHandler: {}", report);
+            // Nobody waits for the report of a ConfigWatcher, it is passed to
+            // the error callback instead.
+            if let Err(SendError(report)) = channel.send(report) {
+                data.responder.send_response(Response::Error(report.to_string()));
+            }
+        } else {
+            unreachable!("ReloadEventHandler should only handle Reload events");
+        }
+    }
+    fn handle(&self) -> RequestHandle {
+        RequestHandle::Reload
+    }
+}
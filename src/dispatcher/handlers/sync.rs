@@ -0,0 +1,27 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use dispatcher::request::{Request, RequestHandle};
+use reactor::{EventHandler, SharedData};
+
+// Answers when every request received before is handled.
+#[derive(Default)]
+pub struct SyncEventHandler;
+
+impl<'a> EventHandler<Request, SharedData<'a>> for SyncEventHandler {
+    fn handle_event(&mut self, event: Request, _: &mut SharedData) {
+        if let Request::Sync(channel) = event {
+            let _ = channel.send(());
+        } else {
+            unreachable!("SyncEventHandler should only handle Sync events");
+        }
+    }
+    fn handle(&self) -> RequestHandle {
+        RequestHandle::Sync
+    }
+}
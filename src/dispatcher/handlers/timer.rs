@@ -17,6 +17,7 @@ impl<'a> EventHandler<Request, SharedData<'a>> for TimerEventHandler {
         for i in data.map.contexts_mut() {
             i.on_event(event.clone(), data.responder);
         }
+        data.map.enforce_memory_budget(data.responder);
     }
     fn handle(&self) -> RequestHandle {
         RequestHandle::Timer
@@ -6,9 +6,10 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Sender, TrySendError};
 
 use action::Alert;
+use queue::QueueSender;
 use reactor::Event;
 use self::response::ResponseSender;
 
@@ -17,17 +18,21 @@ pub mod handlers;
 pub mod response;
 pub mod request;
 pub mod reactor;
+pub mod router;
 
 #[derive(Debug, Clone)]
 pub enum Response {
     Exit,
     Alert(Alert),
+    // The dispatcher thread panicked while handling a request.
+    Error(String),
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub enum ResponseHandle {
     Exit,
     Alert,
+    Error,
 }
 
 impl Event for Response {
@@ -36,6 +41,7 @@ impl Event for Response {
         match *self {
             Response::Exit => ResponseHandle::Exit,
             Response::Alert(_) => ResponseHandle::Alert,
+            Response::Error(_) => ResponseHandle::Error,
         }
     }
 }
@@ -45,3 +51,40 @@ impl ResponseSender for Sender<Response> {
         let _ = self.send(response);
     }
 }
+
+// The alerts are limited by the capacity of the queue, Exit and the errors are
+// always sent.
+impl ResponseSender for QueueSender<Response> {
+    fn send_response(&mut self, response: Response) {
+        let _ = self.try_send_response(response);
+    }
+
+    fn try_send_response(&mut self, response: Response) -> bool {
+        match response {
+            Response::Alert(alert) => {
+                let uuid = alert.message.uuid().clone();
+                let dropped = self.stats().dropped;
+                match self.offer(Response::Alert(alert)) {
+                    Ok(true) => {
+                        // The DropOldest policy made room by dropping an
+                        // older alert.
+                        if self.stats().dropped > dropped {
+                            warn!("Alert queue is full, the oldest alert is dropped");
+                        }
+                        true
+                    }
+                    Ok(false) => {
+                        warn!("Alert queue is full, the alert is dropped; uuid={}", uuid);
+                        false
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Alert queue is full, the alert is refused; uuid={}", uuid);
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                }
+            }
+            Response::Exit | Response::Error(_) => self.send(response).is_ok(),
+        }
+    }
+}
@@ -6,32 +6,125 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::collections::BTreeMap;
-
-use context::ContextMap;
+use control::{CloseMode, Selector};
 use dispatcher::demux::Demultiplexer;
 use dispatcher::request::{RequestHandle, Request};
-use reactor::{Event, EventDemultiplexer, EventHandler, Reactor, SharedData};
+use engine::Engine;
+use reactor::{Event, EventDemultiplexer, Reactor};
 use dispatcher::response::ResponseSender;
+use journal::Journal;
+use snapshot::Snapshot;
 
-#[allow(type_complexity)]
+// The states can be rolled back to a checkpoint kept in memory, which is taken
+// before every control request and after every checkpoint_interval other
+// requests.
 pub struct RequestReactor {
-    handlers: BTreeMap<RequestHandle, Box<for<'a> EventHandler<Request, SharedData<'a>>>>,
     demultiplexer: Demultiplexer<Request>,
-    pub context_map: ContextMap,
+    pub engine: Engine,
     responder: Box<ResponseSender>,
+    journal: Option<Journal>,
+    checkpoint: Option<Snapshot>,
+    checkpoint_interval: Option<u64>,
+    since_checkpoint: u64,
 }
 
 impl RequestReactor {
     pub fn new(demultiplexer: Demultiplexer<Request>,
-               context_map: ContextMap,
+               engine: Engine,
                responder: Box<ResponseSender>)
                -> RequestReactor {
         RequestReactor {
             demultiplexer: demultiplexer,
-            context_map: context_map,
-            handlers: BTreeMap::new(),
+            engine: engine,
             responder: responder,
+            journal: None,
+            checkpoint: None,
+            checkpoint_interval: None,
+            since_checkpoint: 0,
+        }
+    }
+
+    pub fn set_journal(&mut self, journal: Option<Journal>) {
+        self.journal = journal;
+    }
+
+    // None disables the checkpoints, so rollback() does nothing.
+    pub fn set_checkpoint_interval(&mut self, interval: Option<u64>) {
+        self.checkpoint_interval = interval;
+        if interval.is_none() {
+            self.checkpoint = None;
+        }
+    }
+
+    fn take_checkpoint(&mut self) {
+        if self.checkpoint_interval.is_some() {
+            self.checkpoint = Some(self.engine.context_map().snapshot());
+            self.since_checkpoint = 0;
+        }
+    }
+
+    fn on_handled(&mut self) {
+        self.since_checkpoint += 1;
+        if self.checkpoint_interval.map_or(false, |interval| self.since_checkpoint >= interval) {
+            self.take_checkpoint();
+        }
+    }
+
+    // Replaces the states with the ones of the last checkpoint, the effects of
+    // the requests handled after it are lost. Returns their number.
+    pub fn rollback(&mut self) -> u64 {
+        let snapshot = match self.checkpoint {
+            Some(ref snapshot) => snapshot.clone(),
+            None => return 0,
+        };
+        let context_map = self.engine.context_map_mut();
+        let _ = context_map.close_states(&Selector::All, CloseMode::Discard, &mut *self.responder);
+        for i in context_map.restore(snapshot) {
+            warn!("RequestReactor: context of a checkpointed state is not found, discarding its \
+                   states; uuid={}",
+                  i);
+        }
+        trace!("RequestReactor: rolled back to the last checkpoint; lost_requests={}",
+               self.since_checkpoint);
+        let lost = self.since_checkpoint;
+        self.since_checkpoint = 0;
+        if let Some(ref mut journal) = self.journal {
+            journal.checkpoint(context_map);
+        }
+        lost
+    }
+
+    fn dispatch(&mut self, request: Request) {
+        match self.journal {
+            Some(ref mut journal) => {
+                let mut responder = journal.responder(&mut *self.responder);
+                self.engine.dispatch(request, &mut responder);
+            }
+            None => self.engine.dispatch(request, &mut *self.responder),
+        }
+    }
+
+    // Restores the last checkpoint of the journal and replays the requests
+    // received after it.
+    fn recover(&mut self) {
+        let recovery = match self.journal.as_mut().and_then(|journal| journal.take_recovery()) {
+            Some(recovery) => recovery,
+            None => return,
+        };
+        for i in self.engine.context_map_mut().restore(recovery.snapshot) {
+            warn!("RequestReactor: context of a journaled state is not found, discarding its \
+                   states; uuid={}",
+                  i);
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.resend_pending(&mut *self.responder);
+        }
+        trace!("RequestReactor: replaying journal; requests={}", recovery.requests.len());
+        for i in recovery.requests {
+            self.dispatch(i);
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.checkpoint(self.engine.context_map());
         }
     }
 }
@@ -39,21 +132,99 @@ impl RequestReactor {
 impl Reactor for RequestReactor {
     type Event = Request;
     fn handle_events(&mut self) {
-        let mut shared_data = SharedData::new(&mut self.context_map, &mut *self.responder);
-        while let Some(request) = self.demultiplexer.select() {
+        self.recover();
+        if self.checkpoint.is_none() {
+            self.take_checkpoint();
+        }
+        loop {
+            self.demultiplexer.set_timeout(self.engine.next_timeout());
+            let request = match self.demultiplexer.select() {
+                Some(request) => request,
+                None => break,
+            };
             trace!("RequestReactor: got event");
-            if let Some(handler) = self.handlers.get_mut(&request.handle()) {
-                handler.handle_event(request, &mut shared_data);
-            } else {
-                trace!("RequestReactor: no handler found for event");
+            if let Some(ref mut journal) = self.journal {
+                journal.append(&request);
+            }
+            // The control requests are not journaled, their effect is saved by
+            // a checkpoint instead.
+            let is_control = match request.handle() {
+                RequestHandle::Close | RequestHandle::Reload | RequestHandle::Shutdown => true,
+                _ => false,
+            };
+            if is_control {
+                self.take_checkpoint();
+            }
+            self.dispatch(request);
+            if let Some(ref mut journal) = self.journal {
+                if is_control {
+                    journal.checkpoint(self.engine.context_map());
+                } else {
+                    journal.on_applied(self.engine.context_map());
+                }
+            }
+            if !is_control {
+                self.on_handled();
             }
         }
-    }
-    fn register_handler(&mut self,
-                        handler: Box<for<'a> EventHandler<Self::Event, SharedData<'a>>>) {
-        self.handlers.insert(handler.handle(), handler);
+        if let Some(ref mut journal) = self.journal {
+            journal.checkpoint(self.engine.context_map());
+        }
     }
     fn remove_handler_by_handle(&mut self, handler: &RequestHandle) {
-        self.handlers.remove(handler);
+        self.engine.remove_handler_by_handle(handler);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use conditions::ConditionsBuilder;
+    use config::ContextConfigBuilder;
+    use context::ContextMap;
+    use dispatcher::demux::Demultiplexer;
+    use dispatcher::request::Request;
+    use engine::Engine;
+    use message::MessageBuilder;
+    use queue::{queue, QueueConfig};
+    use reactor::Reactor;
+    use super::RequestReactor;
+    use test_utils::{ManualClock, MockResponseSender};
+
+    fn states(reactor: &RequestReactor) -> usize {
+        reactor.engine.context_map().snapshot().contexts.iter().map(|context| context.states.len()).sum()
+    }
+
+    #[test]
+    fn test_given_reactor_with_checkpoints_when_it_is_rolled_back_then_it_returns_the_lost_requests
+        () {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+        let contexts = vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                                .patterns(vec!["LOGIN".to_owned()])
+                                .context_id(Some(vec!["user".into()]))
+                                .build()];
+        let (tx, rx) = queue(QueueConfig::unbounded());
+        let engine = Engine::new(ContextMap::from_configs(contexts));
+        let demultiplexer = Demultiplexer::with_clock(rx, Arc::new(ManualClock::new()));
+        let mut reactor = RequestReactor::new(demultiplexer,
+                                              engine,
+                                              Box::new(MockResponseSender::default()));
+        reactor.set_checkpoint_interval(Some(4));
+        for i in 0..5 {
+            let message = MessageBuilder::new("LOGIN", "login").pair("user", &i.to_string()).build();
+            let _ = tx.send(Request::Message(Arc::new(message)));
+        }
+        let _ = tx.send(Request::Exit);
+        let _ = tx.send(Request::Exit);
+        reactor.handle_events();
+        // The exit request is counted too.
+        assert_eq!(5, states(&reactor));
+        assert_eq!(2, reactor.rollback());
+        assert_eq!(4, states(&reactor));
+        assert_eq!(0, reactor.rollback());
     }
 }
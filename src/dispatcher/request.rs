@@ -7,15 +7,30 @@
 // modified, or distributed except according to those terms.
 
 use std::sync::Arc;
+use std::sync::mpsc::Sender;
 
+use config::ContextConfig;
+use context::ReloadReport;
+use control::{CloseMode, Selector, ShutdownMode};
+use introspection::ContextInfo;
 use message::Message;
 use reactor;
+use snapshot::Snapshot;
 use timer::TimerEvent;
 
 #[derive(Clone, Debug)]
 pub enum Request {
     Message(Arc<Message>),
+    // The messages are handled in order by the MessageEventHandler.
+    Batch(Vec<Arc<Message>>),
     Timer(TimerEvent),
+    Snapshot(Sender<Snapshot>),
+    Query(Sender<Vec<ContextInfo>>),
+    Close(Selector, CloseMode),
+    Reload(Vec<ContextConfig>, CloseMode, Sender<ReloadReport>),
+    Sync(Sender<()>),
+    // Empties the contexts, the persisted states are sent back.
+    Shutdown(ShutdownMode, Sender<Snapshot>),
     Exit,
 }
 
@@ -23,6 +38,12 @@ pub enum Request {
 pub enum RequestHandle {
     Message,
     Timer,
+    Snapshot,
+    Query,
+    Close,
+    Reload,
+    Sync,
+    Shutdown,
     Exit,
 }
 
@@ -30,8 +51,14 @@ impl reactor::Event for Request {
     type Handle = RequestHandle;
     fn handle(&self) -> Self::Handle {
         match *self {
-            Request::Message(_) => RequestHandle::Message,
+            Request::Message(_) | Request::Batch(_) => RequestHandle::Message,
             Request::Timer(_) => RequestHandle::Timer,
+            Request::Snapshot(_) => RequestHandle::Snapshot,
+            Request::Query(_) => RequestHandle::Query,
+            Request::Close(..) => RequestHandle::Close,
+            Request::Reload(..) => RequestHandle::Reload,
+            Request::Sync(_) => RequestHandle::Sync,
+            Request::Shutdown(..) => RequestHandle::Shutdown,
             Request::Exit => RequestHandle::Exit,
         }
     }
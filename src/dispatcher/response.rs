@@ -10,4 +10,10 @@ use super::Response;
 
 pub trait ResponseSender {
     fn send_response(&mut self, response: Response);
+
+    // Returns false if the response is dropped, e.g. by a full alert queue.
+    fn try_send_response(&mut self, response: Response) -> bool {
+        self.send_response(response);
+        true
+    }
 }
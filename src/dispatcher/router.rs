@@ -0,0 +1,351 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TrySendError};
+use std::time::Duration;
+
+use config::ContextConfig;
+use context::ContextMap;
+use dispatcher::Response;
+use dispatcher::request::Request;
+use introspection::ContextInfo;
+use message::Message;
+use queue::{QueueReceiver, QueueSender};
+use snapshot::Snapshot;
+
+// Forwards the requests of a sharded correlator to the dispatchers of the
+// shards. The shards owning a message are looked up once by the router with
+// the contexts subscribed to it, and the message is sent only to them, so the
+// messages of a key are handled in order by one shard. The other requests are
+// sent to every shard. The answers of the shards are merged, the alerts are
+// sent by the shards to the common response channel. The requests keep the
+// time they were queued at.
+// If the clock is not real time, the time passes only by the requests, so the
+// shards which don't own a message get an empty batch with its time instead.
+// A shard whose dispatcher is not running any more is reported once as a
+// Response::Error, its requests are dropped.
+pub struct Router {
+    shards: Vec<QueueSender<Request>>,
+    responder: QueueSender<Response>,
+    routes: ContextMap,
+    forwards_time: bool,
+    stops: u32,
+    stopped_shards: Vec<bool>,
+}
+
+impl Router {
+    pub fn new(configs: Vec<ContextConfig>,
+               shards: Vec<QueueSender<Request>>,
+               responder: QueueSender<Response>,
+               forwards_time: bool)
+               -> Router {
+        Router {
+            stopped_shards: vec![false; shards.len()],
+            shards: shards,
+            responder: responder,
+            routes: ContextMap::from_configs(configs),
+            forwards_time: forwards_time,
+            stops: 0,
+        }
+    }
+
+    // Returns after the second Exit request, the shards stop when their
+    // channels are dropped.
+    pub fn run(mut self, channel: QueueReceiver<Request>) {
+        while let Ok(request) = channel.recv() {
+            if !self.route(request, channel.last_stamp()) {
+                break;
+            }
+        }
+        trace!("Router: exited");
+    }
+
+    fn route(&mut self, request: Request, stamp: Option<Duration>) -> bool {
+        match request {
+            Request::Exit => {
+                if self.stops >= 1 {
+                    return false;
+                }
+                self.stops += 1;
+                let _ = self.responder.send(Response::Exit);
+            }
+            Request::Snapshot(channel) => {
+                let mut snapshot = Snapshot::default();
+                for i in self.collect(Request::Snapshot, stamp) {
+                    snapshot.merge(i);
+                }
+                let _ = channel.send(snapshot);
+            }
+            Request::Query(channel) => {
+                let mut contexts: Vec<ContextInfo> = Vec::new();
+                for infos in self.collect(Request::Query, stamp) {
+                    for i in infos {
+                        match contexts.iter().position(|context| context.uuid == i.uuid) {
+                            Some(index) => contexts[index].merge(i),
+                            None => contexts.push(i),
+                        }
+                    }
+                }
+                let _ = channel.send(contexts);
+            }
+            Request::Reload(configs, mode, channel) => {
+                self.routes = ContextMap::from_configs(configs.clone());
+                let mut reports = self.collect(|tx| Request::Reload(configs.clone(), mode, tx), stamp)
+                                      .into_iter();
+                let mut report = reports.next().unwrap_or_default();
+                for i in reports {
+                    report.merge(i);
+                }
+                if let Err(mpsc::SendError(report)) = channel.send(report) {
+                    let _ = self.responder.send(Response::Error(report.to_string()));
+                }
+            }
+            Request::Shutdown(mode, channel) => {
+                let mut snapshot = Snapshot::default();
+                for i in self.collect(|tx| Request::Shutdown(mode, tx), stamp) {
+                    snapshot.merge(i);
+                }
+                let _ = channel.send(snapshot);
+            }
+            Request::Sync(channel) => {
+                let _ = self.collect(Request::Sync, stamp);
+                let _ = channel.send(());
+            }
+            Request::Message(message) => {
+                let owners = self.owners(&message);
+                let mut stopped = Vec::new();
+                for (index, (shard, owner)) in self.shards.iter().zip(owners).enumerate() {
+                    let is_sent = if owner {
+                        is_sent(shard.push_at(Request::Message(message.clone()), stamp))
+                    } else if self.forwards_time {
+                        shard.send_at(Request::Batch(Vec::new()), stamp).is_ok()
+                    } else {
+                        true
+                    };
+                    if !is_sent {
+                        stopped.push(index);
+                    }
+                }
+                self.on_stopped_shards(stopped);
+            }
+            Request::Batch(messages) => {
+                // Every shard gets its messages in their original order.
+                let mut batches = vec![Vec::new(); self.shards.len()];
+                for message in messages {
+                    for (batch, owner) in batches.iter_mut().zip(self.owners(&message)) {
+                        if owner {
+                            batch.push(message.clone());
+                        }
+                    }
+                }
+                let mut stopped = Vec::new();
+                for (index, (shard, batch)) in self.shards.iter().zip(batches).enumerate() {
+                    let is_sent = if !batch.is_empty() {
+                        let weight = batch.len();
+                        is_sent(shard.offer_weighted_at(Request::Batch(batch), stamp, weight))
+                    } else if self.forwards_time {
+                        shard.send_at(Request::Batch(batch), stamp).is_ok()
+                    } else {
+                        true
+                    };
+                    if !is_sent {
+                        stopped.push(index);
+                    }
+                }
+                self.on_stopped_shards(stopped);
+            }
+            request => {
+                let stopped = self.shards
+                                  .iter()
+                                  .enumerate()
+                                  .filter(|&(_, shard)| {
+                                      shard.send_at(request.clone(), stamp).is_err()
+                                  })
+                                  .map(|(index, _)| index)
+                                  .collect();
+                self.on_stopped_shards(stopped);
+            }
+        }
+        true
+    }
+
+    // Marks the shards handling the message for at least one of the
+    // contexts subscribed to it.
+    fn owners(&self, message: &Arc<Message>) -> Vec<bool> {
+        let mut owners = vec![false; self.shards.len()];
+        for id in message.ids() {
+            for index in self.routes.subscribers(id) {
+                let context = &self.routes.contexts()[*index];
+                if let Some(shard) = context.shard_of(message, self.shards.len()) {
+                    owners[shard.index()] = true;
+                }
+            }
+        }
+        owners
+    }
+
+    // Sends the request to every shard, then waits for their answers.
+    fn collect<T, F>(&mut self, request: F, stamp: Option<Duration>) -> Vec<T>
+        where F: Fn(Sender<T>) -> Request
+    {
+        let receivers = self.shards
+                            .iter()
+                            .map(|shard| {
+                                let (tx, rx) = mpsc::channel();
+                                shard.send_at(request(tx), stamp).ok().map(|_| rx)
+                            })
+                            .collect::<Vec<Option<Receiver<T>>>>();
+        let mut answers = Vec::new();
+        let mut stopped = Vec::new();
+        for (index, rx) in receivers.into_iter().enumerate() {
+            match rx.map(|rx| rx.recv()) {
+                Some(Ok(answer)) => answers.push(answer),
+                _ => stopped.push(index),
+            }
+        }
+        self.on_stopped_shards(stopped);
+        answers
+    }
+
+    fn on_stopped_shards(&mut self, shards: Vec<usize>) {
+        for i in shards {
+            if self.stopped_shards[i] {
+                continue;
+            }
+            self.stopped_shards[i] = true;
+            error!("Router: dispatcher of shard is not running, its requests are dropped; \
+                    shard={}",
+                   i);
+            let error = format!("dispatcher of shard {} is not running, its requests are dropped",
+                                i);
+            let _ = self.responder.send(Response::Error(error));
+        }
+    }
+}
+
+// The items dropped or refused by a full queue are counted by the queue, only
+// a closed queue is a failure.
+fn is_sent<T>(result: Result<T, TrySendError<Request>>) -> bool {
+    match result {
+        Err(TrySendError::Disconnected(_)) => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use conditions::ConditionsBuilder;
+    use config::{ContextConfig, ContextConfigBuilder};
+    use context::shard::Shard;
+    use dispatcher::Response;
+    use dispatcher::request::Request;
+    use message::{Message, MessageBuilder};
+    use queue::{self, QueueConfig, QueueReceiver};
+    use super::Router;
+
+    fn map_context() -> Vec<ContextConfig> {
+        let condition = ConditionsBuilder::new(Duration::from_secs(3600)).build();
+        vec![ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                 .patterns(vec!["LOGIN".to_owned()])
+                 .context_id(Some(vec!["user".into()]))
+                 .build()]
+    }
+
+    fn login(user: &str) -> Arc<Message> {
+        Arc::new(MessageBuilder::new("LOGIN", "login").pair("user", user).build())
+    }
+
+    fn router(shards: usize, forwards_time: bool) -> (Router, Vec<QueueReceiver<Request>>) {
+        let (senders, receivers) = (0..shards)
+                                       .map(|_| queue::queue(QueueConfig::unbounded()))
+                                       .unzip();
+        let (responder, _) = queue::queue(QueueConfig::unbounded());
+        (Router::new(map_context(), senders, responder, forwards_time), receivers)
+    }
+
+    fn owner(user: &str) -> usize {
+        Shard::of_key(&vec![("user".to_owned(), Some(user.to_owned()))], 2).index()
+    }
+
+    #[test]
+    fn test_given_router_when_a_message_is_routed_then_only_the_owner_shard_receives_it() {
+        let (mut router, receivers) = router(2, false);
+        for i in 0..10 {
+            let user = format!("user{}", i);
+            assert_true!(router.route(Request::Message(login(&user)), None));
+            match receivers[owner(&user)].try_recv() {
+                Ok(Request::Message(message)) => assert_eq!(Some(&user), message.get("user")),
+                _ => unreachable!(),
+            }
+            assert_true!(receivers.iter().all(|rx| rx.try_recv().is_err()));
+        }
+    }
+
+    #[test]
+    fn test_given_router_when_a_message_is_not_subscribed_then_no_shard_receives_it() {
+        let (mut router, receivers) = router(2, false);
+        let message = Arc::new(MessageBuilder::new("LOGOUT", "logout").pair("user", "bob").build());
+        assert_true!(router.route(Request::Message(message), None));
+        assert_true!(receivers.iter().all(|rx| rx.try_recv().is_err()));
+    }
+
+    #[test]
+    fn test_given_router_forwarding_time_when_a_message_is_routed_then_the_other_shards_get_its_time() {
+        let (mut router, receivers) = router(2, true);
+        let stamp = Some(Duration::from_secs(5));
+        assert_true!(router.route(Request::Message(login("bob")), stamp));
+        let other = 1 - owner("bob");
+        match receivers[other].try_recv() {
+            Ok(Request::Batch(batch)) => assert_true!(batch.is_empty()),
+            _ => unreachable!(),
+        }
+        assert_eq!(stamp, receivers[other].last_stamp());
+    }
+
+    #[test]
+    fn test_given_router_when_a_batch_is_routed_then_every_shard_gets_its_messages_in_order() {
+        let (mut router, receivers) = router(2, false);
+        let users = (0..10).map(|i| format!("user{}", i)).collect::<Vec<String>>();
+        let batch = users.iter().map(|user| login(user)).collect();
+        assert_true!(router.route(Request::Batch(batch), None));
+        for (index, rx) in receivers.iter().enumerate() {
+            let expected = users.iter().filter(|user| owner(user) == index).collect::<Vec<&String>>();
+            match rx.try_recv() {
+                Ok(Request::Batch(batch)) => {
+                    let routed = batch.iter().filter_map(|message| message.get("user")).collect::<Vec<&String>>();
+                    assert_eq!(expected, routed);
+                }
+                _ => assert_true!(expected.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_given_router_when_a_shard_is_not_running_then_it_is_reported_once() {
+        let (senders, mut receivers): (Vec<_>, Vec<_>) = (0..2)
+                                                             .map(|_| queue::queue(QueueConfig::unbounded()))
+                                                             .unzip();
+        let (responder, responses) = queue::queue(QueueConfig::unbounded());
+        let mut router = Router::new(map_context(), senders, responder, false);
+        let stopped = owner("bob");
+        drop(receivers.remove(stopped));
+        assert_true!(router.route(Request::Message(login("bob")), None));
+        assert_true!(router.route(Request::Message(login("bob")), None));
+        match responses.try_recv() {
+            Ok(Response::Error(error)) => assert_true!(error.contains(&format!("shard {}", stopped))),
+            _ => unreachable!(),
+        }
+        assert_true!(responses.try_recv().is_err());
+    }
+}
@@ -9,7 +9,7 @@
 use std::time::Duration;
 use std::str::FromStr;
 use std::error::Error;
-use serde::de;
+use serde::{de, ser};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct SerializableDuration(pub Duration);
@@ -43,6 +43,15 @@ impl de::Deserialize for SerializableDuration {
     }
 }
 
+impl ser::Serialize for SerializableDuration {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: ser::Serializer
+    {
+        let millis = self.0.as_secs() * 1000 + (self.0.subsec_nanos() / 1_000_000) as u64;
+        serializer.serialize_u64(millis)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
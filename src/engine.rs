@@ -0,0 +1,324 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use action::Alert;
+use config::action::message::InjectMode;
+use context::ContextMap;
+use control::{CloseMode, Selector};
+use dispatcher::Response;
+use dispatcher::request::{Request, RequestHandle};
+use dispatcher::response::ResponseSender;
+use dispatcher::handlers::exit::ExitEventHandler;
+use dispatcher::handlers::timer::TimerEventHandler;
+use dispatcher::handlers::message::MessageEventHandler;
+use dispatcher::handlers::close::CloseEventHandler;
+use dispatcher::handlers::query::QueryEventHandler;
+use dispatcher::handlers::reload::ReloadEventHandler;
+use dispatcher::handlers::shutdown::ShutdownEventHandler;
+use dispatcher::handlers::snapshot::SnapshotEventHandler;
+use dispatcher::handlers::sync::SyncEventHandler;
+use message::Message;
+use queue::QueueSender;
+use reactor::{Event, EventHandler, SharedData};
+use timer::TimerEvent;
+
+// Runs the contexts in the caller's thread. The time of the contexts moves
+// only when it's advanced, so the engine can be driven by an external event
+// loop. The threaded Correlator dispatches its requests to an Engine.
+//
+// The alerts of loopback actions are handled as messages right after the
+// request which generated them, they are not sent to the responder.
+#[allow(type_complexity)]
+pub struct Engine {
+    context_map: ContextMap,
+    handlers: BTreeMap<RequestHandle, Box<for<'a> EventHandler<Request, SharedData<'a>>>>,
+    loopback: Option<QueueSender<Request>>,
+}
+
+impl Engine {
+    pub fn new(context_map: ContextMap) -> Engine {
+        let mut engine = Engine {
+            context_map: context_map,
+            handlers: BTreeMap::new(),
+            loopback: None,
+        };
+        engine.register_handler(Box::new(ExitEventHandler::default()));
+        engine.register_handler(Box::new(TimerEventHandler::default()));
+        engine.register_handler(Box::new(MessageEventHandler::default()));
+        engine.register_handler(Box::new(SnapshotEventHandler::default()));
+        engine.register_handler(Box::new(QueryEventHandler::default()));
+        engine.register_handler(Box::new(CloseEventHandler::default()));
+        engine.register_handler(Box::new(ReloadEventHandler::default()));
+        engine.register_handler(Box::new(SyncEventHandler::default()));
+        engine.register_handler(Box::new(ShutdownEventHandler::default()));
+        engine
+    }
+
+    pub fn process(&mut self, message: Message) -> Vec<Alert> {
+        self.dispatch_collect(Request::Message(Arc::new(message)))
+    }
+
+    // Moves the time of every context forward by elapsed and returns the
+    // alerts of the expired states.
+    pub fn advance(&mut self, elapsed: Duration) -> Vec<Alert> {
+        self.dispatch_collect(Request::Timer(TimerEvent(elapsed)))
+    }
+
+    // Closes the selected states and returns the alerts of their actions.
+    pub fn close(&mut self, selector: Selector) -> Vec<Alert> {
+        self.dispatch_collect(Request::Close(selector, CloseMode::Execute))
+    }
+
+    // Drops the selected states without executing their actions.
+    pub fn discard(&mut self, selector: Selector) {
+        let _ = self.dispatch_collect(Request::Close(selector, CloseMode::Discard));
+    }
+
+    // The time the engine should be advanced by, at the latest, to expire the
+    // next state. None if no state has a deadline.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.context_map.next_timeout()
+    }
+
+    pub fn context_map(&self) -> &ContextMap {
+        &self.context_map
+    }
+
+    pub fn context_map_mut(&mut self) -> &mut ContextMap {
+        &mut self.context_map
+    }
+
+    pub fn into_context_map(self) -> ContextMap {
+        self.context_map
+    }
+
+    // The loopback messages are sent to the channel instead of being handled
+    // by the engine, so they can reach the other shards too.
+    pub fn set_loopback_channel(&mut self, channel: Option<QueueSender<Request>>) {
+        self.loopback = channel;
+    }
+
+    pub fn register_handler(&mut self,
+                            handler: Box<for<'a> EventHandler<Request, SharedData<'a>>>) {
+        self.handlers.insert(handler.handle(), handler);
+    }
+
+    pub fn remove_handler_by_handle(&mut self, handle: &RequestHandle) {
+        self.handlers.remove(handle);
+    }
+
+    pub fn dispatch(&mut self, request: Request, responder: &mut ResponseSender) {
+        let mut responder = LoopbackResponder::new(responder);
+        self.dispatch_request(request, &mut responder);
+        while let Some(message) = responder.messages.pop_front() {
+            if !self.context_map.accept_loopback(&message) {
+                let error = format!("loopback message is dropped, the maximum loopback depth is \
+                                     reached; uuid={}",
+                                    message.uuid());
+                responder.send_response(Response::Error(error));
+                continue;
+            }
+            let request = Request::Message(Arc::new(message));
+            match self.loopback {
+                // The loopback messages bypass the capacity of the queue: a
+                // dispatcher blocked on a full queue couldn't empty its own.
+                Some(ref channel) => {
+                    if channel.send(request).is_err() {
+                        warn!("Engine: loopback message is dropped, the request queue is closed");
+                        let error = "loopback message is dropped, the request queue is closed";
+                        responder.send_response(Response::Error(error.to_owned()));
+                    }
+                }
+                None => self.dispatch_request(request, &mut responder),
+            }
+        }
+    }
+
+    fn dispatch_request(&mut self, request: Request, responder: &mut ResponseSender) {
+        let handler = match self.handlers.get_mut(&request.handle()) {
+            Some(handler) => handler,
+            None => {
+                trace!("Engine: no handler found for event");
+                return;
+            }
+        };
+        let mut shared_data = SharedData::new(&mut self.context_map, responder);
+        handler.handle_event(request, &mut shared_data);
+    }
+
+    fn dispatch_collect(&mut self, request: Request) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        self.dispatch(request, &mut alerts);
+        alerts
+    }
+}
+
+// Keeps the messages of the loopback alerts, the other responses are
+// forwarded.
+struct LoopbackResponder<'a> {
+    responder: &'a mut ResponseSender,
+    messages: VecDeque<Message>,
+}
+
+impl<'a> LoopbackResponder<'a> {
+    fn new(responder: &'a mut ResponseSender) -> LoopbackResponder<'a> {
+        LoopbackResponder {
+            responder: responder,
+            messages: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a> ResponseSender for LoopbackResponder<'a> {
+    fn send_response(&mut self, response: Response) {
+        match response {
+            Response::Alert(alert) => {
+                if alert.inject_mode == InjectMode::Loopback {
+                    self.messages.push_back(alert.message);
+                } else {
+                    self.responder.send_response(Response::Alert(alert));
+                }
+            }
+            response => self.responder.send_response(response),
+        }
+    }
+}
+
+impl ResponseSender for Vec<Alert> {
+    fn send_response(&mut self, response: Response) {
+        if let Response::Alert(alert) = response {
+            self.push(alert);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use conditions::ConditionsBuilder;
+    use config::{ContextConfig, ContextConfigBuilder};
+    use config::action::message::{InjectMode, MessageActionBuilder};
+    use context::ContextMap;
+    use control::Selector;
+    use action::Alert;
+    use dispatcher::request::Request;
+    use message::MessageBuilder;
+    use std::sync::Arc;
+    use super::Engine;
+
+    fn engine(uuid: Uuid) -> Engine {
+        let condition = ConditionsBuilder::new(Duration::from_millis(100)).build();
+        let action = MessageActionBuilder::new("CLOSED", "closed").build();
+        let contexts = vec![ContextConfigBuilder::new(uuid, condition)
+                                .patterns(vec!["p1".to_owned()])
+                                .actions(vec![action.into()])
+                                .build()];
+        Engine::new(ContextMap::from_configs(contexts))
+    }
+
+    #[test]
+    fn test_given_engine_when_a_batch_is_dispatched_then_the_contexts_handle_their_messages_in_turn
+        () {
+        let config = |uuid: &str| {
+            let condition = ConditionsBuilder::new(Duration::from_millis(100)).max_size(1).build();
+            let action = MessageActionBuilder::new(uuid, "closed").build();
+            ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                .patterns(vec!["p1".to_owned()])
+                .actions(vec![action.into()])
+                .build()
+        };
+        let mut engine = Engine::new(ContextMap::from_configs(vec![config("A"), config("B")]));
+        let message = || Arc::new(MessageBuilder::new("p1", "message").build());
+        let uuids = |alerts: Vec<Alert>| {
+            alerts.iter().map(|alert| alert.message.uuid().clone()).collect::<Vec<String>>()
+        };
+        let mut alerts = engine.dispatch_collect(Request::Message(message()));
+        alerts.extend(engine.dispatch_collect(Request::Message(message())));
+        assert_eq!(vec!["A", "B", "A", "B"], uuids(alerts));
+        let alerts = engine.dispatch_collect(Request::Batch(vec![message(), message()]));
+        assert_eq!(vec!["A", "A", "B", "B"], uuids(alerts));
+    }
+
+    #[test]
+    fn test_given_engine_when_it_is_advanced_past_the_timeout_then_it_returns_the_alerts() {
+        let mut engine = engine(Uuid::new_v4());
+        assert_true!(engine.process(MessageBuilder::new("p1", "message").build()).is_empty());
+        assert_eq!(Some(Duration::from_millis(100)), engine.next_timeout());
+        assert_true!(engine.advance(Duration::from_millis(99)).is_empty());
+        let alerts = engine.advance(Duration::from_millis(1));
+        assert_eq!(1, alerts.len());
+        assert_eq!("CLOSED", alerts[0].message.uuid());
+        assert_eq!(None, engine.next_timeout());
+    }
+
+    #[test]
+    fn test_given_engine_when_a_state_is_closed_then_the_alerts_are_returned_without_advancing
+        () {
+        let uuid = Uuid::new_v4();
+        let mut engine = engine(uuid);
+        let _ = engine.process(MessageBuilder::new("p1", "message").build());
+        assert_eq!(1, engine.close(Selector::Context(uuid)).len());
+        assert_true!(engine.advance(Duration::from_millis(100)).is_empty());
+    }
+
+    #[test]
+    fn test_given_engine_when_a_state_is_discarded_then_no_alert_is_returned() {
+        let mut engine = engine(Uuid::new_v4());
+        let _ = engine.process(MessageBuilder::new("p1", "message").build());
+        engine.discard(Selector::All);
+        assert_true!(engine.advance(Duration::from_millis(100)).is_empty());
+        assert_true!(engine.context_map().query()[0].states.is_empty());
+    }
+
+    // Closes a state for every message with the pattern and sends the
+    // generated message back.
+    fn loopback_context(pattern: &str, generated: &str) -> ContextConfig {
+        let condition = ConditionsBuilder::new(Duration::from_millis(100)).max_size(1).build();
+        let action = MessageActionBuilder::new(generated, "generated")
+                         .inject_mode(InjectMode::Loopback)
+                         .build();
+        ContextConfigBuilder::new(Uuid::new_v4(), condition)
+            .patterns(vec![pattern.to_owned()])
+            .actions(vec![action.into()])
+            .build()
+    }
+
+    #[test]
+    fn test_given_engine_when_a_loopback_alert_is_generated_then_it_is_handled_as_a_message() {
+        let condition = ConditionsBuilder::new(Duration::from_millis(100)).max_size(1).build();
+        let action = MessageActionBuilder::new("DONE", "done").build();
+        let contexts = vec![loopback_context("p1", "p2"),
+                            ContextConfigBuilder::new(Uuid::new_v4(), condition)
+                                .patterns(vec!["p2".to_owned()])
+                                .actions(vec![action.into()])
+                                .build()];
+        let mut engine = Engine::new(ContextMap::from_configs(contexts));
+        let alerts = engine.process(MessageBuilder::new("p1", "message").build());
+        assert_eq!(1, alerts.len());
+        assert_eq!("DONE", alerts[0].message.uuid());
+        assert_eq!(0, engine.context_map().dropped_loopbacks());
+    }
+
+    #[test]
+    fn test_given_engine_with_loopback_cycle_when_the_max_depth_is_reached_then_the_message_is_dropped() {
+        let mut context_map = ContextMap::from_configs(vec![loopback_context("LOOP", "LOOP")]);
+        context_map.set_max_loopback_depth(3);
+        let mut engine = Engine::new(context_map);
+        assert_true!(engine.process(MessageBuilder::new("LOOP", "message").build()).is_empty());
+        assert_eq!(1, engine.context_map().dropped_loopbacks());
+        assert_true!(engine.process(MessageBuilder::new("LOOP", "message").build()).is_empty());
+        assert_eq!(2, engine.context_map().dropped_loopbacks());
+    }
+}
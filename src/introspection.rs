@@ -0,0 +1,41 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use context::map::map::ContextKey;
+
+// Describes what a context is tracking at the time of the query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextInfo {
+    pub uuid: Uuid,
+    pub name: Option<String>,
+    pub open: bool,
+    pub states: Vec<StateInfo>,
+}
+
+impl ContextInfo {
+    // Adds the states of the same context of another shard.
+    pub fn merge(&mut self, other: ContextInfo) {
+        self.open = self.open || other.open;
+        self.states.extend(other.states);
+    }
+}
+
+// The key of the states of a context without context_id is empty. The age
+// and the idle time are counted by the timer events of the correlator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateInfo {
+    pub key: ContextKey,
+    pub age: Duration,
+    pub idle: Duration,
+    pub message_count: usize,
+    pub children: Vec<ContextInfo>,
+}
@@ -0,0 +1,256 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::Arc;
+
+use super::{Checkpoint, Entry};
+use dispatcher::request::Request;
+use duration::SerializableDuration;
+use serde::de::{self, Deserialize, Deserializer, Error, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+use timer::TimerEvent;
+
+impl Serialize for Entry {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("Entry",
+                                    EntryMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct EntryMapVisitor<'a> {
+    value: &'a Entry,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for EntryMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("sequence", self.value.sequence)))),
+            2 => {
+                match self.value.request {
+                    Request::Message(ref message) => {
+                        Ok(Some(try!(serializer.serialize_struct_elt("message", message))))
+                    }
+                    Request::Timer(ref event) => {
+                        let duration = SerializableDuration(event.0);
+                        Ok(Some(try!(serializer.serialize_struct_elt("timer", duration))))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Deserialize for Entry {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Entry, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Entry", &[], EntryVisitor)
+    }
+}
+
+enum EntryField {
+    Sequence,
+    Message,
+    Timer,
+}
+
+impl Deserialize for EntryField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<EntryField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = EntryField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<EntryField, E>
+                where E: Error
+            {
+                match value {
+                    "sequence" => Ok(EntryField::Sequence),
+                    "message" => Ok(EntryField::Message),
+                    "timer" => Ok(EntryField::Timer),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct EntryVisitor;
+
+impl Visitor for EntryVisitor {
+    type Value = Entry;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Entry, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut sequence = None;
+        let mut request = None;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                EntryField::Sequence => sequence = Some(try!(visitor.visit_value())),
+                EntryField::Message => {
+                    request = Some(Request::Message(Arc::new(try!(visitor.visit_value()))))
+                }
+                EntryField::Timer => {
+                    let duration: SerializableDuration = try!(visitor.visit_value());
+                    request = Some(Request::Timer(TimerEvent(duration.0)))
+                }
+            }
+        }
+
+        let sequence = match sequence {
+            Some(sequence) => sequence,
+            None => return visitor.missing_field("sequence"),
+        };
+
+        let request = match request {
+            Some(request) => request,
+            None => return visitor.missing_field("message"),
+        };
+
+        try!(visitor.end());
+
+        Ok(Entry {
+            sequence: sequence,
+            request: request,
+        })
+    }
+}
+
+impl Serialize for Checkpoint {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("Checkpoint",
+                                    CheckpointMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct CheckpointMapVisitor<'a> {
+    value: &'a Checkpoint,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for CheckpointMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("sequence", self.value.sequence)))),
+            2 => Ok(Some(try!(serializer.serialize_struct_elt("alerts", self.value.alerts)))),
+            3 => Ok(Some(try!(serializer.serialize_struct_elt("delivered", self.value.delivered)))),
+            4 => Ok(Some(try!(serializer.serialize_struct_elt("pending", &self.value.pending)))),
+            5 => Ok(Some(try!(serializer.serialize_struct_elt("snapshot", &self.value.snapshot)))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Deserialize for Checkpoint {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Checkpoint, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Checkpoint", &[], CheckpointVisitor)
+    }
+}
+
+enum CheckpointField {
+    Sequence,
+    Alerts,
+    Delivered,
+    Pending,
+    Snapshot,
+}
+
+impl Deserialize for CheckpointField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<CheckpointField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = CheckpointField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<CheckpointField, E>
+                where E: Error
+            {
+                match value {
+                    "sequence" => Ok(CheckpointField::Sequence),
+                    "alerts" => Ok(CheckpointField::Alerts),
+                    "delivered" => Ok(CheckpointField::Delivered),
+                    "pending" => Ok(CheckpointField::Pending),
+                    "snapshot" => Ok(CheckpointField::Snapshot),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct CheckpointVisitor;
+
+impl Visitor for CheckpointVisitor {
+    type Value = Checkpoint;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Checkpoint, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut sequence = 0;
+        let mut alerts = 0;
+        let mut delivered = 0;
+        let mut pending = Vec::new();
+        let mut snapshot = None;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                CheckpointField::Sequence => sequence = try!(visitor.visit_value()),
+                CheckpointField::Alerts => alerts = try!(visitor.visit_value()),
+                CheckpointField::Delivered => delivered = try!(visitor.visit_value()),
+                CheckpointField::Pending => pending = try!(visitor.visit_value()),
+                CheckpointField::Snapshot => snapshot = Some(try!(visitor.visit_value())),
+            }
+        }
+
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return visitor.missing_field("snapshot"),
+        };
+
+        try!(visitor.end());
+
+        Ok(Checkpoint {
+            sequence: sequence,
+            alerts: alerts,
+            delivered: delivered,
+            pending: pending,
+            snapshot: snapshot,
+        })
+    }
+}
@@ -0,0 +1,649 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json;
+
+use action::Alert;
+use context::ContextMap;
+use dispatcher::Response;
+use dispatcher::request::Request;
+use dispatcher::response::ResponseSender;
+use snapshot::Snapshot;
+
+mod deser;
+
+const JOURNAL_FILE: &'static str = "journal";
+const CHECKPOINT_FILE: &'static str = "checkpoint.json";
+const DELIVERED_FILE: &'static str = "delivered";
+
+pub struct JournalConfig {
+    pub directory: PathBuf,
+    pub checkpoint_interval: u64,
+    pub sync: bool,
+    pub max_pending_alerts: usize,
+}
+
+impl JournalConfig {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> JournalConfig {
+        JournalConfig {
+            directory: directory.into(),
+            checkpoint_interval: 1000,
+            sync: false,
+            max_pending_alerts: 10000,
+        }
+    }
+
+    // A checkpoint is taken after this many journaled requests.
+    pub fn checkpoint_interval(mut self, checkpoint_interval: u64) -> JournalConfig {
+        self.checkpoint_interval = checkpoint_interval;
+        self
+    }
+
+    // If true, every entry is synced to the disk, so the journal survives
+    // a power loss too (not just a crash of the process).
+    pub fn sync(mut self, sync: bool) -> JournalConfig {
+        self.sync = sync;
+        self
+    }
+
+    // At most this many undelivered alerts are kept for a resend. When the
+    // limit is reached an error is reported and the oldest one is not kept,
+    // it is lost if the process crashes before it is delivered.
+    pub fn max_pending_alerts(mut self, max_pending_alerts: usize) -> JournalConfig {
+        self.max_pending_alerts = max_pending_alerts;
+        self
+    }
+}
+
+pub struct Entry {
+    pub sequence: u64,
+    pub request: Request,
+}
+
+// The alerts accepted by the alert queue are numbered from the start of the
+// journal. The checkpoint contains the alerts which were accepted but not yet
+// delivered, so they are not lost when the journal is truncated.
+pub struct Checkpoint {
+    pub sequence: u64,
+    pub alerts: usize,
+    pub delivered: usize,
+    pub pending: Vec<Alert>,
+    pub snapshot: Snapshot,
+}
+
+pub struct Recovery {
+    pub snapshot: Snapshot,
+    pub requests: Vec<Request>,
+}
+
+// Appends the accepted requests to the journal before they are applied.
+// After a crash the last checkpoint is restored and the rest of the journal
+// is replayed. The alerts which were already delivered (see DeliveryLog) are
+// suppressed during the replay, the rest of them are sent again.
+pub struct Journal {
+    config: JournalConfig,
+    file: File,
+    sequence: u64,
+    since_checkpoint: u64,
+    alerts: usize,
+    suppress_until: usize,
+    pending: VecDeque<Alert>,
+    delivered: Arc<AtomicUsize>,
+    overflowing: bool,
+    recovery: Option<Recovery>,
+}
+
+impl Journal {
+    pub fn open(config: JournalConfig) -> io::Result<(Journal, DeliveryLog)> {
+        try!(fs::create_dir_all(&config.directory));
+        let checkpoint = try!(Journal::read_checkpoint(&config.directory.join(CHECKPOINT_FILE)));
+        let checkpoint = checkpoint.unwrap_or_else(|| {
+            Checkpoint {
+                sequence: 0,
+                alerts: 0,
+                delivered: 0,
+                pending: Vec::new(),
+                snapshot: Snapshot::default(),
+            }
+        });
+        let delivered_path = config.directory.join(DELIVERED_FILE);
+        let delivered = try!(DeliveryLog::read(&delivered_path));
+        let journal_path = config.directory.join(JOURNAL_FILE);
+        let entries = try!(Journal::read_entries(&journal_path, checkpoint.sequence));
+        let file = try!(OpenOptions::new().create(true).append(true).open(&journal_path));
+
+        let Checkpoint {sequence, alerts, delivered: checkpointed, pending, snapshot} = checkpoint;
+        let sequence = entries.last().map_or(sequence, |entry| entry.sequence);
+        let delivered = cmp::max(delivered, checkpointed);
+        let sync = config.sync;
+        let delivered = Arc::new(AtomicUsize::new(delivered));
+        let mut journal = Journal {
+            config: config,
+            file: file,
+            sequence: sequence,
+            since_checkpoint: 0,
+            alerts: alerts,
+            suppress_until: delivered.load(Ordering::SeqCst),
+            pending: pending.into_iter().collect(),
+            delivered: delivered.clone(),
+            overflowing: false,
+            recovery: Some(Recovery {
+                snapshot: snapshot,
+                requests: entries.into_iter().map(|entry| entry.request).collect(),
+            }),
+        };
+        journal.prune_delivered();
+        let delivery_log = DeliveryLog {
+            path: delivered_path,
+            delivered: delivered.clone(),
+            persisted: delivered.load(Ordering::SeqCst),
+            sync: sync,
+        };
+        Ok((journal, delivery_log))
+    }
+
+    fn read_checkpoint(path: &Path) -> io::Result<Option<Checkpoint>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut content = String::new();
+        try!(file.read_to_string(&mut content));
+        serde_json::from_str::<Checkpoint>(&content)
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    fn read_entries(path: &Path, checkpoint: u64) -> io::Result<Vec<Entry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = try!(line);
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => {
+                    if entry.sequence > checkpoint {
+                        entries.push(entry);
+                    }
+                }
+                Err(error) => {
+                    // The process probably crashed while the entry was written.
+                    warn!("Journal: failed to read entry, ignoring the rest of the journal; \
+                           error={}",
+                          error);
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    pub fn take_recovery(&mut self) -> Option<Recovery> {
+        self.recovery.take()
+    }
+
+    // Sends the alerts of the last checkpoint which were not delivered.
+    pub fn resend_pending(&mut self, responder: &mut ResponseSender) {
+        for i in &self.pending {
+            responder.send_response(Response::Alert(i.clone()));
+        }
+    }
+
+    // The messages of a batch are appended as separate entries.
+    pub fn append(&mut self, request: &Request) {
+        match *request {
+            Request::Message(_) | Request::Timer(_) => self.append_entry(request.clone()),
+            Request::Batch(ref messages) => {
+                for i in messages {
+                    self.append_entry(Request::Message(i.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn append_entry(&mut self, request: Request) {
+        self.sequence += 1;
+        let entry = Entry {
+            sequence: self.sequence,
+            request: request,
+        };
+        let result = serde_json::to_string(&entry)
+                         .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+                         .and_then(|mut line| {
+                             line.push('\n');
+                             self.file.write_all(line.as_bytes())
+                         })
+                         .and_then(|_| {
+                             if self.config.sync {
+                                 self.file.sync_data()
+                             } else {
+                                 Ok(())
+                             }
+                         });
+        if let Err(error) = result {
+            error!("Journal: failed to append entry; sequence={} error={}",
+                   self.sequence,
+                   error);
+        }
+        self.since_checkpoint += 1;
+    }
+
+    pub fn on_applied(&mut self, context_map: &ContextMap) {
+        self.prune_delivered();
+        if self.since_checkpoint >= self.config.checkpoint_interval {
+            self.checkpoint(context_map);
+        }
+    }
+
+    pub fn checkpoint(&mut self, context_map: &ContextMap) {
+        self.prune_delivered();
+        let checkpoint = Checkpoint {
+            sequence: self.sequence,
+            alerts: self.alerts,
+            delivered: self.delivered.load(Ordering::SeqCst),
+            pending: self.pending.iter().cloned().collect(),
+            snapshot: context_map.snapshot(),
+        };
+        match self.write_checkpoint(&checkpoint) {
+            Ok(()) => {
+                trace!("Journal: checkpoint is taken; sequence={}", self.sequence);
+                self.since_checkpoint = 0;
+            }
+            Err(error) => {
+                error!("Journal: failed to take checkpoint; sequence={} error={}",
+                       self.sequence,
+                       error)
+            }
+        }
+    }
+
+    fn write_checkpoint(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        let content = try!(serde_json::to_string(checkpoint)
+                               .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)));
+        let path = self.config.directory.join(CHECKPOINT_FILE);
+        try!(write_atomically(&path, content.as_bytes(), true));
+        // The entries before the checkpoint are skipped, even if the process
+        // crashes before the journal is truncated.
+        self.file.set_len(0)
+    }
+
+    fn prune_delivered(&mut self) {
+        let delivered = self.delivered.load(Ordering::SeqCst);
+        while !self.pending.is_empty() && self.alerts - self.pending.len() < delivered {
+            let _ = self.pending.pop_front();
+        }
+        if self.pending.len() < self.config.max_pending_alerts {
+            self.overflowing = false;
+        }
+    }
+
+    pub fn responder<'a>(&'a mut self, responder: &'a mut ResponseSender) -> JournalResponder<'a> {
+        JournalResponder {
+            responder: responder,
+            journal: self,
+        }
+    }
+}
+
+// Numbers the alerts and keeps them until they are delivered. While the
+// journal is replayed, the alerts which were already delivered are dropped.
+pub struct JournalResponder<'a> {
+    responder: &'a mut ResponseSender,
+    journal: &'a mut Journal,
+}
+
+impl<'a> ResponseSender for JournalResponder<'a> {
+    // The alerts dropped by the alert queue are not numbered, they wouldn't be
+    // counted by the DeliveryLog either.
+    fn send_response(&mut self, response: Response) {
+        let alert = match response {
+            Response::Alert(alert) => alert,
+            response => return self.responder.send_response(response),
+        };
+        let index = self.journal.alerts;
+        if index < self.journal.suppress_until {
+            self.journal.alerts += 1;
+            trace!("Journal: alert is already delivered, dropping it; index={}", index);
+            return;
+        }
+        if !self.responder.try_send_response(Response::Alert(alert.clone())) {
+            return;
+        }
+        self.journal.alerts += 1;
+        self.journal.pending.push_back(alert);
+        if self.journal.pending.len() > self.journal.config.max_pending_alerts {
+            let _ = self.journal.pending.pop_front();
+            // Reported once until the consumer catches up.
+            if !self.journal.overflowing {
+                self.journal.overflowing = true;
+                let error = format!("too many undelivered alerts, the oldest ones are not kept \
+                                     for a resend; max_pending_alerts={}",
+                                    self.journal.config.max_pending_alerts);
+                self.responder.send_response(Response::Error(error));
+            }
+        }
+    }
+}
+
+// Records the number of alerts handed to the consumer. The number is written
+// to the disk before the alert is handed over, so an alert is never sent
+// again after a crash; the one being handled when the process crashes is lost.
+pub struct DeliveryLog {
+    path: PathBuf,
+    delivered: Arc<AtomicUsize>,
+    persisted: usize,
+    sync: bool,
+}
+
+impl DeliveryLog {
+    pub fn on_delivered(&mut self) {
+        let _ = self.delivered.fetch_add(1, Ordering::SeqCst);
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        let delivered = self.delivered.load(Ordering::SeqCst);
+        if delivered == self.persisted {
+            return;
+        }
+        let content = delivered.to_string();
+        match write_atomically(&self.path, content.as_bytes(), self.sync) {
+            Ok(()) => self.persisted = delivered,
+            Err(error) => {
+                error!("DeliveryLog: failed to record delivered alerts; delivered={} error={}",
+                       delivered,
+                       error)
+            }
+        }
+    }
+
+    fn read(path: &Path) -> io::Result<usize> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        let mut content = String::new();
+        try!(file.read_to_string(&mut content));
+        content.trim()
+               .parse::<usize>()
+               .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+impl Drop for DeliveryLog {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn write_atomically(path: &Path, content: &[u8], sync: bool) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    {
+        let mut file = try!(File::create(&temporary));
+        try!(file.write_all(content));
+        if sync {
+            try!(file.sync_all());
+        }
+    }
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::mem;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::{DELIVERED_FILE, DeliveryLog, Journal, JournalConfig};
+    use conditions::ConditionsBuilder;
+    use config::ContextConfigBuilder;
+    use config::action::ExecCondition;
+    use config::action::message::MessageActionBuilder;
+    use context::ContextMap;
+    use dispatcher::Response;
+    use dispatcher::demux::Demultiplexer;
+    use dispatcher::handlers::message::MessageEventHandler;
+    use dispatcher::reactor::RequestReactor;
+    use dispatcher::request::Request;
+    use engine::Engine;
+    use message::MessageBuilder;
+    use queue::{queue, OverflowPolicy, QueueConfig};
+    use reactor::{EventHandler, Reactor, SharedData};
+    use test_utils::MockResponseSender;
+
+    fn directory() -> PathBuf {
+        env::temp_dir().join(format!("correlation-journal-{}", Uuid::new_v4().to_simple_string()))
+    }
+
+    fn context_map(uuid: Uuid) -> ContextMap {
+        let conditions = ConditionsBuilder::new(Duration::from_millis(10000)).build();
+        let action = MessageActionBuilder::new("OPENED", "opened")
+                         .when(ExecCondition {
+                             on_opened: true,
+                             on_closed: false,
+                         })
+                         .build();
+        let config = ContextConfigBuilder::new(uuid, conditions)
+                         .patterns(vec!["LOGIN".to_owned()])
+                         .context_id(Some(vec!["user".into()]))
+                         .actions(vec![action.into()])
+                         .build();
+        ContextMap::from_configs(vec![config])
+    }
+
+    fn login(user: &str) -> Request {
+        Request::Message(Arc::new(MessageBuilder::new("LOGIN", "login").pair("user", user).build()))
+    }
+
+    // Starts the dispatcher on the journal and returns the alerts it sends.
+    fn recover(directory: &PathBuf, uuid: Uuid) -> (ContextMap, Vec<Response>) {
+        let (map, responses, _) = recover_with_delivery_log(directory, uuid);
+        (map, responses)
+    }
+
+    fn recover_with_delivery_log(directory: &PathBuf,
+                                 uuid: Uuid)
+                                 -> (ContextMap, Vec<Response>, DeliveryLog) {
+        let (journal, delivery_log) = Journal::open(JournalConfig::new(directory.clone()))
+                               .expect("Failed to open a Journal");
+        let (tx, rx) = queue(QueueConfig::unbounded());
+        let (response_tx, response_rx) = mpsc::channel();
+        let _ = tx.send(Request::Exit);
+        let _ = tx.send(Request::Exit);
+        let mut reactor = RequestReactor::new(Demultiplexer::new(rx),
+                                              Engine::new(context_map(uuid)),
+                                              Box::new(response_tx));
+        reactor.set_journal(Some(journal));
+        reactor.handle_events();
+        let responses = response_rx.try_iter()
+                                   .filter(|response| {
+                                       if let Response::Alert(_) = *response {
+                                           true
+                                       } else {
+                                           false
+                                       }
+                                   })
+                                   .collect();
+        (reactor.engine.into_context_map(), responses, delivery_log)
+    }
+
+    #[test]
+    fn test_given_journal_when_the_process_crashes_before_an_alert_is_delivered_then_it_is_sent_again
+        () {
+        let directory = directory();
+        let uuid = Uuid::new_v4();
+        {
+            let (mut journal, _) = Journal::open(JournalConfig::new(directory.clone()))
+                                       .expect("Failed to open a Journal");
+            let mut map = context_map(uuid);
+            let mut responder = MockResponseSender::default();
+            journal.append(&login("alice"));
+            let mut responder = journal.responder(&mut responder);
+            MessageEventHandler.handle_event(login("alice"), &mut SharedData::new(&mut map, &mut responder));
+        }
+        let (map, alerts) = recover(&directory, uuid);
+        assert_eq!(1, alerts.len());
+        assert_eq!(1, map.snapshot().contexts[0].states.len());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_journal_when_the_process_crashes_after_an_alert_is_delivered_then_it_is_not_duplicated
+        () {
+        let directory = directory();
+        let uuid = Uuid::new_v4();
+        {
+            let (mut journal, mut delivery_log) = Journal::open(JournalConfig::new(directory.clone()))
+                                                      .expect("Failed to open a Journal");
+            let mut map = context_map(uuid);
+            let mut responder = MockResponseSender::default();
+            for user in &["alice", "bob"] {
+                journal.append(&login(user));
+                let mut responder = journal.responder(&mut responder);
+                MessageEventHandler.handle_event(login(user), &mut SharedData::new(&mut map, &mut responder));
+            }
+            delivery_log.on_delivered();
+        }
+        let (map, alerts) = recover(&directory, uuid);
+        assert_eq!(1, alerts.len());
+        assert_eq!(2, map.snapshot().contexts[0].states.len());
+
+        let (map, alerts) = recover(&directory, uuid);
+        assert_eq!(1, alerts.len());
+        assert_eq!(2, map.snapshot().contexts[0].states.len());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_journal_when_the_alert_queue_drops_an_alert_then_it_is_not_kept_for_a_resend() {
+        let directory = directory();
+        let (mut journal, _) = Journal::open(JournalConfig::new(directory.clone()))
+                                   .expect("Failed to open a Journal");
+        let mut map = context_map(Uuid::new_v4());
+        let (mut responder, _rx) = queue(QueueConfig::bounded(1, OverflowPolicy::DropNewest));
+        for user in &["alice", "bob"] {
+            let mut responder = journal.responder(&mut responder);
+            MessageEventHandler.handle_event(login(user), &mut SharedData::new(&mut map, &mut responder));
+        }
+        assert_eq!(1, journal.alerts);
+        assert_eq!(1, journal.pending.len());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_journal_with_max_pending_alerts_when_more_alerts_are_undelivered_then_the_oldest_are_not_kept
+        () {
+        let directory = directory();
+        let config = JournalConfig::new(directory.clone()).max_pending_alerts(1);
+        let (mut journal, _) = Journal::open(config).expect("Failed to open a Journal");
+        let mut map = context_map(Uuid::new_v4());
+        let mut responder = MockResponseSender::default();
+        for user in &["alice", "bob"] {
+            let mut responder = journal.responder(&mut responder);
+            MessageEventHandler.handle_event(login(user), &mut SharedData::new(&mut map, &mut responder));
+        }
+        assert_eq!(2, journal.alerts);
+        assert_eq!(1, journal.pending.len());
+        let errors = responder.0.iter().filter(|response| match **response {
+            Response::Error(_) => true,
+            _ => false,
+        });
+        assert_eq!(1, errors.count());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_delivery_log_when_an_alert_is_delivered_then_the_count_is_written_immediately() {
+        let directory = directory();
+        let (_, mut delivery_log) = Journal::open(JournalConfig::new(directory.clone()))
+                                        .expect("Failed to open a Journal");
+        let path = directory.join(DELIVERED_FILE);
+        delivery_log.on_delivered();
+        assert_eq!(1, DeliveryLog::read(&path).unwrap());
+        delivery_log.on_delivered();
+        assert_eq!(2, DeliveryLog::read(&path).unwrap());
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_journal_when_the_process_crashes_repeatedly_then_every_alert_is_delivered_exactly_once
+        () {
+        let directory = directory();
+        let uuid = Uuid::new_v4();
+        let users = ["alice", "bob", "carol"];
+        {
+            let (mut journal, mut delivery_log) = Journal::open(JournalConfig::new(directory.clone()))
+                                                      .expect("Failed to open a Journal");
+            let mut map = context_map(uuid);
+            let mut responder = MockResponseSender::default();
+            for user in &users {
+                journal.append(&login(user));
+                let mut responder = journal.responder(&mut responder);
+                MessageEventHandler.handle_event(login(user), &mut SharedData::new(&mut map, &mut responder));
+            }
+            delivery_log.on_delivered();
+            // A crash: nothing is flushed on the way out.
+            mem::forget(delivery_log);
+        }
+        // Every recovery hands one of the resent alerts over and crashes.
+        let mut delivered = 1;
+        loop {
+            let (_, alerts, mut delivery_log) = recover_with_delivery_log(&directory, uuid);
+            assert_eq!(users.len() - delivered, alerts.len());
+            if alerts.is_empty() {
+                break;
+            }
+            delivery_log.on_delivered();
+            delivered += 1;
+            mem::forget(delivery_log);
+        }
+        assert_eq!(users.len(), delivered);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_given_journal_when_a_batch_is_appended_then_its_messages_are_replayed() {
+        let directory = directory();
+        let uuid = Uuid::new_v4();
+        {
+            let (mut journal, _) = Journal::open(JournalConfig::new(directory.clone()))
+                                       .expect("Failed to open a Journal");
+            let batch = vec!["alice", "bob"]
+                            .into_iter()
+                            .map(|user| {
+                                Arc::new(MessageBuilder::new("LOGIN", "login").pair("user", user).build())
+                            })
+                            .collect();
+            journal.append(&Request::Batch(batch));
+        }
+        let (map, alerts) = recover(&directory, uuid);
+        assert_eq!(2, alerts.len());
+        assert_eq!(2, map.snapshot().contexts[0].states.len());
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -18,6 +18,7 @@ extern crate serde;
 extern crate serde_json;
 extern crate serde_yaml;
 extern crate rustc_serialize;
+extern crate regex;
 #[macro_use]
 extern crate log;
 
@@ -25,23 +26,42 @@ extern crate log;
 mod macros;
 
 pub use action::Alert;
+pub use clock::{Clock, SystemClock};
 pub use conditions::{Conditions, ConditionsBuilder};
 pub use config::action::ActionType;
 pub use dispatcher::{Response, ResponseHandle};
 pub use dispatcher::request::Request;
 pub use message::{Message, MessageBuilder};
-pub use context::ContextMap;
+pub use context::{ContextMap, ReloadReport};
+pub use control::{CloseMode, CloseReason, Selector, ShutdownMode};
+pub use engine::Engine;
+pub use context::budget::{MemoryBudget, MemoryUsage, Shedding};
+pub use context::map::key::{Case, KeyExpression, KeyExpressionBuilder, KeySource, MissingPolicy};
+pub use context::map::limit::{EvictionPolicy, KeyLimit};
 pub use reactor::{EventHandler, SharedData};
+pub use schedule::{Schedule, ScheduleBuilder, TimeSource, TimeWindow, Weekday};
+pub use introspection::{ContextInfo, StateInfo};
+pub use queue::{OverflowPolicy, QueueConfig, QueueSender, QueueStats};
+pub use journal::JournalConfig;
+pub use snapshot::{ContextSnapshot, OrphanPolicy, Snapshot, StateSnapshot};
 
 pub mod config;
 pub mod correlator;
 pub mod test_utils;
+mod clock;
 mod conditions;
 mod action;
 mod message;
 mod context;
+mod control;
 mod dispatcher;
+mod engine;
 mod reactor;
+mod introspection;
+mod queue;
+mod journal;
+mod schedule;
+mod snapshot;
 mod state;
 mod timer;
 mod duration;
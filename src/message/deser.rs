@@ -0,0 +1,130 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::collections::BTreeMap;
+
+use serde::de::{self, Deserialize, Deserializer, Error, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+
+use super::Message;
+
+impl Serialize for Message {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("Message",
+                                    MessageMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct MessageMapVisitor<'a> {
+    value: &'a Message,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for MessageMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("uuid", &self.value.uuid)))),
+            2 => Ok(Some(try!(serializer.serialize_struct_elt("name", &self.value.name)))),
+            3 => Ok(Some(try!(serializer.serialize_struct_elt("message", &self.value.message)))),
+            4 => Ok(Some(try!(serializer.serialize_struct_elt("values", &self.value.values)))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Deserialize for Message {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Message, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Message", &[], MessageVisitor)
+    }
+}
+
+enum Field {
+    Uuid,
+    Name,
+    Message,
+    Values,
+}
+
+impl Deserialize for Field {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Field, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = Field;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<Field, E>
+                where E: Error
+            {
+                match value {
+                    "uuid" => Ok(Field::Uuid),
+                    "name" => Ok(Field::Name),
+                    "message" => Ok(Field::Message),
+                    "values" => Ok(Field::Values),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct MessageVisitor;
+
+impl Visitor for MessageVisitor {
+    type Value = Message;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Message, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut uuid = None;
+        let mut name = None;
+        let mut message = None;
+        let mut values = BTreeMap::new();
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                Field::Uuid => uuid = Some(try!(visitor.visit_value())),
+                Field::Name => name = try!(visitor.visit_value()),
+                Field::Message => message = Some(try!(visitor.visit_value())),
+                Field::Values => values = try!(visitor.visit_value()),
+            }
+        }
+
+        let uuid = match uuid {
+            Some(uuid) => uuid,
+            None => return visitor.missing_field("uuid"),
+        };
+
+        let message = match message {
+            Some(message) => message,
+            None => return visitor.missing_field("message"),
+        };
+
+        try!(visitor.end());
+
+        Ok(Message {
+            uuid: uuid,
+            name: name,
+            message: message,
+            values: values,
+        })
+    }
+}
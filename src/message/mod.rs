@@ -7,13 +7,19 @@
 // modified, or distributed except according to those terms.
 
 use std::collections::BTreeMap;
+use std::mem;
 
 pub use self::builder::MessageBuilder;
 
 mod builder;
+mod deser;
 #[cfg(test)]
 mod test;
 
+// The number of times a message was generated by a loopback action from the
+// messages of the correlator.
+pub const LOOPBACK_HOPS: &'static str = "loopback_hops";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Message {
     uuid: String,
@@ -47,6 +53,18 @@ impl Message {
         self.values.insert(key.to_owned(), value.to_owned());
     }
 
+    // Zero for the messages which weren't generated by the correlator.
+    pub fn hops(&self) -> usize {
+        self.get(LOOPBACK_HOPS).and_then(|hops| hops.parse().ok()).unwrap_or(0)
+    }
+
+    // A rough estimation of the heap and stack memory used by the message.
+    pub fn estimated_size(&self) -> usize {
+        mem::size_of::<Message>() + self.uuid.len() +
+        self.name.as_ref().map_or(0, |name| name.len()) + self.message.len() +
+        estimated_values_size(&self.values)
+    }
+
     pub fn ids(&self) -> IdIterator {
         IdIterator {
             message: self,
@@ -55,6 +73,14 @@ impl Message {
     }
 }
 
+// A rough estimation of the memory used by the values, like the values of a
+// message.
+pub fn estimated_values_size(values: &BTreeMap<String, String>) -> usize {
+    values.iter()
+          .map(|(key, value)| key.len() + value.len() + 2 * mem::size_of::<String>())
+          .sum()
+}
+
 pub struct IdIterator<'a> {
     message: &'a Message,
     state: u8,
@@ -0,0 +1,453 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::cell::Cell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::time::{Duration, Instant};
+
+use clock::Clock;
+
+// What happens with a pushed item when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // The producer waits until there is room for the item.
+    Block,
+    // The pushed item is dropped.
+    DropNewest,
+    // The oldest pushed item in the queue is dropped to make room.
+    DropOldest,
+    // The item is returned in a TrySendError::Full.
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueConfig {
+    pub capacity: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl QueueConfig {
+    pub fn unbounded() -> QueueConfig {
+        QueueConfig {
+            capacity: None,
+            policy: OverflowPolicy::Block,
+        }
+    }
+
+    pub fn bounded(capacity: usize, policy: OverflowPolicy) -> QueueConfig {
+        QueueConfig {
+            capacity: Some(capacity),
+            policy: policy,
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig::unbounded()
+    }
+}
+
+// The weight of the pushed items in the queue and the counters of the items
+// lost because the queue was full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub len: usize,
+    pub dropped: u64,
+    pub refused: u64,
+}
+
+// The pushed items are counted against the capacity with their weight and
+// can be dropped. The sent items have no weight.
+struct Item<T> {
+    value: T,
+    weight: usize,
+    stamp: Option<Duration>,
+}
+
+struct State<T> {
+    items: VecDeque<Item<T>>,
+    pushed: usize,
+    senders: usize,
+    receiving: bool,
+    dropped: u64,
+    refused: u64,
+}
+
+struct Shared<T> {
+    config: QueueConfig,
+    clock: Option<Arc<Clock>>,
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().expect("Failed to lock the state of a queue")
+    }
+
+    fn stats(&self) -> QueueStats {
+        let state = self.lock();
+        QueueStats {
+            len: state.pushed,
+            dropped: state.dropped,
+            refused: state.refused,
+        }
+    }
+}
+
+// A multi-producer, single-consumer queue like mpsc::channel, but the items
+// sent by push() are limited by the capacity of the queue. The items sent by
+// send() are never dropped nor counted.
+pub fn queue<T>(config: QueueConfig) -> (QueueSender<T>, QueueReceiver<T>) {
+    new_queue(config, None)
+}
+
+// The items are stamped with the time of the clock when they are queued.
+pub fn queue_with_clock<T>(config: QueueConfig,
+                           clock: Arc<Clock>)
+                           -> (QueueSender<T>, QueueReceiver<T>) {
+    new_queue(config, Some(clock))
+}
+
+fn new_queue<T>(config: QueueConfig,
+                clock: Option<Arc<Clock>>)
+                -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        config: config,
+        clock: clock,
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            pushed: 0,
+            senders: 1,
+            receiving: true,
+            dropped: 0,
+            refused: 0,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    (QueueSender { shared: shared.clone() },
+     QueueReceiver {
+        shared: shared,
+        last_stamp: Cell::new(None),
+    })
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        self.send_at(item, None)
+    }
+
+    // The item keeps the stamp it got in another queue. Items without a
+    // stamp are stamped now if the queue has a clock.
+    pub fn send_at(&self, item: T, stamp: Option<Duration>) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        if !state.receiving {
+            return Err(SendError(item));
+        }
+        let stamp = stamp.or_else(|| self.stamp());
+        state.items.push_back(Item {
+            value: item,
+            weight: 0,
+            stamp: stamp,
+        });
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn stamp(&self) -> Option<Duration> {
+        self.shared.clock.as_ref().map(|clock| clock.now())
+    }
+
+    // Applies the overflow policy of the queue if it's full.
+    pub fn push(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.offer(item).map(|_| ())
+    }
+
+    pub fn push_at(&self, item: T, stamp: Option<Duration>) -> Result<(), TrySendError<T>> {
+        self.offer_at(item, stamp).map(|_| ())
+    }
+
+    // Like push(), but returns false if the item is dropped by the DropNewest
+    // policy.
+    pub fn offer(&self, item: T) -> Result<bool, TrySendError<T>> {
+        self.offer_at(item, None)
+    }
+
+    // The stamp is kept like by send_at().
+    pub fn offer_at(&self, item: T, stamp: Option<Duration>) -> Result<bool, TrySendError<T>> {
+        self.offer_weighted_at(item, stamp, 1)
+    }
+
+    // The item counts as weight items against the capacity, e.g. a batch of
+    // messages.
+    pub fn push_weighted(&self, item: T, weight: usize) -> Result<(), TrySendError<T>> {
+        self.offer_weighted_at(item, None, weight).map(|_| ())
+    }
+
+    // An item heavier than the capacity is accepted once the queue is empty.
+    pub fn offer_weighted_at(&self,
+                             item: T,
+                             stamp: Option<Duration>,
+                             weight: usize)
+                             -> Result<bool, TrySendError<T>> {
+        let weight = cmp::max(weight, 1);
+        let mut state = self.shared.lock();
+        if let Some(capacity) = self.shared.config.capacity {
+            let needed = cmp::max(cmp::min(weight, capacity), 1);
+            while state.receiving && state.pushed + needed > capacity {
+                match self.shared.config.policy {
+                    OverflowPolicy::Block => {
+                        state = self.shared
+                                    .not_full
+                                    .wait(state)
+                                    .expect("Failed to lock the state of a queue");
+                    }
+                    OverflowPolicy::DropNewest => {
+                        state.dropped += weight as u64;
+                        return Ok(false);
+                    }
+                    OverflowPolicy::DropOldest => {
+                        let oldest = state.items.iter().position(|item| item.weight > 0);
+                        match oldest.and_then(|index| state.items.remove(index)) {
+                            Some(item) => {
+                                state.pushed -= item.weight;
+                                state.dropped += item.weight as u64;
+                            }
+                            None => {
+                                state.dropped += weight as u64;
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    OverflowPolicy::Fail => {
+                        state.refused += weight as u64;
+                        return Err(TrySendError::Full(item));
+                    }
+                }
+            }
+        }
+        if !state.receiving {
+            return Err(TrySendError::Disconnected(item));
+        }
+        let stamp = stamp.or_else(|| self.stamp());
+        state.items.push_back(Item {
+            value: item,
+            weight: weight,
+            stamp: stamp,
+        });
+        state.pushed += weight;
+        self.shared.not_empty.notify_one();
+        Ok(true)
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> QueueSender<T> {
+        self.shared.lock().senders += 1;
+        QueueSender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+    last_stamp: Cell<Option<Duration>>,
+}
+
+impl<T> QueueReceiver<T> {
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        state.items.pop_front().map(|item| {
+            if item.weight > 0 {
+                state.pushed -= item.weight;
+                self.shared.not_full.notify_one();
+            }
+            self.last_stamp.set(item.stamp);
+            item.value
+        })
+    }
+
+    // The time the last received item was queued at, if it's stamped.
+    pub fn last_stamp(&self) -> Option<Duration> {
+        self.last_stamp.get()
+    }
+
+    // The time the next item in the queue was queued at, if it's stamped.
+    pub fn next_stamp(&self) -> Option<Duration> {
+        self.shared.lock().items.front().and_then(|item| item.stamp)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match self.pop(&mut state) {
+            Some(item) => Ok(item),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.not_empty.wait(state).expect("Failed to lock the state of a queue");
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.shared
+                        .not_empty
+                        .wait_timeout(state, deadline - now)
+                        .expect("Failed to lock the state of a queue")
+                        .0;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiving = false;
+        // The pending requests are dropped, so their reply channels are
+        // disconnected.
+        state.items.clear();
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::{RecvTimeoutError, TryRecvError, TrySendError};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{queue, OverflowPolicy, QueueConfig};
+
+    #[test]
+    fn test_given_full_queue_with_drop_newest_policy_when_an_item_is_pushed_then_it_is_dropped() {
+        let (tx, rx) = queue(QueueConfig::bounded(2, OverflowPolicy::DropNewest));
+        for i in 0..4 {
+            tx.push(i).expect("Failed to push an item");
+        }
+        assert_eq!(Ok(0), rx.try_recv());
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        assert_eq!(2, tx.stats().dropped);
+    }
+
+    #[test]
+    fn test_given_full_queue_with_drop_oldest_policy_when_an_item_is_pushed_then_the_oldest_pushed_item_is_dropped
+        () {
+        let (tx, rx) = queue(QueueConfig::bounded(2, OverflowPolicy::DropOldest));
+        tx.push(0).expect("Failed to push an item");
+        tx.send(10).expect("Failed to send an item");
+        tx.push(1).expect("Failed to push an item");
+        tx.push(2).expect("Failed to push an item");
+        assert_eq!(Ok(10), rx.try_recv());
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Ok(2), rx.try_recv());
+        assert_eq!(1, rx.stats().dropped);
+    }
+
+    #[test]
+    fn test_given_weighted_items_when_they_are_pushed_then_their_weight_is_counted_against_the_capacity
+        () {
+        let (tx, rx) = queue(QueueConfig::bounded(4, OverflowPolicy::DropOldest));
+        tx.push_weighted(0, 3).expect("Failed to push an item");
+        tx.push(1).expect("Failed to push an item");
+        assert_eq!(4, tx.stats().len);
+        tx.push_weighted(2, 2).expect("Failed to push an item");
+        assert_eq!(3, tx.stats().dropped);
+        assert_eq!(3, tx.stats().len);
+        tx.push_weighted(3, 10).expect("Failed to push an item");
+        assert_eq!(6, tx.stats().dropped);
+        assert_eq!(10, tx.stats().len);
+        assert_eq!(Ok(3), rx.try_recv());
+        assert_eq!(0, rx.stats().len);
+    }
+
+    #[test]
+    fn test_given_full_queue_with_fail_policy_when_an_item_is_pushed_then_it_is_returned() {
+        let (tx, rx) = queue(QueueConfig::bounded(1, OverflowPolicy::Fail));
+        tx.push(0).expect("Failed to push an item");
+        assert_eq!(Err(TrySendError::Full(1)), tx.push(1));
+        tx.send(2).expect("Failed to send an item");
+        assert_eq!(1, tx.stats().refused);
+        assert_eq!(1, tx.stats().len);
+        assert_eq!(Ok(0), rx.try_recv());
+        assert_eq!(Ok(2), rx.try_recv());
+    }
+
+    #[test]
+    fn test_given_full_queue_with_block_policy_when_an_item_is_received_then_the_producer_continues
+        () {
+        let (tx, rx) = queue(QueueConfig::bounded(1, OverflowPolicy::Block));
+        tx.push(0).expect("Failed to push an item");
+        let producer = thread::spawn(move || tx.push(1).is_ok());
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(Ok(0), rx.recv());
+        assert_true!(producer.join().expect("Failed to join the producer"));
+        assert_eq!(Ok(1), rx.recv_timeout(Duration::from_secs(5)));
+        assert_eq!(Err(RecvTimeoutError::Disconnected), rx.recv_timeout(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_given_queue_when_the_receiver_is_dropped_then_the_blocked_producer_returns_an_error() {
+        let (tx, rx) = queue(QueueConfig::bounded(1, OverflowPolicy::Block));
+        tx.push(0).expect("Failed to push an item");
+        let producer = thread::spawn(move || tx.push(1));
+        thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert_eq!(Err(TrySendError::Disconnected(1)),
+                   producer.join().expect("Failed to join the producer"));
+    }
+}
@@ -36,8 +36,6 @@ pub trait EventDemultiplexer {
 pub trait Reactor {
     type Event: Event;
     fn handle_events(&mut self);
-    fn register_handler(&mut self,
-                        handler: Box<for<'a> EventHandler<Self::Event, SharedData<'a>>>);
     fn remove_handler_by_handle(&mut self,
                                  handler: &<<Self as Reactor>::Event as Event>::Handle);
 }
@@ -0,0 +1,179 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use super::{parse_time_of_day, parse_utc_offset, Schedule, TimeSource, TimeWindow, Weekday,
+            CLOSE_ON_END_DEFAULT};
+use serde::de::{Deserialize, Deserializer, Error, MapVisitor, Visitor};
+use std::str::FromStr;
+
+impl Deserialize for Schedule {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Schedule, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Schedule", &[], ScheduleVisitor)
+    }
+}
+
+impl Deserialize for Weekday {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Weekday, D::Error>
+        where D: Deserializer
+    {
+        let value: String = try!(Deserialize::deserialize(deserializer));
+        Weekday::from_str(&value).map_err(D::Error::custom)
+    }
+}
+
+enum Field {
+    Windows,
+    UtcOffset,
+    TimeField,
+    CloseOnEnd,
+}
+
+impl Deserialize for Field {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Field, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = Field;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<Field, E>
+                where E: Error
+            {
+                match value {
+                    "windows" => Ok(Field::Windows),
+                    // The offset is fixed, the old name is accepted for the
+                    // existing configurations.
+                    "utc_offset" | "timezone" => Ok(Field::UtcOffset),
+                    "time_field" => Ok(Field::TimeField),
+                    "close_on_end" => Ok(Field::CloseOnEnd),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct ScheduleVisitor;
+
+impl Visitor for ScheduleVisitor {
+    type Value = Schedule;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Schedule, V::Error>
+        where V: MapVisitor
+    {
+        let mut windows = None;
+        let mut utc_offset = 0;
+        let mut time_source = TimeSource::default();
+        let mut close_on_end = CLOSE_ON_END_DEFAULT;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                Field::Windows => windows = Some(try!(visitor.visit_value())),
+                Field::UtcOffset => {
+                    let offset: String = try!(visitor.visit_value());
+                    utc_offset = try!(parse_utc_offset(&offset).map_err(V::Error::custom));
+                }
+                Field::TimeField => time_source = TimeSource::Event(try!(visitor.visit_value())),
+                Field::CloseOnEnd => close_on_end = try!(visitor.visit_value()),
+            }
+        }
+
+        let windows = match windows {
+            Some(windows) => windows,
+            None => return visitor.missing_field("windows"),
+        };
+
+        try!(visitor.end());
+
+        Ok(Schedule {
+            windows: windows,
+            utc_offset: utc_offset,
+            time_source: time_source,
+            close_on_end: close_on_end,
+        })
+    }
+}
+
+impl Deserialize for TimeWindow {
+    fn deserialize<D>(deserializer: &mut D) -> Result<TimeWindow, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("TimeWindow", &[], TimeWindowVisitor)
+    }
+}
+
+enum WindowField {
+    Days,
+    From,
+    To,
+}
+
+impl Deserialize for WindowField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<WindowField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = WindowField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<WindowField, E>
+                where E: Error
+            {
+                match value {
+                    "days" => Ok(WindowField::Days),
+                    "from" => Ok(WindowField::From),
+                    "to" => Ok(WindowField::To),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct TimeWindowVisitor;
+
+impl Visitor for TimeWindowVisitor {
+    type Value = TimeWindow;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<TimeWindow, V::Error>
+        where V: MapVisitor
+    {
+        let mut days = Vec::new();
+        let mut from: Option<String> = None;
+        let mut to: Option<String> = None;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                WindowField::Days => days = try!(visitor.visit_value()),
+                WindowField::From => from = Some(try!(visitor.visit_value())),
+                WindowField::To => to = Some(try!(visitor.visit_value())),
+            }
+        }
+
+        let from = match from {
+            Some(from) => try!(parse_time_of_day(&from).map_err(V::Error::custom)),
+            None => return visitor.missing_field("from"),
+        };
+        let to = match to {
+            Some(to) => try!(parse_time_of_day(&to).map_err(V::Error::custom)),
+            None => return visitor.missing_field("to"),
+        };
+
+        try!(visitor.end());
+
+        Ok(TimeWindow::new(from, to).days(days))
+    }
+}
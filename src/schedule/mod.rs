@@ -0,0 +1,453 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use message::Message;
+
+mod deser;
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const SECONDS_PER_DAY: u64 = MINUTES_PER_DAY * SECONDS_PER_MINUTE;
+const DAYS_PER_WEEK: u64 = 7;
+// 1970-01-01 was a Thursday
+const EPOCH_WEEKDAY: u64 = 3;
+const CLOSE_ON_END_DEFAULT: bool = false;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    fn from_days_since_epoch(days: u64) -> Weekday {
+        match (days + EPOCH_WEEKDAY) % 7 {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+}
+
+impl FromStr for Weekday {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Weekday, String> {
+        match &s.to_lowercase()[..] {
+            "mon" | "monday" => Ok(Weekday::Monday),
+            "tue" | "tuesday" => Ok(Weekday::Tuesday),
+            "wed" | "wednesday" => Ok(Weekday::Wednesday),
+            "thu" | "thursday" => Ok(Weekday::Thursday),
+            "fri" | "friday" => Ok(Weekday::Friday),
+            "sat" | "saturday" => Ok(Weekday::Saturday),
+            "sun" | "sunday" => Ok(Weekday::Sunday),
+            _ => Err(format!("Invalid weekday: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeSource {
+    Wall,
+    Event(String),
+}
+
+impl Default for TimeSource {
+    fn default() -> TimeSource {
+        TimeSource::Wall
+    }
+}
+
+// `from` and `to` are minutes since midnight. If `from` is greater than `to`,
+// the window spans midnight and `days` refer to the day on which it starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub days: Vec<Weekday>,
+    pub from: u32,
+    pub to: u32,
+}
+
+impl TimeWindow {
+    pub fn new(from: u32, to: u32) -> TimeWindow {
+        TimeWindow {
+            days: Vec::new(),
+            from: from,
+            to: to,
+        }
+    }
+
+    pub fn days(mut self, days: Vec<Weekday>) -> TimeWindow {
+        self.days = days;
+        self
+    }
+
+    fn is_day_matching(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, day: Weekday, previous_day: Weekday, minute: u32) -> bool {
+        self.end(day, previous_day, minute).is_some()
+    }
+
+    // The end of the occurrence which contains the minute, in minutes since
+    // the start of the day.
+    fn end(&self, day: Weekday, previous_day: Weekday, minute: u32) -> Option<u32> {
+        if self.from <= self.to {
+            if self.is_day_matching(day) && minute >= self.from && minute < self.to {
+                Some(self.to)
+            } else {
+                None
+            }
+        } else if self.is_day_matching(day) && minute >= self.from {
+            Some(MINUTES_PER_DAY as u32 + self.to)
+        } else if self.is_day_matching(previous_day) && minute < self.to {
+            Some(self.to)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub windows: Vec<TimeWindow>,
+    pub utc_offset: i64,
+    pub time_source: TimeSource,
+    pub close_on_end: bool,
+}
+
+impl Schedule {
+    fn new(windows: Vec<TimeWindow>) -> Schedule {
+        Schedule {
+            windows: windows,
+            utc_offset: 0,
+            time_source: TimeSource::default(),
+            close_on_end: CLOSE_ON_END_DEFAULT,
+        }
+    }
+
+    // The wall_time (seconds since the Unix epoch) is used if the time is not
+    // read from the message.
+    pub fn is_active(&self, message: &Message, wall_time: u64) -> bool {
+        self.is_active_at(self.time_of(message, wall_time))
+    }
+
+    // The time of the schedule elapsed after the message. The event time
+    // passes like the time of the contexts since the message.
+    pub fn time_after(&self, message: &Message, wall_time: u64, elapsed: Duration) -> u64 {
+        match self.time_source {
+            TimeSource::Wall => wall_time,
+            TimeSource::Event(_) => self.time_of(message, wall_time).saturating_add(elapsed.as_secs()),
+        }
+    }
+
+    // The timestamps which can't be shifted by the offset are never active.
+    pub fn is_active_at(&self, timestamp: u64) -> bool {
+        let local = match self.to_local(timestamp) {
+            Some(local) => local,
+            None => return false,
+        };
+        let days = local / SECONDS_PER_DAY;
+        let minute = ((local % SECONDS_PER_DAY) / SECONDS_PER_MINUTE) as u32;
+        let day = Weekday::from_days_since_epoch(days);
+        let previous_day = Weekday::from_days_since_epoch(days + 6);
+        self.windows.iter().any(|window| window.contains(day, previous_day, minute))
+    }
+
+    // The time the schedule becomes inactive at, or timestamp if it's not
+    // active. Adjacent windows are joined. None if it's active for a week.
+    pub fn end_after(&self, timestamp: u64) -> Option<u64> {
+        let mut end = timestamp;
+        while let Some(window_end) = self.window_end(end) {
+            if window_end - timestamp > DAYS_PER_WEEK * SECONDS_PER_DAY {
+                return None;
+            }
+            end = window_end;
+        }
+        Some(end)
+    }
+
+    // The latest end of the windows which contain the timestamp.
+    fn window_end(&self, timestamp: u64) -> Option<u64> {
+        let local = match self.to_local(timestamp) {
+            Some(local) => local,
+            None => return None,
+        };
+        let days = local / SECONDS_PER_DAY;
+        let minute = ((local % SECONDS_PER_DAY) / SECONDS_PER_MINUTE) as u32;
+        let day = Weekday::from_days_since_epoch(days);
+        let previous_day = Weekday::from_days_since_epoch(days + 6);
+        let start_of_day = local - local % SECONDS_PER_DAY;
+        self.windows
+            .iter()
+            .filter_map(|window| window.end(day, previous_day, minute))
+            .max()
+            .and_then(|end| start_of_day.checked_add(end as u64 * SECONDS_PER_MINUTE))
+            .and_then(|end| self.to_utc(end))
+    }
+
+    fn to_local(&self, timestamp: u64) -> Option<u64> {
+        shift(timestamp, self.utc_offset)
+    }
+
+    fn to_utc(&self, local: u64) -> Option<u64> {
+        self.utc_offset.checked_neg().and_then(|offset| shift(local, offset))
+    }
+
+    fn time_of(&self, message: &Message, wall_time: u64) -> u64 {
+        match self.time_source {
+            TimeSource::Wall => wall_time,
+            TimeSource::Event(ref field) => {
+                match message.get(field).map(|value| u64::from_str(value)) {
+                    Some(Ok(timestamp)) => timestamp,
+                    _ => {
+                        warn!("Schedule: failed to read event time from message, falling back \
+                               to wall time; field={}",
+                              field);
+                        wall_time
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct ScheduleBuilder {
+    schedule: Schedule,
+}
+
+impl ScheduleBuilder {
+    pub fn new(windows: Vec<TimeWindow>) -> ScheduleBuilder {
+        ScheduleBuilder { schedule: Schedule::new(windows) }
+    }
+
+    pub fn utc_offset(&mut self, seconds: i64) -> &mut ScheduleBuilder {
+        self.schedule.utc_offset = seconds;
+        self
+    }
+
+    pub fn time_source(&mut self, time_source: TimeSource) -> &mut ScheduleBuilder {
+        self.schedule.time_source = time_source;
+        self
+    }
+
+    pub fn close_on_end(&mut self, close_on_end: bool) -> &mut ScheduleBuilder {
+        self.schedule.close_on_end = close_on_end;
+        self
+    }
+
+    pub fn build(&mut self) -> Schedule {
+        self.schedule.clone()
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<u32, String> {
+    let mut parts = value.splitn(2, ':');
+    let hours = parts.next().and_then(|hours| u32::from_str(hours).ok());
+    let minutes = parts.next().and_then(|minutes| u32::from_str(minutes).ok());
+    match (hours, minutes) {
+        (Some(hours), Some(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        (Some(24), Some(0)) => Ok(MINUTES_PER_DAY as u32),
+        _ => Err(format!("Invalid time of day, expected HH:MM: {}", value)),
+    }
+}
+
+// None if the result is out of the range of the timestamps.
+fn shift(timestamp: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        timestamp.checked_add(offset as u64)
+    } else {
+        timestamp.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+fn parse_utc_offset(value: &str) -> Result<i64, String> {
+    if value == "UTC" || value == "Z" {
+        return Ok(0);
+    }
+    let error = || format!("Invalid UTC offset, expected UTC or +HH:MM: {}", value);
+    let (sign, rest) = match value.chars().next() {
+        Some('+') => (1, value[1..].replace(":", "")),
+        Some('-') => (-1, value[1..].replace(":", "")),
+        _ => return Err(error()),
+    };
+    if rest.len() != 4 {
+        return Err(error());
+    }
+    let minutes = try!(parse_time_of_day(&format!("{}:{}", &rest[..2], &rest[2..]))
+                           .map_err(|_| error()));
+    Ok(sign * minutes as i64 * SECONDS_PER_MINUTE as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Schedule, ScheduleBuilder, TimeSource, TimeWindow, Weekday};
+    use message::MessageBuilder;
+    use serde_json::from_str;
+
+    // 2016-06-06 00:00:00 UTC, a Monday
+    const MONDAY: u64 = 1465171200;
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+
+    fn night_shift() -> Schedule {
+        ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 6 * 60).days(vec![Weekday::Monday])])
+            .build()
+    }
+
+    #[test]
+    fn test_given_schedule_when_the_window_does_not_span_midnight_then_it_is_active_only_inside() {
+        let schedule = ScheduleBuilder::new(vec![TimeWindow::new(9 * 60, 17 * 60)]).build();
+        assert_false!(schedule.is_active_at(MONDAY + 8 * HOUR));
+        assert_true!(schedule.is_active_at(MONDAY + 9 * HOUR));
+        assert_true!(schedule.is_active_at(MONDAY + 16 * HOUR));
+        assert_false!(schedule.is_active_at(MONDAY + 17 * HOUR));
+    }
+
+    #[test]
+    fn test_given_schedule_when_the_window_spans_midnight_then_it_belongs_to_the_starting_day() {
+        let schedule = night_shift();
+        assert_false!(schedule.is_active_at(MONDAY + 2 * HOUR));
+        assert_true!(schedule.is_active_at(MONDAY + 23 * HOUR));
+        assert_true!(schedule.is_active_at(MONDAY + DAY + 5 * HOUR));
+        assert_false!(schedule.is_active_at(MONDAY + DAY + 6 * HOUR));
+        assert_false!(schedule.is_active_at(MONDAY + DAY + 23 * HOUR));
+    }
+
+    #[test]
+    fn test_given_schedule_when_its_end_is_computed_then_the_adjacent_windows_are_joined() {
+        let schedule = ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 6 * 60),
+                                                 TimeWindow::new(6 * 60, 8 * 60)])
+                           .utc_offset(2 * HOUR as i64)
+                           .build();
+        assert_eq!(Some(MONDAY + 6 * HOUR), schedule.end_after(MONDAY + 2 * HOUR));
+        assert_eq!(Some(MONDAY + 6 * HOUR), schedule.end_after(MONDAY + 3 * HOUR + 30 * 60));
+        assert_eq!(Some(MONDAY + 10 * HOUR), schedule.end_after(MONDAY + 10 * HOUR));
+        let always = ScheduleBuilder::new(vec![TimeWindow::new(0, 24 * 60)]).build();
+        assert_eq!(None, always.end_after(MONDAY));
+    }
+
+    #[test]
+    fn test_given_schedule_with_negative_utc_offset_when_the_local_day_differs_then_the_window_is_found
+        () {
+        let schedule = ScheduleBuilder::new(vec![TimeWindow::new(22 * 60, 23 * 60)])
+                           .utc_offset(-2 * HOUR as i64)
+                           .build();
+        assert_true!(schedule.is_active_at(MONDAY + HOUR / 2));
+        assert_false!(schedule.is_active_at(MONDAY + HOUR));
+        assert_eq!(Some(MONDAY + HOUR), schedule.end_after(MONDAY + HOUR / 2));
+        assert_false!(schedule.is_active_at(0));
+        assert_eq!(Some(0), schedule.end_after(0));
+    }
+
+    #[test]
+    fn test_given_schedule_when_the_timestamp_is_extreme_then_it_does_not_overflow() {
+        let shifted = ScheduleBuilder::new(vec![TimeWindow::new(0, 24 * 60)])
+                          .utc_offset(HOUR as i64)
+                          .build();
+        assert_false!(shifted.is_active_at(u64::max_value()));
+        assert_eq!(Some(u64::max_value()), shifted.end_after(u64::max_value()));
+        let utc = ScheduleBuilder::new(vec![TimeWindow::new(0, 24 * 60)]).build();
+        assert_true!(utc.is_active_at(u64::max_value()));
+        assert_eq!(Some(u64::max_value()), utc.end_after(u64::max_value()));
+        let message = MessageBuilder::new("uuid", "message")
+                          .pair("TIMESTAMP", &u64::max_value().to_string())
+                          .build();
+        let event_time = ScheduleBuilder::new(vec![TimeWindow::new(0, 24 * 60)])
+                             .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                             .build();
+        let elapsed = ::std::time::Duration::from_secs(HOUR);
+        assert_eq!(u64::max_value(), event_time.time_after(&message, 0, elapsed));
+    }
+
+    #[test]
+    fn test_given_schedule_with_utc_offset_when_it_is_evaluated_then_local_time_is_used() {
+        let schedule = ScheduleBuilder::new(vec![TimeWindow::new(9 * 60, 10 * 60)])
+                           .utc_offset(2 * HOUR as i64)
+                           .build();
+        assert_true!(schedule.is_active_at(MONDAY + 7 * HOUR));
+        assert_false!(schedule.is_active_at(MONDAY + 9 * HOUR));
+    }
+
+    #[test]
+    fn test_given_schedule_with_event_time_source_when_a_message_is_checked_then_its_field_is_used
+        () {
+        let schedule = ScheduleBuilder::new(vec![TimeWindow::new(9 * 60, 10 * 60)])
+                           .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                           .build();
+        let inside = MessageBuilder::new("uuid", "message")
+                         .pair("TIMESTAMP", &(MONDAY + 9 * HOUR).to_string())
+                         .build();
+        let outside = MessageBuilder::new("uuid", "message")
+                          .pair("TIMESTAMP", &(MONDAY + 11 * HOUR).to_string())
+                          .build();
+        assert_true!(schedule.is_active(&inside, MONDAY + 11 * HOUR));
+        assert_false!(schedule.is_active(&outside, MONDAY + 9 * HOUR));
+    }
+
+    #[test]
+    fn test_given_schedule_in_json_when_it_is_deserialized_then_we_get_the_expected_result() {
+        let json = r#"
+        {
+            "utc_offset": "+01:30",
+            "time_field": "TIMESTAMP",
+            "close_on_end": true,
+            "windows": [
+                { "days": ["mon", "Friday"], "from": "22:00", "to": "06:00" },
+                { "from": "12:00", "to": "13:00" }
+            ]
+        }
+        "#;
+
+        let schedule = from_str::<Schedule>(json);
+        println!("{:?}", &schedule);
+        let schedule = schedule.expect("Failed to deserialize a valid Schedule");
+        let expected = ScheduleBuilder::new(vec![
+                           TimeWindow::new(22 * 60, 6 * 60).days(vec![Weekday::Monday, Weekday::Friday]),
+                           TimeWindow::new(12 * 60, 13 * 60),
+                       ])
+                           .utc_offset(90 * 60)
+                           .time_source(TimeSource::Event("TIMESTAMP".to_owned()))
+                           .close_on_end(true)
+                           .build();
+        assert_eq!(expected, schedule);
+    }
+
+    #[test]
+    fn test_given_schedule_in_json_when_a_time_is_invalid_then_we_get_error() {
+        let json = r#"{ "windows": [ { "from": "25:00", "to": "06:00" } ] }"#;
+        let result = from_str::<Schedule>(json);
+        let _ = result.err().expect("Successfully deserialized a Schedule with invalid time");
+    }
+
+    #[test]
+    fn test_given_schedule_in_json_when_the_timezone_is_invalid_then_we_get_error() {
+        let json = r#"{ "timezone": "CEST", "windows": [] }"#;
+        let result = from_str::<Schedule>(json);
+        let _ = result.err().expect("Successfully deserialized a Schedule with invalid timezone");
+    }
+
+    #[test]
+    fn test_given_schedule_in_json_when_windows_are_missing_then_we_get_error() {
+        let json = r#"{ "timezone": "UTC" }"#;
+        let result = from_str::<Schedule>(json);
+        let _ = result.err().expect("Successfully deserialized a Schedule without windows");
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use super::{ContextSnapshot, Snapshot, StateSnapshot};
+use context::map::map::ContextKey;
+use duration::SerializableDuration;
+use serde::de::{self, Deserialize, Deserializer, Error, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+use uuid::Uuid;
+
+impl Serialize for Snapshot {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("Snapshot",
+                                    SnapshotMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct SnapshotMapVisitor<'a> {
+    value: &'a Snapshot,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for SnapshotMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("contexts", &self.value.contexts)))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Serialize for ContextSnapshot {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("ContextSnapshot",
+                                    ContextSnapshotMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct ContextSnapshotMapVisitor<'a> {
+    value: &'a ContextSnapshot,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for ContextSnapshotMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("uuid", self.value.uuid.to_simple_string())))),
+            2 => Ok(Some(try!(serializer.serialize_struct_elt("states", &self.value.states)))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Serialize for StateSnapshot {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_struct("StateSnapshot",
+                                    StateSnapshotMapVisitor {
+                                        value: self,
+                                        state: 0,
+                                    })
+    }
+}
+
+struct StateSnapshotMapVisitor<'a> {
+    value: &'a StateSnapshot,
+    state: u8,
+}
+
+impl<'a> ser::MapVisitor for StateSnapshotMapVisitor<'a> {
+    fn visit<S>(&mut self, serializer: &mut S) -> Result<Option<()>, S::Error>
+        where S: Serializer
+    {
+        self.state += 1;
+        let value = self.value;
+        match self.state {
+            1 => Ok(Some(try!(serializer.serialize_struct_elt("key", &value.key)))),
+            2 => {
+                let elapsed_time = SerializableDuration(value.elapsed_time);
+                Ok(Some(try!(serializer.serialize_struct_elt("elapsed_time", elapsed_time))))
+            }
+            3 => {
+                let elapsed_time = SerializableDuration(value.elapsed_time_since_last_message);
+                Ok(Some(try!(serializer.serialize_struct_elt("elapsed_time_since_last_message",
+                                                             elapsed_time))))
+            }
+            4 => Ok(Some(try!(serializer.serialize_struct_elt("opened", value.opened)))),
+            5 => {
+                Ok(Some(try!(serializer.serialize_struct_elt("message_count",
+                                                             value.message_count))))
+            }
+            6 => Ok(Some(try!(serializer.serialize_struct_elt("messages", &value.messages)))),
+            7 => {
+                Ok(Some(try!(serializer.serialize_struct_elt("parent_values",
+                                                             &value.parent_values))))
+            }
+            8 => Ok(Some(try!(serializer.serialize_struct_elt("children", &value.children)))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Deserialize for Snapshot {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Snapshot, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("Snapshot", &[], SnapshotVisitor)
+    }
+}
+
+enum SnapshotField {
+    Contexts,
+}
+
+impl Deserialize for SnapshotField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<SnapshotField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = SnapshotField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<SnapshotField, E>
+                where E: Error
+            {
+                match value {
+                    "contexts" => Ok(SnapshotField::Contexts),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct SnapshotVisitor;
+
+impl Visitor for SnapshotVisitor {
+    type Value = Snapshot;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Snapshot, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut contexts = Vec::new();
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                SnapshotField::Contexts => contexts = try!(visitor.visit_value()),
+            }
+        }
+
+        try!(visitor.end());
+
+        Ok(Snapshot { contexts: contexts })
+    }
+}
+
+impl Deserialize for ContextSnapshot {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ContextSnapshot, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("ContextSnapshot", &[], ContextSnapshotVisitor)
+    }
+}
+
+enum ContextField {
+    Uuid,
+    States,
+}
+
+impl Deserialize for ContextField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ContextField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = ContextField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<ContextField, E>
+                where E: Error
+            {
+                match value {
+                    "uuid" => Ok(ContextField::Uuid),
+                    "states" => Ok(ContextField::States),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct ContextSnapshotVisitor;
+
+impl Visitor for ContextSnapshotVisitor {
+    type Value = ContextSnapshot;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<ContextSnapshot, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut uuid: Option<String> = None;
+        let mut states = Vec::new();
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                ContextField::Uuid => uuid = Some(try!(visitor.visit_value())),
+                ContextField::States => states = try!(visitor.visit_value()),
+            }
+        }
+
+        let uuid = match uuid {
+            Some(uuid) => {
+                match Uuid::parse_str(&uuid) {
+                    Ok(uuid) => uuid,
+                    Err(err) => {
+                        return Err(V::Error::custom(format!("Failed to parse field 'uuid': \
+                                                             uuid={} error={}",
+                                                            uuid,
+                                                            err)))
+                    }
+                }
+            }
+            None => return visitor.missing_field("uuid"),
+        };
+
+        try!(visitor.end());
+
+        Ok(ContextSnapshot {
+            uuid: uuid,
+            states: states,
+        })
+    }
+}
+
+impl Deserialize for StateSnapshot {
+    fn deserialize<D>(deserializer: &mut D) -> Result<StateSnapshot, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_struct("StateSnapshot", &[], StateSnapshotVisitor)
+    }
+}
+
+enum StateField {
+    Key,
+    ElapsedTime,
+    ElapsedTimeSinceLastMessage,
+    Opened,
+    MessageCount,
+    Messages,
+    ParentValues,
+    Children,
+}
+
+impl Deserialize for StateField {
+    fn deserialize<D>(deserializer: &mut D) -> Result<StateField, D::Error>
+        where D: Deserializer
+    {
+        struct FieldVisitor;
+
+        impl Visitor for FieldVisitor {
+            type Value = StateField;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<StateField, E>
+                where E: Error
+            {
+                match value {
+                    "key" => Ok(StateField::Key),
+                    "elapsed_time" => Ok(StateField::ElapsedTime),
+                    "elapsed_time_since_last_message" => Ok(StateField::ElapsedTimeSinceLastMessage),
+                    "opened" => Ok(StateField::Opened),
+                    "message_count" => Ok(StateField::MessageCount),
+                    "messages" => Ok(StateField::Messages),
+                    "parent_values" => Ok(StateField::ParentValues),
+                    "children" => Ok(StateField::Children),
+                    _ => Err(E::custom(format!("Unexpected field: {}", value))),
+                }
+            }
+        }
+
+        deserializer.deserialize(FieldVisitor)
+    }
+}
+
+struct StateSnapshotVisitor;
+
+impl Visitor for StateSnapshotVisitor {
+    type Value = StateSnapshot;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<StateSnapshot, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut snapshot = StateSnapshot::default();
+        let mut message_count = None;
+
+        while let Some(field) = try!(visitor.visit_key()) {
+            match field {
+                StateField::Key => snapshot.key = try!(visitor.visit_value::<ContextKey>()),
+                StateField::ElapsedTime => {
+                    let elapsed_time: SerializableDuration = try!(visitor.visit_value());
+                    snapshot.elapsed_time = elapsed_time.0;
+                }
+                StateField::ElapsedTimeSinceLastMessage => {
+                    let elapsed_time: SerializableDuration = try!(visitor.visit_value());
+                    snapshot.elapsed_time_since_last_message = elapsed_time.0;
+                }
+                StateField::Opened => snapshot.opened = try!(visitor.visit_value()),
+                StateField::MessageCount => message_count = Some(try!(visitor.visit_value())),
+                StateField::Messages => snapshot.messages = try!(visitor.visit_value()),
+                StateField::ParentValues => snapshot.parent_values = try!(visitor.visit_value()),
+                StateField::Children => snapshot.children = try!(visitor.visit_value()),
+            }
+        }
+
+        try!(visitor.end());
+
+        snapshot.message_count = message_count.unwrap_or_else(|| snapshot.messages.len());
+        Ok(snapshot)
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use context::map::map::ContextKey;
+use message::Message;
+
+mod deser;
+
+// The open states of a ContextMap, tied to the uuids of their contexts. The
+// time while the correlator is not running is not counted in the timers of
+// the restored states.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub contexts: Vec<ContextSnapshot>,
+}
+
+impl Snapshot {
+    // Adds the states of the snapshot of another shard.
+    pub fn merge(&mut self, other: Snapshot) {
+        for i in other.contexts {
+            match self.contexts.iter().position(|context| context.uuid == i.uuid) {
+                Some(index) => self.contexts[index].states.extend(i.states),
+                None => self.contexts.push(i),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextSnapshot {
+    pub uuid: Uuid,
+    pub states: Vec<StateSnapshot>,
+}
+
+// The key of the states of a context without context_id is empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateSnapshot {
+    pub key: ContextKey,
+    pub elapsed_time: Duration,
+    pub elapsed_time_since_last_message: Duration,
+    pub opened: bool,
+    pub message_count: usize,
+    pub messages: Vec<Message>,
+    pub parent_values: BTreeMap<String, String>,
+    pub children: Vec<ContextSnapshot>,
+}
+
+// What to do with the states of contexts which are not present in the
+// configuration any more.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    // The states are dropped without executing any actions.
+    Discard,
+    // The restore fails.
+    Fail,
+}
+
+impl Default for OrphanPolicy {
+    fn default() -> OrphanPolicy {
+        OrphanPolicy::Discard
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ContextSnapshot, Snapshot, StateSnapshot};
+    use message::MessageBuilder;
+    use serde_json::{from_str, to_string};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_given_snapshot_when_it_is_serialized_and_deserialized_then_we_get_the_same_snapshot() {
+        let message = MessageBuilder::new("LOGIN", "user logged in")
+                          .name(Some("login"))
+                          .pair("user", "alice")
+                          .build();
+        let child = StateSnapshot {
+            key: vec![("session".to_owned(), None)],
+            opened: true,
+            message_count: 1,
+            messages: vec![message.clone()],
+            parent_values: btreemap!{"user".to_owned() => "alice".to_owned()},
+            ..StateSnapshot::default()
+        };
+        let state = StateSnapshot {
+            key: vec![("user".to_owned(), Some("alice".to_owned()))],
+            elapsed_time: Duration::from_millis(1200),
+            elapsed_time_since_last_message: Duration::from_millis(300),
+            opened: true,
+            message_count: 3,
+            messages: vec![message],
+            children: vec![ContextSnapshot {
+                               uuid: Uuid::new_v4(),
+                               states: vec![child],
+                           }],
+            ..StateSnapshot::default()
+        };
+        let snapshot = Snapshot {
+            contexts: vec![ContextSnapshot {
+                               uuid: Uuid::new_v4(),
+                               states: vec![state],
+                           }],
+        };
+
+        let json = to_string(&snapshot).expect("Failed to serialize a Snapshot");
+        let result = from_str::<Snapshot>(&json).expect("Failed to deserialize a Snapshot");
+        assert_eq!(snapshot, result);
+    }
+
+    #[test]
+    fn test_given_snapshot_in_json_when_a_field_is_unknown_then_we_get_error() {
+        let json = r#"{ "contexts": [], "states": [] }"#;
+        let _ = from_str::<Snapshot>(json).err().expect("Deserialized an invalid Snapshot");
+    }
+}
@@ -6,18 +6,41 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
 use std::sync::Arc;
 
 use Message;
-use timer::TimerEvent;
+use message;
+use context::budget::MemoryUsage;
+use context::map::map::ContextKey;
+use control::CloseReason;
+use snapshot::StateSnapshot;
 use std::time::Duration;
 
+pub type ChildStates = BTreeMap<ContextKey, State>;
+
+// The timers are not updated by every timer event. They are stored as they
+// were at synced_at (a point of the timeline of the context), and the time
+// passed since then is added when they are read.
 #[derive(Debug)]
 pub struct State {
     elapsed_time: Duration,
     elapsed_time_since_last_message: Duration,
+    synced_at: Duration,
+    deadline: Option<Duration>,
+    opened_at: Option<Duration>,
     messages: Vec<Arc<Message>>,
+    message_count: usize,
+    bytes: usize,
+    retaining: bool,
     opened: bool,
+    children: Vec<ChildStates>,
+    parent_values: BTreeMap<String, String>,
+    parent_values_bytes: usize,
+    close_reason: Option<CloseReason>,
+    hops: usize,
 }
 
 impl Default for State {
@@ -35,8 +58,19 @@ impl State {
         State {
             elapsed_time: Duration::from_secs(0),
             elapsed_time_since_last_message: Duration::from_secs(0),
+            synced_at: Duration::from_secs(0),
+            deadline: None,
+            opened_at: None,
+            message_count: messages.len(),
+            bytes: messages.iter().map(|message| message.estimated_size()).sum(),
+            hops: messages.iter().map(|message| message.hops()).max().unwrap_or(0),
+            retaining: true,
             messages: messages,
             opened: false,
+            children: Vec::new(),
+            parent_values: BTreeMap::new(),
+            parent_values_bytes: 0,
+            close_reason: None,
         }
     }
 
@@ -52,33 +86,198 @@ impl State {
         self.reset();
     }
 
-    pub fn elapsed_time(&self) -> Duration {
-        self.elapsed_time
+    // Set while the close actions of the state are executed.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+
+    // The most hops of the messages added to the state.
+    pub fn hops(&self) -> usize {
+        self.hops
+    }
+
+    pub fn set_close_reason(&mut self, reason: Option<CloseReason>) {
+        self.close_reason = reason;
+    }
+
+    pub fn elapsed_time(&self, now: Duration) -> Duration {
+        self.elapsed_time + self.unsynced(now)
     }
 
-    pub fn elapsed_time_since_last_message(&self) -> Duration {
-        self.elapsed_time_since_last_message
+    pub fn elapsed_time_since_last_message(&self, now: Duration) -> Duration {
+        self.elapsed_time_since_last_message + self.unsynced(now)
+    }
+
+    fn unsynced(&self, now: Duration) -> Duration {
+        if self.opened && now > self.synced_at {
+            now - self.synced_at
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    // Adds the time passed since the last sync to the timers. It must be
+    // called before the state is modified.
+    pub fn sync(&mut self, now: Duration) {
+        let delta = self.unsynced(now);
+        self.elapsed_time = self.elapsed_time + delta;
+        self.elapsed_time_since_last_message = self.elapsed_time_since_last_message + delta;
+        self.synced_at = now;
+    }
+
+    // The deadline the state is scheduled for by its context.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline;
+    }
+
+    // The point of the timeline the state is indexed by its age at.
+    pub fn opened_at(&self) -> Option<Duration> {
+        self.opened_at
+    }
+
+    pub fn set_opened_at(&mut self, opened_at: Option<Duration>) {
+        self.opened_at = opened_at;
     }
 
     pub fn messages(&self) -> &[Arc<Message>] {
         &self.messages
     }
 
+    // The number of messages added to the state, including the ones which were
+    // not retained.
+    pub fn message_count(&self) -> usize {
+        self.message_count
+    }
+
+    pub fn is_retaining(&self) -> bool {
+        self.retaining
+    }
+
+    // If the state is not retaining, only the last message is kept.
+    pub fn set_retaining(&mut self, retaining: bool) {
+        self.retaining = retaining;
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        let own = MemoryUsage {
+            messages: self.messages.len(),
+            bytes: self.bytes + self.parent_values_bytes,
+        };
+        self.children
+            .iter()
+            .flat_map(|states| states.values())
+            .fold(own, |usage, state| usage + state.usage())
+    }
+
+    // The values of the messages, the later messages override the earlier
+    // ones.
+    pub fn values_of(messages: &[Arc<Message>]) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        for message in messages {
+            for (key, value) in message.values() {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+        values
+    }
+
+    // The values of the parent state when this child state was created, only
+    // set for the children which inherit some of them.
+    pub fn parent_values(&self) -> &BTreeMap<String, String> {
+        &self.parent_values
+    }
+
+    pub fn set_parent_values(&mut self, values: BTreeMap<String, String>) {
+        self.parent_values_bytes = message::estimated_values_size(&values);
+        self.parent_values = values;
+    }
+
+    pub fn children(&self) -> &[ChildStates] {
+        &self.children
+    }
+
+    pub fn children_mut(&mut self, count: usize) -> &mut [ChildStates] {
+        self.messages_and_children_mut(count).1
+    }
+
+    // The messages are read while the child states are updated.
+    pub fn messages_and_children_mut(&mut self,
+                                     count: usize)
+                                     -> (&[Arc<Message>], &mut [ChildStates]) {
+        while self.children.len() < count {
+            self.children.push(ChildStates::new());
+        }
+        (&self.messages, &mut self.children)
+    }
+
+    pub fn take_children(&mut self) -> Vec<ChildStates> {
+        mem::replace(&mut self.children, Vec::new())
+    }
+
+    // Moves the state from the timeline of a context to the one of another
+    // context. It is scheduled again by the new context.
+    pub fn rebase(&mut self, from: Duration, to: Duration) {
+        self.sync(from);
+        self.synced_at = to;
+        self.deadline = None;
+        self.opened_at = None;
+    }
+
     pub fn add_message(&mut self, message: Arc<Message>) {
+        if !self.retaining {
+            self.messages.clear();
+            self.bytes = 0;
+        }
+        self.bytes += message.estimated_size();
+        self.message_count += 1;
+        self.hops = cmp::max(self.hops, message.hops());
         self.messages.push(message);
         self.elapsed_time_since_last_message = Duration::from_secs(0);
     }
 
-    pub fn update_timers(&mut self, event: &TimerEvent) {
-        let delta = event.0;
-        self.elapsed_time = self.elapsed_time + delta;
-        self.elapsed_time_since_last_message = self.elapsed_time_since_last_message + delta;
+    // The states of the children are not part of the snapshot, they are
+    // handled by the context.
+    pub fn to_snapshot(&self, key: ContextKey, now: Duration) -> StateSnapshot {
+        StateSnapshot {
+            key: key,
+            elapsed_time: self.elapsed_time(now),
+            elapsed_time_since_last_message: self.elapsed_time_since_last_message(now),
+            opened: self.opened,
+            message_count: self.message_count,
+            messages: self.messages.iter().map(|message| (**message).clone()).collect(),
+            parent_values: self.parent_values.clone(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: StateSnapshot, now: Duration) -> State {
+        let StateSnapshot {elapsed_time, elapsed_time_since_last_message, opened, message_count,
+                           messages, parent_values, ..} = snapshot;
+        let mut state = State::with_messages(messages.into_iter().map(Arc::new).collect());
+        state.elapsed_time = elapsed_time;
+        state.elapsed_time_since_last_message = elapsed_time_since_last_message;
+        state.synced_at = now;
+        state.opened = opened;
+        state.message_count = message_count;
+        state.set_parent_values(parent_values);
+        state
     }
 
     fn reset(&mut self) {
         self.elapsed_time = Duration::from_secs(0);
         self.elapsed_time_since_last_message = Duration::from_secs(0);
         self.messages.clear();
+        self.message_count = 0;
+        self.bytes = 0;
         self.opened = false;
+        self.children.clear();
+        self.parent_values.clear();
+        self.parent_values_bytes = 0;
+        self.close_reason = None;
+        self.hops = 0;
     }
 }
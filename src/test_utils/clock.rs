@@ -0,0 +1,53 @@
+// Copyright (c) 2016 Tibor Benke <ihrwein@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clock::Clock;
+
+// A clock which is moved only by advance(). The clones share the same time,
+// so a test can keep one and pass an other one to the correlator.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+    origin_timestamp: u64,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    // The wall clock starts at timestamp (seconds since the Unix epoch).
+    pub fn with_timestamp(timestamp: u64) -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Duration::from_secs(0))),
+            origin_timestamp: timestamp,
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().expect("Failed to lock the time of a ManualClock");
+        *now = *now + duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().expect("Failed to lock the time of a ManualClock")
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.origin_timestamp + self.now().as_secs()
+    }
+
+    fn is_real_time(&self) -> bool {
+        false
+    }
+}
@@ -6,16 +6,15 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::sync::mpsc::Sender;
-
 use dispatcher::request::Request;
+use queue::QueueSender;
 use action::Alert;
 use correlator::AlertHandler;
 
 pub struct MockAlertHandler;
 
 impl AlertHandler<Vec<Alert>> for MockAlertHandler {
-    fn on_alert(&mut self, alert: Alert, _: &mut Sender<Request>, extra_data: &mut Vec<Alert>) {
+    fn on_alert(&mut self, alert: Alert, _: &mut QueueSender<Request>, extra_data: &mut Vec<Alert>) {
         extra_data.push(alert);
     }
 }
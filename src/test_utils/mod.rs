@@ -6,8 +6,10 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+pub use self::clock::ManualClock;
 pub use self::correlator::MockAlertHandler;
 pub use self::responder::MockResponseSender;
 
+mod clock;
 mod correlator;
 mod responder;
@@ -6,11 +6,10 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::sync::mpsc;
-use std::thread;
+use std::sync::Arc;
 use std::time::Duration;
 
-use dispatcher::request::Request;
+use clock::Clock;
 
 #[derive(Clone, Copy, Debug)]
 pub struct TimerEvent(pub Duration);
@@ -22,14 +21,67 @@ impl TimerEvent {
     }
 }
 
-pub struct Timer;
+// Measures the time passed since the last timer event on the monotonic clock.
+// The events are whole milliseconds (the rest is kept for the next event), so
+// the journaled events add up to the real elapsed time.
+pub struct Timer {
+    clock: Arc<Clock>,
+    last: Duration,
+}
 
 impl Timer {
-    pub fn from_chan(duration: Duration, tx: mpsc::Sender<Request>) {
-        thread::spawn(move || {
-            while let Ok(_) = tx.send(Request::Timer(TimerEvent(duration))) {
-                thread::sleep(duration);
-            }
-        });
+    pub fn new(clock: Arc<Clock>) -> Timer {
+        let last = clock.now();
+        Timer {
+            clock: clock,
+            last: last,
+        }
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &*self.clock
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed_until(self.clock.now())
+    }
+
+    fn elapsed_until(&self, time: Duration) -> Duration {
+        let elapsed = if time > self.last {
+            time - self.last
+        } else {
+            Duration::from_secs(0)
+        };
+        let millis = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        Duration::from_millis(millis)
     }
+
+    // Returns None if less than a millisecond passed since the last event.
+    pub fn tick(&mut self) -> Option<TimerEvent> {
+        let now = self.clock.now();
+        self.tick_until(now)
+    }
+
+    // Moves the timer to time (a time of its clock) instead of now. Returns
+    // None if time is less than a millisecond after the last event.
+    pub fn tick_until(&mut self, time: Duration) -> Option<TimerEvent> {
+        let elapsed = self.elapsed_until(time);
+        if elapsed == Duration::from_secs(0) {
+            None
+        } else {
+            self.last = self.last + elapsed;
+            Some(TimerEvent(elapsed))
+        }
+    }
+}
+
+// Returns the time left until the deadline on a timeline which is at now.
+pub fn next_timeout(deadline: Option<Duration>, now: Duration) -> Option<Duration> {
+    deadline.map(|deadline| {
+        if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_secs(0)
+        }
+    })
 }
@@ -6,8 +6,11 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use correlation::Alert;
-use correlation::correlator::{Correlator, CorrelatorFactory, Error};
+use correlation::correlator::{Correlator, CorrelatorBuilder, CorrelatorFactory, Error};
 use correlation::MessageBuilder;
 use correlation::test_utils::MockAlertHandler;
 
@@ -83,3 +86,56 @@ fn test_name() {
         unreachable!();
     }
 }
+
+#[test]
+fn test_given_config_file_when_a_context_is_invalid_then_the_error_contains_its_location() {
+    let _ = env_logger::init();
+    let contexts_file = "tests/correlator/partially_invalid.json";
+    let result = CorrelatorFactory::load_file(contexts_file);
+    if let Error::Context(error) = result.err().unwrap() {
+        assert_eq!(1, error.index);
+        assert_eq!(Some("2b3d0f8e-6a5c-4e8b-9f3e-1d2c3b4a5f60".to_owned()), error.uuid);
+        assert_eq!("[1].conditions", error.location);
+        assert!(error.line.is_some());
+    } else {
+        unreachable!();
+    }
+}
+
+#[test]
+fn test_given_config_file_when_it_is_loaded_leniently_then_the_valid_contexts_are_loaded() {
+    let _ = env_logger::init();
+    let contexts_file = "tests/correlator/partially_invalid.json";
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let errors_clone = errors.clone();
+    let mut correlator: Correlator<()> = CorrelatorBuilder::from_path(contexts_file)
+                                             .lenient(true)
+                                             .error_callback(move |error| {
+                                                 errors_clone.borrow_mut().push(error.to_owned())
+                                             })
+                                             .build()
+                                             .unwrap();
+    assert_eq!(1, correlator.query().unwrap().len());
+    let errors = errors.borrow();
+    assert_eq!(1, errors.len());
+    assert!(errors[0].contains("[1].conditions"));
+    assert!(CorrelatorBuilder::<()>::from_path(contexts_file).build().is_err());
+}
+
+#[test]
+fn test_given_config_file_when_it_is_built_leniently_then_the_errors_of_the_skipped_contexts_are_returned() {
+    let _ = env_logger::init();
+    let contexts_file = "tests/correlator/partially_invalid.json";
+    let (mut correlator, errors) = CorrelatorBuilder::<()>::from_path(contexts_file)
+                                       .build_lenient()
+                                       .unwrap();
+    assert_eq!(1, correlator.query().unwrap().len());
+    assert_eq!(1, errors.len());
+    assert_eq!(1, errors[0].index);
+    assert_eq!(Some("2b3d0f8e-6a5c-4e8b-9f3e-1d2c3b4a5f60"),
+               errors[0].uuid.as_ref().map(|uuid| uuid.as_str()));
+    assert!(errors[0].location.starts_with("[1].conditions"));
+    let (contexts, errors) = CorrelatorFactory::load_file_lenient(contexts_file).unwrap();
+    assert_eq!(1, contexts.len());
+    assert_eq!(1, errors.len());
+}